@@ -72,6 +72,31 @@ pub enum Commands {
         #[command(subcommand)]
         action: SetsAction,
     },
+
+    /// Show estimated cost per profile and model for a date range
+    Cost {
+        /// Start date, inclusive (YYYY-MM-DD, default: 30 days before --to)
+        #[arg(long)]
+        from: Option<String>,
+        /// End date, inclusive (YYYY-MM-DD, default: today)
+        #[arg(long)]
+        to: Option<String>,
+        /// Only include this profile
+        #[arg(short, long)]
+        profile: Option<String>,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Re-run response translation offline against a recorded capture file
+    Replay {
+        /// Capture file written by the proxy traffic recorder
+        capture: std::path::PathBuf,
+        /// Only print the match summary, not the translated output
+        #[arg(short, long)]
+        quiet: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -80,14 +80,11 @@ fn cmd_show(config: &ClaudexConfig, raw: bool, json: bool) -> Result<()> {
             "disabled"
         }
     );
-    println!(
-        "  RAG: {}",
-        if config.context.rag.enabled {
-            "enabled"
-        } else {
-            "disabled"
-        }
-    );
+    if config.context.rag.enabled {
+        println!("  RAG: enabled ({})", config.context.rag.retrieval);
+    } else {
+        println!("  RAG: disabled");
+    }
     Ok(())
 }
 
@@ -264,6 +261,55 @@ async fn cmd_validate(config: &ClaudexConfig, connectivity: bool) -> Result<()>
         }
     }
 
+    // model_routes 目标必须是已有 profile 或 pool
+    for p in &config.profiles {
+        for (pattern, target) in &p.model_routes {
+            if config.find_profile(target).is_none() && config.find_pool(target).is_none() {
+                errors.push(format!(
+                    "profile '{}': model_routes '{}' target '{}' does not exist",
+                    p.name, pattern, target
+                ));
+            }
+        }
+    }
+
+    // overflow reroute 目标必须是已有 profile
+    for p in &config.profiles {
+        if let Some(target) = p.overflow.as_ref().and_then(|o| o.profile.as_ref()) {
+            if config.find_profile(target).is_none() {
+                errors.push(format!(
+                    "profile '{}': overflow profile '{}' does not exist",
+                    p.name, target
+                ));
+            }
+        }
+    }
+
+    // 路由规则中的正则必须能编译
+    for (i, rule) in config.router.routes.iter().enumerate() {
+        for (field, pattern) in rule.invalid_patterns() {
+            errors.push(format!(
+                "router.routes[{i}] ('{}'): invalid {field} regex '{}': {}",
+                rule.label(),
+                pattern.as_str(),
+                pattern.error().unwrap_or_default()
+            ));
+        }
+    }
+
+    // mock fixture 必须能解析，match 正则必须能编译
+    for p in &config.profiles {
+        if let Some(path) = p.mock_fixtures.as_ref() {
+            if let Err(e) = crate::proxy::adapter::mock::MockFixtures::load(path) {
+                errors.push(format!(
+                    "profile '{}': invalid mock_fixtures '{}': {e:#}",
+                    p.name,
+                    path.display()
+                ));
+            }
+        }
+    }
+
     // 3. OAuth profiles must have oauth_provider
     for p in &config.profiles {
         if p.auth_type == AuthType::OAuth && p.oauth_provider.is_none() {
@@ -326,9 +372,10 @@ async fn cmd_validate(config: &ClaudexConfig, connectivity: bool) -> Result<()>
             && p.auth_type == AuthType::ApiKey
             && p.api_key.is_empty()
             && p.api_key_keyring.is_none()
+            && p.key_pool.as_ref().is_none_or(|pool| pool.keys.is_empty())
         {
             warnings.push(format!(
-                "profile '{}': enabled with auth_type=ApiKey but no api_key or api_key_keyring",
+                "profile '{}': enabled with auth_type=ApiKey but no api_key, api_key_keyring or key_pool",
                 p.name
             ));
         }
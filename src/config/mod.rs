@@ -1,7 +1,7 @@
 pub mod cmd;
 pub mod profile;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
@@ -11,6 +11,16 @@ use serde::{Deserialize, Serialize};
 
 use crate::context::ContextEngineConfig;
 use crate::oauth::{AuthType, OAuthProvider};
+use crate::proxy::budget::BudgetConfig;
+use crate::proxy::cache::CacheConfig;
+use crate::proxy::capture::CaptureConfig;
+use crate::proxy::context_window::OverflowConfig;
+use crate::proxy::fallback::CircuitBreakerConfig;
+use crate::proxy::health::HealthConfig;
+use crate::proxy::key_pool::KeyPoolConfig;
+use crate::proxy::pool::PoolConfig;
+use crate::proxy::pricing::ModelPrice;
+use crate::proxy::rate_limit::RateLimitConfig;
 use crate::router::RouterConfig;
 
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
@@ -41,6 +51,9 @@ pub struct ClaudexConfig {
     pub log_level: String,
     #[serde(default)]
     pub profiles: Vec<ProfileConfig>,
+    /// 虚拟负载均衡 profile（`[[pools]]`），可像普通 profile 一样作为 proxy 目标
+    #[serde(default)]
+    pub pools: Vec<PoolConfig>,
     #[serde(default)]
     pub model_aliases: HashMap<String, String>,
     #[serde(default)]
@@ -49,6 +62,21 @@ pub struct ClaudexConfig {
     pub context: ContextEngineConfig,
     #[serde(default)]
     pub hyperlinks: HyperlinksConfig,
+    /// 模型价格覆盖（美元 / 百万 token），按模型 id 前缀匹配，优先于内置价格表
+    #[serde(default)]
+    pub pricing: HashMap<String, ModelPrice>,
+    /// 模型上下文窗口覆盖（token），按模型 id 前缀匹配，优先于内置表
+    #[serde(default)]
+    pub context_windows: HashMap<String, u64>,
+    /// 流量录制（调试用，默认关闭）
+    #[serde(default)]
+    pub capture: CaptureConfig,
+    /// 确定性请求的磁盘响应缓存（默认关闭）
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// 将响应中的模型名（含流式 message_start）改写为实际上游模型
+    #[serde(default)]
+    pub rewrite_response_model: bool,
     #[serde(skip)]
     pub config_source: Option<PathBuf>,
     #[serde(skip)]
@@ -127,6 +155,44 @@ pub struct ProfileConfig {
     /// 追加到请求 URL 的 query 参数（如 Azure OpenAI 的 api-version）
     #[serde(default)]
     pub query_params: HashMap<String, String>,
+    /// 日 / 月预算（token 或估算费用），由 proxy 强制执行
+    #[serde(default)]
+    pub budget: Option<BudgetConfig>,
+    /// 限流（每分钟请求数 / token 数、最大并发），由 proxy 强制执行
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Mock provider 的 fixture 文件（TOML / YAML / JSON）
+    #[serde(default)]
+    pub mock_fixtures: Option<PathBuf>,
+    /// 多 API key 轮换（设置后替代 api_key），401 / 429 时自动换 key 重试
+    #[serde(default)]
+    pub key_pool: Option<KeyPoolConfig>,
+    /// 健康检查（主动探测间隔、方式、开关），未设置时使用默认值
+    #[serde(default)]
+    pub health: Option<HealthConfig>,
+    /// 熔断器参数（阈值、窗口、恢复时间与退避），未设置时使用默认值
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// 按请求模型转发到其他 profile（或 pool）：glob pattern → 目标名
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub model_routes: BTreeMap<String, String>,
+    /// 该 profile 模型的上下文窗口（token），覆盖 `[context_windows]` 与内置表
+    #[serde(default)]
+    pub context_window: Option<u64>,
+    /// 请求超出上下文窗口时的处理（压缩或改发长上下文 profile），未设置时默认压缩
+    #[serde(default)]
+    pub overflow: Option<OverflowConfig>,
+}
+
+impl ProfileConfig {
+    /// 请求模型命中的路由目标；多条 pattern 命中时取最长（最具体）的一条
+    pub fn model_route(&self, model: &str) -> Option<&str> {
+        self.model_routes
+            .iter()
+            .filter(|(pattern, _)| crate::proxy::util::glob_match(pattern, model))
+            .max_by_key(|(pattern, _)| pattern.len())
+            .map(|(_, target)| target.as_str())
+    }
 }
 
 /// 参数剥离配置
@@ -200,6 +266,23 @@ pub struct ProfileModels {
     pub opus: Option<String>,
 }
 
+impl ProfileModels {
+    /// 按 Claude 模型名（含 haiku / sonnet / opus）取对应 slot 配置的模型
+    pub fn for_claude_model(&self, model: &str) -> Option<&str> {
+        let model = model.to_ascii_lowercase();
+        let slot = if model.contains("haiku") {
+            &self.haiku
+        } else if model.contains("sonnet") {
+            &self.sonnet
+        } else if model.contains("opus") {
+            &self.opus
+        } else {
+            return None;
+        };
+        slot.as_deref()
+    }
+}
+
 impl Default for ProfileConfig {
     fn default() -> Self {
         Self {
@@ -220,6 +303,15 @@ impl Default for ProfileConfig {
             max_tokens: None,
             strip_params: StripParams::default(),
             query_params: HashMap::new(),
+            budget: None,
+            rate_limit: None,
+            mock_fixtures: None,
+            key_pool: None,
+            health: None,
+            circuit_breaker: None,
+            model_routes: BTreeMap::new(),
+            context_window: None,
+            overflow: None,
         }
     }
 }
@@ -229,6 +321,8 @@ pub enum ProviderType {
     DirectAnthropic,
     OpenAICompatible,
     OpenAIResponses,
+    /// 离线 Mock provider，按 fixture 文件返回脚本化响应
+    Mock,
 }
 
 impl std::fmt::Display for ProviderType {
@@ -237,6 +331,7 @@ impl std::fmt::Display for ProviderType {
             ProviderType::DirectAnthropic => write!(f, "Anthropic"),
             ProviderType::OpenAICompatible => write!(f, "OpenAI"),
             ProviderType::OpenAIResponses => write!(f, "Responses"),
+            ProviderType::Mock => write!(f, "Mock"),
         }
     }
 }
@@ -558,6 +653,10 @@ enabled = false
         self.profiles.iter().find(|p| p.name == name)
     }
 
+    pub fn find_pool(&self, name: &str) -> Option<&PoolConfig> {
+        self.pools.iter().find(|p| p.name == name)
+    }
+
     pub fn find_profile_mut(&mut self, name: &str) -> Option<&mut ProfileConfig> {
         self.profiles.iter_mut().find(|p| p.name == name)
     }
@@ -566,6 +665,11 @@ enabled = false
         self.profiles.iter().filter(|p| p.enabled).collect()
     }
 
+    /// 查找模型价格（用户 `[pricing]` 覆盖优先，其次内置价格表）
+    pub fn model_price(&self, model: &str) -> Option<ModelPrice> {
+        crate::proxy::pricing::lookup_price(&self.pricing, model)
+    }
+
     pub fn resolve_model(&self, model: &str) -> String {
         self.model_aliases
             .get(model)
@@ -582,10 +686,16 @@ impl Default for ClaudexConfig {
             proxy_host: default_proxy_host(),
             log_level: default_log_level(),
             profiles: Vec::new(),
+            pools: Vec::new(),
             model_aliases: HashMap::new(),
             router: RouterConfig::default(),
             context: ContextEngineConfig::default(),
             hyperlinks: HyperlinksConfig::default(),
+            pricing: HashMap::new(),
+            context_windows: HashMap::new(),
+            capture: CaptureConfig::default(),
+            cache: CacheConfig::default(),
+            rewrite_response_model: false,
             config_source: None,
             config_format: ConfigFormat::Toml,
         }
@@ -593,6 +703,7 @@ impl Default for ClaudexConfig {
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
 
@@ -618,6 +729,32 @@ mod tests {
         assert!(config.model_aliases.is_empty());
     }
 
+    #[test]
+    fn test_model_route_prefers_most_specific_pattern() {
+        let profile: ProfileConfig = toml::from_str(
+            r#"
+            name = "main"
+            base_url = "http://localhost"
+            default_model = "claude-sonnet-4"
+
+            [model_routes]
+            "claude-*" = "fallback"
+            "claude-*haiku*" = "local-qwen"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            profile.model_route("claude-3-5-haiku-20241022"),
+            Some("local-qwen")
+        );
+        assert_eq!(profile.model_route("claude-opus-4"), Some("fallback"));
+        assert_eq!(profile.model_route("gpt-4o"), None);
+        assert_eq!(
+            make_profile("plain", true).model_route("claude-opus-4"),
+            None
+        );
+    }
+
     #[test]
     fn test_find_profile() {
         let mut config = ClaudexConfig::default();
@@ -1078,6 +1215,26 @@ profiles:
         assert_eq!(config.proxy_port, 13456);
     }
 
+    #[test]
+    fn test_parse_pricing_overrides() {
+        let toml_str = r#"
+            [pricing."deepseek-chat"]
+            input = 0.1
+            output = 0.2
+
+            [pricing."my-local-model"]
+            input = 0
+            output = 0
+        "#;
+        let config: ClaudexConfig = toml::from_str(toml_str).unwrap();
+        let price = config.model_price("deepseek-chat").unwrap();
+        assert_eq!(price.input, 0.1);
+        assert_eq!(price.cache_read, 0.0);
+        assert!(config.model_price("my-local-model").is_some());
+        // 未覆盖的模型回落到内置价格表
+        assert_eq!(config.model_price("gpt-4o").unwrap().input, 2.5);
+    }
+
     #[test]
     fn test_config_format_from_path() {
         assert_eq!(
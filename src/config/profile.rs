@@ -2,9 +2,12 @@ use std::io::{self, Write};
 use std::time::{Duration, Instant};
 
 use anyhow::{bail, Result};
+use chrono::Datelike;
 use reqwest::Client;
 
 use super::{ClaudexConfig, ProfileConfig, ProviderType};
+use crate::proxy::budget::BudgetPeriod;
+use crate::proxy::pricing::format_cost;
 
 pub async fn list_profiles(config: &ClaudexConfig) {
     if config.profiles.is_empty() {
@@ -41,6 +44,80 @@ pub async fn show_profile(config: &ClaudexConfig, name: &str) -> Result<()> {
     if !profile.custom_headers.is_empty() {
         println!("Custom Headers: {:?}", profile.custom_headers);
     }
+    match config.model_price(&profile.default_model) {
+        Some(price) => println!(
+            "Pricing:        ${} in / ${} out per MTok (cache read ${}, write ${})",
+            price.input, price.output, price.cache_read, price.cache_write
+        ),
+        None => println!("Pricing:        unknown (add a [pricing] entry to estimate cost)"),
+    }
+    let today = chrono::Local::now().date_naive();
+    let month_start = today.with_day(1).unwrap_or(today);
+    match crate::proxy::usage::profile_summary(&profile.name, month_start, today) {
+        Ok(summary) => println!(
+            "Cost (month):   {} over {} requests ({} in / {} out tokens)",
+            format_cost(summary.cost_usd),
+            summary.requests,
+            summary.usage.input_tokens + summary.usage.cache_read_tokens,
+            summary.usage.output_tokens
+        ),
+        Err(e) => println!("Cost (month):   unavailable ({e})"),
+    }
+    if let Some(ref budget) = profile.budget {
+        let from = match budget.period {
+            BudgetPeriod::Daily => today,
+            BudgetPeriod::Monthly => month_start,
+        };
+        let spent =
+            crate::proxy::usage::profile_summary(&profile.name, from, today).unwrap_or_default();
+        let mut limits = Vec::new();
+        if let Some(max) = budget.max_tokens {
+            limits.push(format!("{}/{max} tokens", spent.usage.total()));
+        }
+        if let Some(max) = budget.max_cost {
+            limits.push(format!(
+                "{}/{}",
+                format_cost(spent.cost_usd),
+                format_cost(max)
+            ));
+        }
+        println!(
+            "Budget:         {} {} (on exceed: {:?}{})",
+            budget.period,
+            limits.join(", "),
+            budget.on_exceed,
+            budget
+                .fallback_profile
+                .as_ref()
+                .map(|f| format!(" → {f}"))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(ref limit) = profile.rate_limit {
+        let mut parts = Vec::new();
+        if let Some(rpm) = limit.requests_per_minute {
+            parts.push(format!("{rpm} req/min"));
+        }
+        if let Some(tpm) = limit.tokens_per_minute {
+            parts.push(format!("{tpm} tokens/min"));
+        }
+        if let Some(n) = limit.max_concurrent {
+            parts.push(format!("{n} concurrent"));
+        }
+        println!(
+            "Rate limit:     {} (max wait {}ms)",
+            parts.join(", "),
+            limit.max_wait_ms
+        );
+    }
+    if let Some(ref pool) = profile.key_pool {
+        println!(
+            "Key pool:       {} keys ({:?}, cooldown {}s)",
+            pool.keys.len(),
+            pool.strategy,
+            pool.cooldown_secs
+        );
+    }
     Ok(())
 }
 
@@ -79,31 +156,35 @@ pub async fn test_connectivity(profile: &ProfileConfig) -> Result<u128> {
 
     let start = Instant::now();
 
-    let url = match profile.provider_type {
+    let base_url = profile.base_url.trim_end_matches('/');
+    let has_key = !profile.api_key.is_empty();
+    let req = match profile.provider_type {
         ProviderType::DirectAnthropic => {
-            format!("{}/v1/models", profile.base_url.trim_end_matches('/'))
-        }
-        ProviderType::OpenAICompatible => {
-            format!("{}/models", profile.base_url.trim_end_matches('/'))
-        }
-        ProviderType::OpenAIResponses => {
-            // Responses API 没有 /models 端点，直接发一个轻量请求验证连通性
-            format!("{}/models", profile.base_url.trim_end_matches('/'))
+            let req = client.get(format!("{base_url}/v1/models"));
+            if has_key {
+                req.header("x-api-key", &profile.api_key)
+                    .header("anthropic-version", "2023-06-01")
+            } else {
+                req
+            }
         }
-    };
-
-    let mut req = client.get(&url);
-    if !profile.api_key.is_empty() {
-        match profile.provider_type {
-            ProviderType::DirectAnthropic => {
-                req = req.header("x-api-key", &profile.api_key);
-                req = req.header("anthropic-version", "2023-06-01");
+        // Responses API 没有 /models 端点，直接发一个轻量请求验证连通性
+        ProviderType::OpenAICompatible | ProviderType::OpenAIResponses => {
+            let req = client.get(format!("{base_url}/models"));
+            if has_key {
+                req.header("Authorization", format!("Bearer {}", profile.api_key))
+            } else {
+                req
             }
-            ProviderType::OpenAICompatible | ProviderType::OpenAIResponses => {
-                req = req.header("Authorization", format!("Bearer {}", profile.api_key));
+        }
+        // Mock provider 不走网络，只校验 fixture 文件可解析
+        ProviderType::Mock => {
+            if let Some(ref path) = profile.mock_fixtures {
+                crate::proxy::adapter::mock::MockFixtures::load(path)?;
             }
+            return Ok(start.elapsed().as_millis());
         }
-    }
+    };
 
     let resp = req.send().await?;
     let latency = start.elapsed().as_millis();
@@ -154,11 +235,13 @@ pub async fn interactive_add(config: &mut ClaudexConfig) -> Result<()> {
     println!("  1) DirectAnthropic  (Anthropic, MiniMax, OpenRouter)");
     println!("  2) OpenAICompatible (Grok, OpenAI, DeepSeek, Kimi, GLM, Ollama)");
     println!("  3) OpenAIResponses  (ChatGPT/Codex subscription)");
-    let choice = prompt_input("Select [1/2/3]")?;
+    println!("  4) Mock             (offline scripted responses for testing)");
+    let choice = prompt_input("Select [1/2/3/4]")?;
     let provider_type = match choice.as_str() {
         "1" => ProviderType::DirectAnthropic,
         "2" => ProviderType::OpenAICompatible,
         "3" => ProviderType::OpenAIResponses,
+        "4" => ProviderType::Mock,
         _ => {
             println!("Invalid choice, defaulting to OpenAICompatible");
             ProviderType::OpenAICompatible
@@ -183,6 +266,7 @@ pub async fn interactive_add(config: &mut ClaudexConfig) -> Result<()> {
         ProviderType::OpenAIResponses => {
             vec![("ChatGPT/Codex", "https://chatgpt.com/backend-api/codex")]
         }
+        ProviderType::Mock => vec![("Mock (offline)", "mock://local")],
     };
 
     println!("\nBase URL presets:");
@@ -1,5 +1,14 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
 use anyhow::Result;
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::proxy::internal::{self, InternalRequest};
+use crate::proxy::pricing::TokenUsage;
+use crate::proxy::ProxyState;
 
 const COMPRESSION_PROMPT: &str = r#"You are a conversation summarizer. Compress the following conversation history into a concise summary that preserves:
 1. Key decisions and conclusions
@@ -9,85 +18,567 @@ const COMPRESSION_PROMPT: &str = r#"You are a conversation summarizer. Compress
 
 Output a brief but comprehensive summary."#;
 
+/// 摘要输入中单个 tool_result / tool_use 输入保留的最大字符数
+const MAX_TOOL_CHARS: usize = 2000;
+
+const SUMMARY_PREFIX: &str = "[Previous conversation summary]";
+
+/// 摘要缓存最多保留的条目数，超出后淘汰最久未用的
+const MAX_CACHED_SUMMARIES: usize = 256;
+
+/// 摘要模型：经 ProviderAdapter 调用的 profile（`model` 为空时用其 default_model）
+pub struct Summarizer<'a> {
+    pub state: &'a ProxyState,
+    pub profile: &'a str,
+    pub model: &'a str,
+}
+
+/// 一次压缩的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compressed {
+    pub messages: Value,
+    /// 是否直接复用了缓存的摘要（未调用摘要模型）
+    pub cache_hit: bool,
+    /// 摘要模型调用的 token 用量（未调用时为 None）
+    pub usage: Option<TokenUsage>,
+}
+
+impl Compressed {
+    fn unchanged(messages: &[Value]) -> Self {
+        Self {
+            messages: json!(messages),
+            cache_hit: false,
+            usage: None,
+        }
+    }
+}
+
+/// 按被摘要前缀的哈希缓存摘要，供同一会话后续轮次复用
+#[derive(Debug, Default, Clone)]
+pub struct SummaryCache {
+    inner: Arc<Mutex<HashMap<String, (String, Instant)>>>,
+}
+
+impl SummaryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        let mut map = self.inner.lock().unwrap();
+        let entry = map.get_mut(key)?;
+        entry.1 = Instant::now();
+        Some(entry.0.clone())
+    }
+
+    fn insert(&self, key: String, summary: String) {
+        let mut map = self.inner.lock().unwrap();
+        if map.len() >= MAX_CACHED_SUMMARIES && !map.contains_key(&key) {
+            if let Some(oldest) = map
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(k, _)| k.clone())
+            {
+                map.remove(&oldest);
+            }
+        }
+        map.insert(key, (summary, Instant::now()));
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// 各前缀 `messages[..i]` 的链式哈希（下标 0..=len），以摘要模型为种子
+fn prefix_hashes(model: &str, messages: &[Value]) -> Vec<String> {
+    let mut hashes = Vec::with_capacity(messages.len() + 1);
+    let mut current = Sha256::digest(model.as_bytes());
+    hashes.push(hex(&current));
+    for msg in messages {
+        let mut hasher = Sha256::new();
+        hasher.update(current);
+        hasher.update(serde_json::to_vec(msg).unwrap_or_default());
+        current = hasher.finalize();
+        hashes.push(hex(&current));
+    }
+    hashes
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// 压缩较早的历史：优先复用缓存的前缀摘要；前缀比缓存多出 `step` 条以上时，
+/// 只把新增部分与旧摘要一起交给摘要模型（增量摘要）
 pub async fn compress_messages(
-    enabled: bool,
-    keep_recent: usize,
-    base_url: &str,
-    api_key: &str,
-    model: &str,
     messages: &[Value],
-    http_client: &reqwest::Client,
-) -> Result<Value> {
-    if !enabled || messages.len() <= keep_recent {
-        return Ok(json!(messages));
+    keep_recent: usize,
+    step: usize,
+    cache: &SummaryCache,
+    summarizer: &Summarizer<'_>,
+) -> Result<Compressed> {
+    if messages.len() <= keep_recent {
+        return Ok(Compressed::unchanged(messages));
+    }
+
+    let split_at = safe_split_point(messages, keep_recent);
+    if split_at == 0 {
+        return Ok(Compressed::unchanged(messages));
+    }
+
+    let seed = format!("{}/{}", summarizer.profile, summarizer.model);
+    let hashes = prefix_hashes(&seed, messages);
+    let cached = (1..=split_at)
+        .rev()
+        .filter(|&n| is_cut_point(messages, n))
+        .find_map(|n| cache.get(&hashes[n]).map(|summary| (n, summary)));
+
+    let input = match cached {
+        Some((n, summary)) if split_at - n <= step => {
+            tracing::debug!(prefix = n, "reusing cached conversation summary");
+            return Ok(Compressed {
+                messages: json!(with_summary(&summary, &messages[n..])),
+                cache_hit: true,
+                usage: None,
+            });
+        }
+        Some((n, summary)) => {
+            let delta = conversation_text(&messages[n..split_at]);
+            format!("{SUMMARY_PREFIX}\n{summary}\n\n{delta}")
+        }
+        None => conversation_text(&messages[..split_at]),
+    };
+    if input.is_empty() {
+        return Ok(Compressed::unchanged(messages));
     }
 
-    let split_at = messages.len().saturating_sub(keep_recent);
-    let old_messages = &messages[..split_at];
-    let recent_messages = &messages[split_at..];
+    let (summary, usage) = call_summarizer(summarizer, &input).await?;
+    cache.insert(hashes[split_at].clone(), summary.clone());
+
+    Ok(Compressed {
+        messages: json!(with_summary(&summary, &messages[split_at..])),
+        cache_hit: false,
+        usage,
+    })
+}
+
+/// 压缩切分点：不早于保留 `keep_recent` 条的位置向前回退，直到遇到开启新一轮的 user 消息
+/// （不含 tool_result），或紧跟在工具结果已齐全的 user 消息之后的 assistant 消息
+/// （单条指令后连续多轮工具调用的会话也能压缩），保证保留部分不会出现找不到对应 tool_use 的
+/// tool_result。找不到安全切分点时返回 0（不压缩）
+pub fn safe_split_point(messages: &[Value], keep_recent: usize) -> usize {
+    let start = messages.len().saturating_sub(keep_recent);
+    (1..=start)
+        .rev()
+        .find(|&i| is_cut_point(messages, i))
+        .unwrap_or(0)
+}
+
+/// 可在 `messages[i]` 之前切分；摘要只会缓存在这些位置上
+fn is_cut_point(messages: &[Value], i: usize) -> bool {
+    is_turn_start(&messages[i]) || follows_tool_round(messages, i)
+}
+
+/// `messages[i]` 是否为 assistant 消息，且前一条 user 消息答复了再前一条 assistant 的全部 tool_use
+fn follows_tool_round(messages: &[Value], i: usize) -> bool {
+    if i < 2 || role(&messages[i]) != Some("assistant") || role(&messages[i - 1]) != Some("user") {
+        return false;
+    }
+    let results = block_ids(&messages[i - 1], "tool_result", "tool_use_id");
+    !results.is_empty()
+        && block_ids(&messages[i - 2], "tool_use", "id")
+            .iter()
+            .all(|id| results.contains(id))
+}
+
+fn role(message: &Value) -> Option<&str> {
+    message.get("role").and_then(|r| r.as_str())
+}
+
+/// 消息中指定类型内容块的 id 字段
+fn block_ids<'a>(message: &'a Value, block_type: &str, id_field: &str) -> Vec<&'a str> {
+    message
+        .get("content")
+        .and_then(|c| c.as_array())
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some(block_type))
+                .filter_map(|b| b.get(id_field).and_then(|id| id.as_str()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
 
-    // Build conversation text from old messages
-    let conversation_text: String = old_messages
+/// 是否为新一轮对话的开始：user 消息且不含 tool_result
+fn is_turn_start(message: &Value) -> bool {
+    message.get("role").and_then(|r| r.as_str()) == Some("user")
+        && !message
+            .get("content")
+            .and_then(|c| c.as_array())
+            .is_some_and(|blocks| {
+                blocks
+                    .iter()
+                    .any(|b| b.get("type").and_then(|t| t.as_str()) == Some("tool_result"))
+            })
+}
+
+/// 把待压缩的消息展开为摘要输入文本
+pub fn conversation_text(messages: &[Value]) -> String {
+    messages
         .iter()
         .filter_map(|msg| {
             let role = msg.get("role")?.as_str()?;
-            let content = msg.get("content")?.as_str()?;
-            Some(format!("{role}: {content}"))
+            let content = flatten_content(msg.get("content")?);
+            (!content.is_empty()).then(|| format!("{role}: {content}"))
         })
         .collect::<Vec<_>>()
-        .join("\n\n");
+        .join("\n\n")
+}
 
-    if conversation_text.is_empty() {
-        return Ok(json!(messages));
+/// 展开字符串或内容块数组：文本原样保留，工具调用与结果截断，图片等以占位符表示
+pub fn flatten_content(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(flatten_block)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
     }
+}
 
-    let summary =
-        call_summarizer(base_url, api_key, model, &conversation_text, http_client).await?;
+fn flatten_block(block: &Value) -> Option<String> {
+    match block.get("type").and_then(|t| t.as_str())? {
+        "text" => block.get("text")?.as_str().map(String::from),
+        "tool_use" => {
+            let name = block.get("name").and_then(|n| n.as_str()).unwrap_or("tool");
+            let input = block
+                .get("input")
+                .map(|i| i.to_string())
+                .unwrap_or_default();
+            Some(format!("[tool_use {name}] {}", truncate(&input)))
+        }
+        "tool_result" => {
+            let label = if block.get("is_error").and_then(|e| e.as_bool()) == Some(true) {
+                "[tool_result error]"
+            } else {
+                "[tool_result]"
+            };
+            let output = block
+                .get("content")
+                .map(flatten_content)
+                .unwrap_or_default();
+            Some(format!("{label} {}", truncate(&output)))
+        }
+        "image" => Some("[image]".to_string()),
+        "document" => Some("[document]".to_string()),
+        // thinking / redacted_thinking 等不进入摘要
+        _ => None,
+    }
+}
 
-    let mut result = vec![json!({
-        "role": "user",
-        "content": format!("[Previous conversation summary]\n{summary}")
-    })];
-    result.extend(recent_messages.iter().cloned());
+fn truncate(text: &str) -> String {
+    let count = text.chars().count();
+    if count <= MAX_TOOL_CHARS {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(MAX_TOOL_CHARS).collect();
+    format!("{head}...(truncated, {count} chars)")
+}
 
-    Ok(json!(result))
+/// 把摘要并入保留部分的首条 user 消息，保持 user / assistant 交替
+pub fn with_summary(summary: &str, recent: &[Value]) -> Vec<Value> {
+    let summary_text = format!("{SUMMARY_PREFIX}\n{summary}");
+    let mut result = vec![json!({"role": "user", "content": summary_text})];
+    result.extend(recent.iter().cloned());
+    merge_consecutive_roles(result)
+}
+
+/// 合并相邻的同角色消息（内容统一转为块数组）
+pub fn merge_consecutive_roles(messages: Vec<Value>) -> Vec<Value> {
+    let mut merged: Vec<Value> = Vec::with_capacity(messages.len());
+    for msg in messages {
+        let role = msg.get("role").and_then(|r| r.as_str());
+        let same_role = role.is_some()
+            && merged
+                .last()
+                .is_some_and(|last| last.get("role").and_then(|r| r.as_str()) == role);
+        if !same_role {
+            merged.push(msg);
+            continue;
+        }
+        let last = merged.last_mut().expect("checked above");
+        let mut blocks = into_blocks(last.get("content"));
+        blocks.extend(into_blocks(msg.get("content")));
+        last["content"] = Value::Array(blocks);
+    }
+    merged
+}
+
+fn into_blocks(content: Option<&Value>) -> Vec<Value> {
+    match content {
+        Some(Value::String(s)) => vec![json!({"type": "text", "text": s})],
+        Some(Value::Array(blocks)) => blocks.clone(),
+        _ => Vec::new(),
+    }
 }
 
 async fn call_summarizer(
-    base_url: &str,
-    api_key: &str,
-    model: &str,
+    summarizer: &Summarizer<'_>,
     text: &str,
-    http_client: &reqwest::Client,
-) -> Result<String> {
-    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+) -> Result<(String, Option<TokenUsage>)> {
+    let reply = internal::complete(
+        summarizer.state,
+        &InternalRequest {
+            profile: summarizer.profile,
+            model: summarizer.model,
+            system: COMPRESSION_PROMPT,
+            prompt: text,
+            max_tokens: 1000,
+            temperature: 0.3,
+        },
+    )
+    .await?;
+    if reply.text.trim().is_empty() {
+        anyhow::bail!("summarizer returned an empty summary");
+    }
+    Ok((reply.text, reply.usage))
+}
 
-    let body = json!({
-        "model": model,
-        "messages": [
-            {"role": "system", "content": COMPRESSION_PROMPT},
-            {"role": "user", "content": text},
-        ],
-        "max_tokens": 1000,
-        "temperature": 0.3,
-    });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut req = http_client.post(&url).json(&body);
-    if !api_key.is_empty() {
-        req = req.header("Authorization", format!("Bearer {api_key}"));
+    fn tool_turn(id: &str) -> [Value; 2] {
+        [
+            json!({"role": "assistant", "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": id, "name": "Read", "input": {"path": "src/main.rs"}}
+            ]}),
+            json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": id, "content": [{"type": "text", "text": "fn main() {}"}]}
+            ]}),
+        ]
     }
 
-    let resp: Value = req.send().await?.json().await?;
+    fn conversation() -> Vec<Value> {
+        let mut messages = vec![json!({"role": "user", "content": "fix the bug"})];
+        messages.extend(tool_turn("t1"));
+        messages.push(json!({"role": "assistant", "content": "Found it."}));
+        messages
+            .push(json!({"role": "user", "content": [{"type": "text", "text": "now add tests"}]}));
+        messages.extend(tool_turn("t2"));
+        messages.push(json!({"role": "assistant", "content": "Done."}));
+        messages
+    }
 
-    let summary = resp
-        .get("choices")
-        .and_then(|c| c.as_array())
-        .and_then(|c| c.first())
-        .and_then(|c| c.get("message"))
-        .and_then(|m| m.get("content"))
-        .and_then(|c| c.as_str())
-        .unwrap_or("[compression failed]")
-        .to_string();
-
-    Ok(summary)
+    fn roles(messages: &[Value]) -> Vec<&str> {
+        messages
+            .iter()
+            .map(|m| m["role"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_flatten_blocks() {
+        let text = conversation_text(&conversation()[..3]);
+        assert!(text.contains("user: fix the bug"));
+        assert!(text.contains("[tool_use Read] {\"path\":\"src/main.rs\"}"));
+        assert!(text.contains("[tool_result] fn main() {}"));
+    }
+
+    #[test]
+    fn test_flatten_truncates_tool_results() {
+        let big = "x".repeat(MAX_TOOL_CHARS + 500);
+        let content =
+            json!([{"type": "tool_result", "tool_use_id": "t", "is_error": true, "content": big}]);
+        let text = flatten_content(&content);
+        assert!(text.starts_with("[tool_result error] "));
+        assert!(text.ends_with(&format!("(truncated, {} chars)", MAX_TOOL_CHARS + 500)));
+        assert!(text.len() < MAX_TOOL_CHARS + 100);
+        assert_eq!(flatten_content(&json!([{"type": "image"}])), "[image]");
+    }
+
+    #[test]
+    fn test_split_never_orphans_tool_result() {
+        let messages = conversation();
+        // keep_recent = 2 落在 tool_result 上，回退到 "now add tests"
+        assert_eq!(safe_split_point(&messages, 2), 4);
+        assert_eq!(safe_split_point(&messages, 4), 4);
+        // 要保留更多时回退到工具结果之后的 assistant 消息
+        assert_eq!(safe_split_point(&messages, 5), 3);
+        assert_eq!(safe_split_point(&messages[..4], 1), 3);
+        // 再往前只能回退到开头，放弃压缩
+        assert_eq!(safe_split_point(&messages, 6), 0);
+        assert_eq!(safe_split_point(&messages[..3], 1), 0);
+    }
+
+    #[test]
+    fn test_split_within_single_prompt_tool_rounds() {
+        let mut messages = vec![json!({"role": "user", "content": "migrate the repo"})];
+        for i in 0..5 {
+            messages.extend(tool_turn(&format!("t{i}")));
+        }
+        messages.push(json!({"role": "assistant", "content": "Migrated."}));
+
+        let split = safe_split_point(&messages, 4);
+        assert_eq!(split, 7);
+        assert_eq!(messages[split]["role"], "assistant");
+        let result = with_summary("earlier rounds", &messages[split..]);
+        assert_eq!(
+            roles(&result),
+            vec![
+                "user",
+                "assistant",
+                "user",
+                "assistant",
+                "user",
+                "assistant"
+            ]
+        );
+
+        // tool_result 不齐全时不在其后切分
+        let mut partial = messages.clone();
+        partial[6]["content"][0]["tool_use_id"] = json!("other");
+        assert_eq!(safe_split_point(&partial, 4), 5);
+    }
+
+    #[test]
+    fn test_summary_keeps_alternation() {
+        let messages = conversation();
+        let split = safe_split_point(&messages, 2);
+        let result = with_summary("earlier work", &messages[split..]);
+        assert_eq!(
+            roles(&result),
+            vec!["user", "assistant", "user", "assistant"]
+        );
+        let first = result[0]["content"].as_array().unwrap();
+        assert!(first[0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with(SUMMARY_PREFIX));
+        assert_eq!(first[1]["text"], "now add tests");
+    }
+
+    #[test]
+    fn test_prefix_hashes_extend() {
+        let messages = conversation();
+        let full = prefix_hashes("m", &messages);
+        let shorter = prefix_hashes("m", &messages[..4]);
+        assert_eq!(full.len(), messages.len() + 1);
+        assert_eq!(&full[..5], &shorter[..]);
+        assert_ne!(prefix_hashes("other", &messages)[4], full[4]);
+    }
+
+    /// 摘要 profile 指向不可达的地址：若真的调用摘要模型会返回错误
+    fn unreachable_state() -> ProxyState {
+        let config = crate::config::ClaudexConfig {
+            profiles: vec![crate::config::ProfileConfig {
+                name: "s".to_string(),
+                provider_type: crate::config::ProviderType::OpenAICompatible,
+                base_url: "http://127.0.0.1:9".to_string(),
+                default_model: "m".to_string(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        ProxyState::new(config, reqwest::Client::new())
+    }
+
+    #[tokio::test]
+    async fn test_cached_summary_reused_within_step() {
+        let state = unreachable_state();
+        let summarizer = Summarizer {
+            state: &state,
+            profile: "s",
+            model: "",
+        };
+        let mut messages = conversation();
+        messages.push(json!({"role": "user", "content": "and the docs"}));
+        messages.push(json!({"role": "assistant", "content": "Updated."}));
+        let cache = SummaryCache::new();
+        cache.insert(prefix_hashes("s/", &messages)[4].clone(), "earlier".into());
+
+        // 切分点 8 比缓存前缀 4 多 4 条，未超过 step
+        let out = compress_messages(&messages, 2, 10, &cache, &summarizer)
+            .await
+            .unwrap();
+        assert!(out.cache_hit);
+        assert!(out.usage.is_none());
+        let result = out.messages.as_array().unwrap();
+        assert_eq!(result.len(), messages.len() - 4);
+        assert!(result[0]["content"][0]["text"]
+            .as_str()
+            .unwrap()
+            .ends_with("earlier"));
+
+        // 超过 step 时需要增量摘要（此处摘要模型不可达）
+        assert!(compress_messages(&messages, 2, 2, &cache, &summarizer)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cached_summary_reused_across_tool_rounds() {
+        let state = unreachable_state();
+        let summarizer = Summarizer {
+            state: &state,
+            profile: "s",
+            model: "",
+        };
+        let mut messages = vec![json!({"role": "user", "content": "migrate the repo"})];
+        for i in 0..5 {
+            messages.extend(tool_turn(&format!("t{i}")));
+        }
+        messages.push(json!({"role": "assistant", "content": "Migrated."}));
+        // 上一轮在工具结果之后的 assistant 处切分并缓存了摘要
+        let split = safe_split_point(&messages, 4);
+        assert_eq!(split, 7);
+        let cache = SummaryCache::new();
+        cache.insert(
+            prefix_hashes("s/", &messages)[split].clone(),
+            "earlier".into(),
+        );
+
+        // 同一条指令下又跑了一轮工具，新切分点 9 仍可复用前缀 7 的摘要
+        messages.pop();
+        messages.extend(tool_turn("t5"));
+        messages.push(json!({"role": "assistant", "content": "Migrated."}));
+        let out = compress_messages(&messages, 4, 10, &cache, &summarizer)
+            .await
+            .unwrap();
+        assert!(out.cache_hit);
+        let result = out.messages.as_array().unwrap();
+        assert_eq!(result.len(), messages.len() - split + 1);
+        assert!(result[0]["content"].as_str().unwrap().ends_with("earlier"));
+        assert_eq!(result[1]["role"], "assistant");
+    }
+
+    #[test]
+    fn test_summary_cache_evicts_oldest() {
+        let cache = SummaryCache::new();
+        for i in 0..MAX_CACHED_SUMMARIES + 5 {
+            cache.insert(format!("k{i}"), "s".into());
+        }
+        assert_eq!(cache.len(), MAX_CACHED_SUMMARIES);
+    }
+
+    #[test]
+    fn test_merge_consecutive_roles() {
+        let merged = merge_consecutive_roles(vec![
+            json!({"role": "user", "content": "a"}),
+            json!({"role": "user", "content": [{"type": "text", "text": "b"}]}),
+            json!({"role": "assistant", "content": "c"}),
+        ]);
+        assert_eq!(roles(&merged), vec!["user", "assistant"]);
+        assert_eq!(merged[0]["content"].as_array().unwrap().len(), 2);
+        assert_eq!(merged[1]["content"], "c");
+    }
 }
@@ -2,6 +2,8 @@ pub mod compression;
 pub mod rag;
 pub mod sharing;
 
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 
 use crate::config::ClaudexConfig;
@@ -24,6 +26,9 @@ pub struct CompressionConfig {
     pub threshold_tokens: usize,
     #[serde(default = "default_keep_recent")]
     pub keep_recent: usize,
+    /// 被摘要前缀比缓存的摘要多出超过该条数时才重新（增量）摘要
+    #[serde(default = "default_summary_step")]
+    pub summary_step: usize,
     #[serde(default)]
     pub profile: String,
     #[serde(default)]
@@ -48,10 +53,55 @@ pub struct RagConfig {
     pub profile: String,
     #[serde(default)]
     pub model: String,
+    /// 每个分块的最大 token 数
     #[serde(default = "default_chunk_size")]
     pub chunk_size: usize,
+    /// 超长条目按行切分时相邻分块重叠的行数
+    #[serde(default = "default_chunk_overlap")]
+    pub chunk_overlap: usize,
     #[serde(default = "default_top_k")]
     pub top_k: usize,
+    /// 持久化索引目录（默认 ~/.cache/claudex/rag）
+    #[serde(default)]
+    pub index_dir: Option<PathBuf>,
+    /// 监听 index_paths 的文件变化并在后台增量更新索引
+    #[serde(default = "default_true")]
+    pub watch: bool,
+    /// 合并连续保存的防抖时间（毫秒）
+    #[serde(default = "default_watch_debounce_ms")]
+    pub watch_debounce_ms: u64,
+    /// 额外排除的路径（.gitignore 语法，相对各 index path；隐藏目录始终跳过）
+    #[serde(default = "default_rag_exclude")]
+    pub exclude: Vec<String>,
+    /// 检索方式；embedding 不可用时回退到 BM25
+    #[serde(default)]
+    pub retrieval: RetrievalMode,
+    /// hybrid 模式中 embedding 分数的权重（其余为 BM25）
+    #[serde(default = "default_hybrid_weight")]
+    pub hybrid_weight: f32,
+}
+
+/// RAG 检索方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RetrievalMode {
+    /// 仅向量相似度
+    Embedding,
+    /// 仅本地 BM25（不访问 embedding 接口）
+    Bm25,
+    /// 向量与 BM25 加权融合
+    #[default]
+    Hybrid,
+}
+
+impl std::fmt::Display for RetrievalMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RetrievalMode::Embedding => write!(f, "embedding"),
+            RetrievalMode::Bm25 => write!(f, "bm25"),
+            RetrievalMode::Hybrid => write!(f, "hybrid"),
+        }
+    }
 }
 
 fn default_threshold_tokens() -> usize {
@@ -60,15 +110,35 @@ fn default_threshold_tokens() -> usize {
 fn default_keep_recent() -> usize {
     10
 }
+fn default_summary_step() -> usize {
+    10
+}
 fn default_max_context_size() -> usize {
     2000
 }
 fn default_chunk_size() -> usize {
     512
 }
+fn default_chunk_overlap() -> usize {
+    3
+}
 fn default_top_k() -> usize {
     5
 }
+fn default_true() -> bool {
+    true
+}
+fn default_watch_debounce_ms() -> u64 {
+    500
+}
+fn default_hybrid_weight() -> f32 {
+    0.5
+}
+fn default_rag_exclude() -> Vec<String> {
+    ["target/", "node_modules/", "dist/"]
+        .map(String::from)
+        .to_vec()
+}
 
 impl Default for CompressionConfig {
     fn default() -> Self {
@@ -76,6 +146,7 @@ impl Default for CompressionConfig {
             enabled: false,
             threshold_tokens: default_threshold_tokens(),
             keep_recent: default_keep_recent(),
+            summary_step: default_summary_step(),
             profile: String::new(),
             model: String::new(),
         }
@@ -99,11 +170,26 @@ impl Default for RagConfig {
             profile: String::new(),
             model: String::new(),
             chunk_size: default_chunk_size(),
+            chunk_overlap: default_chunk_overlap(),
             top_k: default_top_k(),
+            index_dir: None,
+            watch: true,
+            watch_debounce_ms: default_watch_debounce_ms(),
+            exclude: default_rag_exclude(),
+            retrieval: RetrievalMode::default(),
+            hybrid_weight: default_hybrid_weight(),
         }
     }
 }
 
+impl RagConfig {
+    pub fn resolve_index_dir(&self) -> Option<PathBuf> {
+        self.index_dir
+            .clone()
+            .or_else(|| dirs::cache_dir().map(|d| d.join("claudex").join("rag")))
+    }
+}
+
 /// Resolve a profile reference to (base_url, api_key, model).
 /// `model_override` takes precedence over the profile's `default_model`.
 pub fn resolve_profile_endpoint(
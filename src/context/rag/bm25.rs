@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+
+/// BM25 词频饱和参数
+const K1: f32 = 1.2;
+/// BM25 文档长度归一化参数
+const B: f32 = 0.75;
+
+/// 基于倒排表的 BM25 索引，文档顺序与构建时传入的 chunk 顺序一致
+#[derive(Debug, Default)]
+pub(super) struct Bm25Index {
+    postings: HashMap<String, Vec<(u32, u32)>>,
+    doc_len: Vec<u32>,
+    avg_len: f32,
+}
+
+impl Bm25Index {
+    pub fn build<'a>(docs: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut index = Self::default();
+        for (doc, text) in docs.into_iter().enumerate() {
+            let mut tf: HashMap<String, u32> = HashMap::new();
+            let mut len = 0;
+            for term in tokenize(text) {
+                *tf.entry(term).or_default() += 1;
+                len += 1;
+            }
+            for (term, count) in tf {
+                index
+                    .postings
+                    .entry(term)
+                    .or_default()
+                    .push((doc as u32, count));
+            }
+            index.doc_len.push(len);
+        }
+        let total: u64 = index.doc_len.iter().map(|&l| l as u64).sum();
+        index.avg_len = if index.doc_len.is_empty() {
+            0.0
+        } else {
+            total as f32 / index.doc_len.len() as f32
+        };
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.doc_len.len()
+    }
+
+    /// 每个文档对查询的 BM25 分数（与构建顺序对应，不含查询词的文档为 0）
+    pub fn scores(&self, query: &str) -> Vec<f32> {
+        let mut scores = vec![0.0; self.doc_len.len()];
+        let n = self.doc_len.len() as f32;
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+        for term in terms {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+            let df = postings.len() as f32;
+            let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+            for &(doc, tf) in postings {
+                let tf = tf as f32;
+                let len = self.doc_len[doc as usize] as f32;
+                let norm = K1 * (1.0 - B + B * len / self.avg_len.max(1.0));
+                scores[doc as usize] += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+        scores
+    }
+}
+
+/// 词项切分：按非字母数字切开，标识符额外拆出驼峰 / 下划线片段，CJK 按单字与相邻双字切分
+pub(super) fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut word = String::new();
+    let mut cjk_prev: Option<char> = None;
+    for c in text.chars().chain(std::iter::once(' ')) {
+        if is_cjk(c) {
+            flush_word(&mut word, &mut terms);
+            terms.push(c.to_string());
+            if let Some(prev) = cjk_prev {
+                terms.push(format!("{prev}{c}"));
+            }
+            cjk_prev = Some(c);
+            continue;
+        }
+        cjk_prev = None;
+        if c.is_alphanumeric() || c == '_' {
+            word.push(c);
+        } else {
+            flush_word(&mut word, &mut terms);
+        }
+    }
+    terms
+}
+
+fn flush_word(word: &mut String, terms: &mut Vec<String>) {
+    if word.is_empty() {
+        return;
+    }
+    let parts = identifier_parts(word);
+    if parts.len() > 1 {
+        terms.extend(parts.into_iter().filter(|p| p.chars().count() > 1));
+    }
+    let whole = word.trim_matches('_').to_lowercase();
+    if whole.chars().count() > 1 {
+        terms.push(whole);
+    }
+    word.clear();
+}
+
+/// `parseHttpRequest` / `parse_http_request` → `parse`, `http`, `request`
+fn identifier_parts(word: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut prev_lower = false;
+    for c in word.chars() {
+        if c == '_' {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current).to_lowercase());
+            }
+            prev_lower = false;
+            continue;
+        }
+        if c.is_uppercase() && prev_lower && !current.is_empty() {
+            parts.push(std::mem::take(&mut current).to_lowercase());
+        }
+        prev_lower = c.is_lowercase() || c.is_ascii_digit();
+        current.push(c);
+    }
+    if !current.is_empty() {
+        parts.push(current.to_lowercase());
+    }
+    parts
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32, 0x3040..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_identifiers_and_cjk() {
+        assert_eq!(
+            tokenize("fn parseHttpRequest(x)"),
+            vec!["fn", "parse", "http", "request", "parsehttprequest"]
+        );
+        assert_eq!(
+            tokenize("load_config"),
+            vec!["load", "config", "load_config"]
+        );
+        assert_eq!(tokenize("熔断器"), vec!["熔", "断", "熔断", "器", "断器"]);
+    }
+
+    #[test]
+    fn test_bm25_ranks_matching_document_first() {
+        let docs = [
+            "fn load_config(path: &Path) -> Result<Config>",
+            "fn render_dashboard(frame: &mut Frame)",
+            "// circuit breaker 熔断器 state machine",
+        ];
+        let index = Bm25Index::build(docs);
+        assert_eq!(index.len(), 3);
+
+        let scores = index.scores("where is the config loaded");
+        assert!(scores[0] > 0.0);
+        assert_eq!(scores[1], 0.0);
+
+        let scores = index.scores("熔断");
+        assert!(scores[2] > scores[0]);
+        assert!(index.scores("").iter().all(|&s| s == 0.0));
+    }
+}
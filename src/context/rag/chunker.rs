@@ -0,0 +1,571 @@
+use std::path::Path;
+
+use crate::tokenizer::Tokenizer;
+
+/// 一个分块：起始行号（从 1 开始）、文本与所属符号（如 `fn load_config`）
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Chunk {
+    pub start_line: usize,
+    pub content: String,
+    pub symbol: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Language {
+    Rust,
+    TypeScript,
+    Python,
+    Go,
+    Markdown,
+    Other,
+}
+
+impl Language {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()).unwrap_or("") {
+            "rs" => Language::Rust,
+            "ts" | "tsx" | "js" | "jsx" | "mjs" | "cjs" => Language::TypeScript,
+            "py" => Language::Python,
+            "go" => Language::Go,
+            "md" | "markdown" => Language::Markdown,
+            _ => Language::Other,
+        }
+    }
+
+    /// 嵌套符号的分隔符（`Config::load` / `Config.load`）
+    fn separator(self) -> &'static str {
+        match self {
+            Language::Rust => "::",
+            _ => ".",
+        }
+    }
+
+    /// 归属于下一个条目的前导行（文档注释、属性、装饰器）
+    fn is_leading(self, trimmed: &str) -> bool {
+        let prefixes: &[&str] = match self {
+            Language::Rust => &["//", "#[", "#!["],
+            Language::TypeScript => &["//", "/*", "*", "@"],
+            Language::Python => &["#", "@"],
+            Language::Go => &["//"],
+            Language::Markdown | Language::Other => &[],
+        };
+        prefixes.iter().any(|p| trimmed.starts_with(p))
+    }
+}
+
+/// 识别出的条目：种类（fn / impl / class / def / ## ...）与名称
+#[derive(Debug, Clone, PartialEq)]
+struct Item {
+    kind: String,
+    name: String,
+}
+
+impl Item {
+    fn new(kind: &str, name: &str) -> Self {
+        Self {
+            kind: kind.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    fn symbol(&self) -> String {
+        format!("{} {}", self.kind, self.name)
+    }
+
+    /// 作为嵌套条目前缀时使用的名称（`impl Display for Config` → `Config`）
+    fn scope_name(&self) -> &str {
+        let name = self.name.rsplit(" for ").next().unwrap_or(&self.name);
+        name.split('<').next().unwrap_or(name).trim()
+    }
+}
+
+/// 按语言切分文件：顶层条目（fn / impl / class / def / func / 标题）各成一块，
+/// 超出 `max_tokens` 的条目先按其内部方法拆分，再按行窗口切分（相邻窗口重叠 `overlap` 行）。
+/// 不支持的语言或找不到条目时按行窗口切分
+pub(super) fn chunk_file(
+    path: &Path,
+    content: &str,
+    max_tokens: usize,
+    overlap: usize,
+) -> Vec<Chunk> {
+    let language = Language::from_path(path);
+    let lines: Vec<&str> = content.lines().collect();
+    let tokenizer = Tokenizer::for_model("");
+    let costs: Vec<usize> = lines
+        .iter()
+        .map(|l| tokenizer.count_text(l) as usize + 1)
+        .collect();
+    let splitter = Splitter {
+        language,
+        lines: &lines,
+        costs: &costs,
+        max_tokens: max_tokens.max(1),
+        overlap,
+    };
+
+    let mut chunks = Vec::new();
+    for (start, end, item) in splitter.segments(0, lines.len(), true) {
+        splitter.emit(start, end, item.as_ref(), true, &mut chunks);
+    }
+    chunks
+}
+
+struct Splitter<'a> {
+    language: Language,
+    lines: &'a [&'a str],
+    costs: &'a [usize],
+    max_tokens: usize,
+    overlap: usize,
+}
+
+impl Splitter<'_> {
+    /// 在 [start, end) 中按条目边界切段；`top_level` 时只认不缩进的条目，
+    /// 否则认缩进最小的一层嵌套条目
+    fn segments(
+        &self,
+        start: usize,
+        end: usize,
+        top_level: bool,
+    ) -> Vec<(usize, usize, Option<Item>)> {
+        let mut starts: Vec<(usize, Item)> = Vec::new();
+        let mut in_fence = false;
+        let mut nested_indent = usize::MAX;
+        for i in start..end {
+            let line = self.lines[i];
+            if self.language == Language::Markdown && line.trim_start().starts_with("```") {
+                in_fence = !in_fence;
+                continue;
+            }
+            if in_fence {
+                continue;
+            }
+            let indent = line.len() - line.trim_start().len();
+            if top_level != (indent == 0) {
+                continue;
+            }
+            if let Some(item) = detect_item(self.language, line.trim(), !top_level) {
+                if !top_level {
+                    if indent > nested_indent {
+                        continue;
+                    }
+                    if indent < nested_indent {
+                        nested_indent = indent;
+                        starts.clear();
+                    }
+                }
+                starts.push((i, item));
+            }
+        }
+
+        // 前导注释 / 属性 / 装饰器归入下一个条目
+        let mut boundaries: Vec<(usize, Item)> = Vec::new();
+        for (i, item) in starts {
+            let floor = boundaries.last().map_or(start, |(b, _)| b + 1);
+            let mut b = i;
+            while b > floor && self.language.is_leading(self.lines[b - 1].trim()) {
+                b -= 1;
+            }
+            boundaries.push((b, item));
+        }
+
+        let mut segments = Vec::new();
+        let first = boundaries.first().map_or(end, |(b, _)| *b);
+        if first > start {
+            segments.push((start, first, None));
+        }
+        for (k, (b, item)) in boundaries.iter().enumerate() {
+            let next = boundaries.get(k + 1).map_or(end, |(n, _)| *n);
+            segments.push((*b, next, Some(item.clone())));
+        }
+        segments
+    }
+
+    fn cost(&self, start: usize, end: usize) -> usize {
+        self.costs[start..end].iter().sum()
+    }
+
+    fn emit(
+        &self,
+        start: usize,
+        end: usize,
+        item: Option<&Item>,
+        top_level: bool,
+        chunks: &mut Vec<Chunk>,
+    ) {
+        if self.lines[start..end].iter().all(|l| l.trim().is_empty()) {
+            return;
+        }
+        if self.cost(start, end) <= self.max_tokens {
+            self.push(start, end, item.map(Item::symbol), chunks);
+            return;
+        }
+        // 过大的 impl / class：按内部方法拆分
+        if let (true, Some(parent)) = (top_level, item) {
+            let nested = self.segments(start, end, false);
+            if nested.iter().any(|(_, _, i)| i.is_some()) {
+                for (s, e, child) in nested {
+                    let child = match child {
+                        Some(child) => Item {
+                            kind: child.kind.clone(),
+                            name: format!(
+                                "{}{}{}",
+                                parent.scope_name(),
+                                self.language.separator(),
+                                child.name
+                            ),
+                        },
+                        None => parent.clone(),
+                    };
+                    self.emit(s, e, Some(&child), false, chunks);
+                }
+                return;
+            }
+        }
+        self.windows(start, end, item.map(Item::symbol), chunks);
+    }
+
+    /// 按 token 预算切成行窗口，相邻窗口重叠 `overlap` 行
+    fn windows(&self, start: usize, end: usize, symbol: Option<String>, chunks: &mut Vec<Chunk>) {
+        let mut from = start;
+        while from < end {
+            let mut to = from;
+            let mut used = 0;
+            while to < end && (to == from || used + self.costs[to] <= self.max_tokens) {
+                used += self.costs[to];
+                to += 1;
+            }
+            self.push(from, to, symbol.clone(), chunks);
+            if to >= end {
+                break;
+            }
+            from = to.saturating_sub(self.overlap).max(from + 1);
+        }
+    }
+
+    fn push(&self, start: usize, end: usize, symbol: Option<String>, chunks: &mut Vec<Chunk>) {
+        let content = self.lines[start..end].join("\n");
+        if content.trim().is_empty() {
+            return;
+        }
+        chunks.push(Chunk {
+            start_line: start + 1,
+            content,
+            symbol,
+        });
+    }
+}
+
+/// 行首的标识符
+fn ident(s: &str) -> &str {
+    let end = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .unwrap_or(s.len());
+    &s[..end]
+}
+
+/// 依次去掉 `prefixes` 中出现在行首的修饰词
+fn strip_modifiers<'a>(mut line: &'a str, prefixes: &[&str]) -> &'a str {
+    loop {
+        let before = line;
+        for prefix in prefixes {
+            if let Some(rest) = line.strip_prefix(prefix) {
+                if rest.starts_with(char::is_whitespace) {
+                    line = rest.trim_start();
+                }
+            }
+        }
+        if line == before {
+            return line;
+        }
+    }
+}
+
+fn detect_item(language: Language, line: &str, nested: bool) -> Option<Item> {
+    match language {
+        Language::Rust => rust_item(line),
+        Language::TypeScript => ts_item(line, nested),
+        Language::Python => python_item(line),
+        Language::Go if !nested => go_item(line),
+        Language::Markdown if !nested => markdown_heading(line),
+        _ => None,
+    }
+}
+
+fn rust_item(line: &str) -> Option<Item> {
+    let mut rest = line;
+    if let Some(after) = rest.strip_prefix("pub") {
+        rest = match after.strip_prefix('(') {
+            Some(scoped) => &scoped[scoped.find(')')? + 1..],
+            None => after,
+        };
+        if !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        rest = rest.trim_start();
+    }
+    rest = strip_modifiers(rest, &["async", "unsafe", "default", "extern \"C\""]);
+    // `const fn` 与 `const X` 都以 const 开头
+    if let Some(after) = rest.strip_prefix("const ") {
+        let after = strip_modifiers(after.trim_start(), &["async", "unsafe"]);
+        if after.starts_with("fn ") {
+            rest = after;
+        }
+    }
+    if let Some(name) = rest.strip_prefix("macro_rules!") {
+        return Some(Item::new("macro_rules!", ident(name.trim_start())));
+    }
+    let kind = ident(rest);
+    let after = rest[kind.len()..].trim_start();
+    match kind {
+        "impl" => {
+            let mut target = after;
+            if target.starts_with('<') {
+                target = target[matching_angle(target)?..].trim_start();
+            }
+            let target = target
+                .split(['{', ';'])
+                .next()
+                .unwrap_or(target)
+                .split(" where")
+                .next()
+                .unwrap_or(target)
+                .trim();
+            (!target.is_empty()).then(|| Item::new("impl", target))
+        }
+        "fn" | "struct" | "enum" | "trait" | "mod" | "type" | "union" | "const" | "static" => {
+            let name = ident(after.strip_prefix("mut ").unwrap_or(after));
+            (!name.is_empty()).then(|| Item::new(kind, name))
+        }
+        _ => None,
+    }
+}
+
+/// `<...>` 泛型参数结束后的位置
+fn matching_angle(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn ts_item(line: &str, nested: bool) -> Option<Item> {
+    if nested {
+        // 类方法：`async foo(a, b) {`、`private bar(): void {`
+        let rest = strip_modifiers(
+            line,
+            &[
+                "public",
+                "private",
+                "protected",
+                "static",
+                "async",
+                "readonly",
+                "override",
+                "get",
+                "set",
+            ],
+        );
+        let name = ident(rest);
+        let after = rest[name.len()..].trim_start();
+        let keyword = matches!(
+            name,
+            "" | "if"
+                | "for"
+                | "while"
+                | "switch"
+                | "catch"
+                | "return"
+                | "function"
+                | "constructor"
+        );
+        if !keyword && (after.starts_with('(') || after.starts_with('<')) && line.ends_with('{') {
+            return Some(Item::new("method", name));
+        }
+        if name == "constructor" && after.starts_with('(') {
+            return Some(Item::new("method", "constructor"));
+        }
+        return None;
+    }
+    let rest = strip_modifiers(line, &["export", "default", "declare", "abstract", "async"]);
+    let kind = ident(rest);
+    let after = rest[kind.len()..].trim_start();
+    match kind {
+        "function" => {
+            let name = ident(after.trim_start_matches('*').trim_start());
+            Some(Item::new(
+                "function",
+                if name.is_empty() { "default" } else { name },
+            ))
+        }
+        "class" | "interface" | "enum" | "namespace" => {
+            let name = ident(after);
+            (!name.is_empty()).then(|| Item::new(kind, name))
+        }
+        "type" => {
+            let name = ident(after);
+            (!name.is_empty() && after[name.len()..].trim_start().starts_with(['=', '<']))
+                .then(|| Item::new("type", name))
+        }
+        // 只把函数值的顶层变量当作条目
+        "const" | "let" | "var" => {
+            let name = ident(after);
+            let value = after[name.len()..].split_once('=')?.1;
+            let is_function = value.contains("=>") || value.trim_start().starts_with("function");
+            (!name.is_empty() && is_function).then(|| Item::new("const", name))
+        }
+        _ => None,
+    }
+}
+
+fn python_item(line: &str) -> Option<Item> {
+    let rest = strip_modifiers(line, &["async"]);
+    for kind in ["def", "class"] {
+        if let Some(after) = rest.strip_prefix(kind) {
+            if after.starts_with(char::is_whitespace) {
+                let name = ident(after.trim_start());
+                return (!name.is_empty()).then(|| Item::new(kind, name));
+            }
+        }
+    }
+    None
+}
+
+fn go_item(line: &str) -> Option<Item> {
+    if let Some(after) = line.strip_prefix("func ") {
+        let after = after.trim_start();
+        // 方法：`func (s *Server) Start()` → `func Server.Start`
+        if let Some(receiver) = after.strip_prefix('(') {
+            let close = receiver.find(')')?;
+            let ty = receiver[..close]
+                .split_whitespace()
+                .last()?
+                .trim_start_matches('*');
+            let ty = ty.split('[').next().unwrap_or(ty);
+            let name = ident(receiver[close + 1..].trim_start());
+            return (!name.is_empty()).then(|| Item::new("func", &format!("{ty}.{name}")));
+        }
+        let name = ident(after);
+        return (!name.is_empty()).then(|| Item::new("func", name));
+    }
+    if let Some(after) = line.strip_prefix("type ") {
+        let name = ident(after.trim_start());
+        return (!name.is_empty()).then(|| Item::new("type", name));
+    }
+    None
+}
+
+fn markdown_heading(line: &str) -> Option<Item> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    if !(1..=6).contains(&level) {
+        return None;
+    }
+    let title = line[level..].strip_prefix(' ')?.trim();
+    (!title.is_empty()).then(|| Item::new(&line[..level], title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(chunks: &[Chunk]) -> Vec<Option<&str>> {
+        chunks.iter().map(|c| c.symbol.as_deref()).collect()
+    }
+
+    #[test]
+    fn test_rust_top_level_items_with_doc_comments() {
+        let src = "use std::fmt;\n\n/// Loads config\n#[inline]\npub fn load_config() -> u32 {\n    1\n}\n\npub(crate) struct Config {\n    a: u32,\n}\n\nimpl<T: Clone> fmt::Display for Wrapper<T> {\n}\n";
+        let chunks = chunk_file(Path::new("lib.rs"), src, 512, 2);
+        assert_eq!(
+            symbols(&chunks),
+            vec![
+                None,
+                Some("fn load_config"),
+                Some("struct Config"),
+                Some("impl fmt::Display for Wrapper<T>")
+            ]
+        );
+        assert_eq!(chunks[1].start_line, 3);
+        assert!(chunks[1].content.starts_with("/// Loads config\n#[inline]"));
+    }
+
+    #[test]
+    fn test_large_impl_splits_on_methods() {
+        let body = "        let x = compute_something_long(a, b, c);\n".repeat(8);
+        let src = format!(
+            "impl Server {{\n    pub fn start(&self) {{\n{body}    }}\n\n    /// stop it\n    fn stop(&self) {{\n{body}    }}\n}}\n"
+        );
+        let chunks = chunk_file(Path::new("server.rs"), &src, 120, 2);
+        let names = symbols(&chunks);
+        assert!(names.contains(&Some("fn Server::start")), "{names:?}");
+        assert!(names.contains(&Some("fn Server::stop")), "{names:?}");
+        let stop = chunks
+            .iter()
+            .find(|c| c.symbol.as_deref() == Some("fn Server::stop"))
+            .unwrap();
+        assert!(stop.content.trim_start().starts_with("/// stop it"));
+    }
+
+    #[test]
+    fn test_oversized_item_windows_overlap() {
+        let body: String = (0..40).map(|i| format!("    let v{i} = {i};\n")).collect();
+        let src = format!("fn big() {{\n{body}}}\n");
+        let chunks = chunk_file(Path::new("big.rs"), &src, 60, 3);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.symbol.as_deref() == Some("fn big")));
+        // 相邻窗口重叠 3 行
+        let first_end = chunks[0].start_line + chunks[0].content.lines().count();
+        assert_eq!(chunks[1].start_line, first_end - 3);
+    }
+
+    #[test]
+    fn test_typescript_python_go_markdown() {
+        let ts = "import x from 'y';\n\nexport async function fetchUser(id) {\n  return 1;\n}\n\n@Component()\nexport class UserView {\n}\n\nexport const handler = async (req) => {\n};\n";
+        assert_eq!(
+            symbols(&chunk_file(Path::new("a.ts"), ts, 512, 2)),
+            vec![
+                None,
+                Some("function fetchUser"),
+                Some("class UserView"),
+                Some("const handler")
+            ]
+        );
+
+        let py =
+            "import os\n\n@dataclass\nclass Config:\n    x: int\n\nasync def main():\n    pass\n";
+        assert_eq!(
+            symbols(&chunk_file(Path::new("a.py"), py, 512, 2)),
+            vec![None, Some("class Config"), Some("def main")]
+        );
+
+        let go = "package main\n\n// Start runs\nfunc (s *Server[T]) Start() error {\n}\n\ntype Server struct {\n}\n";
+        assert_eq!(
+            symbols(&chunk_file(Path::new("a.go"), go, 512, 2)),
+            vec![None, Some("func Server.Start"), Some("type Server")]
+        );
+
+        let md = "# Guide\nintro\n\n## Install\n```bash\n# not a heading\n```\n";
+        assert_eq!(
+            symbols(&chunk_file(Path::new("README.md"), md, 512, 2)),
+            vec![Some("# Guide"), Some("## Install")]
+        );
+    }
+
+    #[test]
+    fn test_unknown_language_uses_windows() {
+        let yaml: String = (0..30).map(|i| format!("key{i}: value\n")).collect();
+        let chunks = chunk_file(Path::new("a.yaml"), &yaml, 40, 2);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.symbol.is_none()));
+        assert_eq!(chunks[0].start_line, 1);
+    }
+}
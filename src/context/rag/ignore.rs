@@ -0,0 +1,205 @@
+use std::path::{Path, PathBuf};
+
+use crate::proxy::util::glob_match;
+
+/// `.gitignore` 语法的忽略规则（支持 `!` 取反、`/` 锚定、目录专用的尾部 `/` 与 `**`），
+/// 后出现的规则优先
+#[derive(Debug, Clone, Default)]
+pub(super) struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// 规则所在目录（绝对路径）
+    base: PathBuf,
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(base: &Path, line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (mut anchored, mut pattern) = match line.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (line.contains('/'), line),
+        };
+        if let Some(rest) = pattern.strip_prefix("**/") {
+            anchored = false;
+            pattern = rest;
+        }
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Self {
+            base: base.to_path_buf(),
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    /// `rel` 为相对 `base` 的 `/` 分隔路径
+    fn matches(&self, rel: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let glob = |text: &str| {
+            glob_match(&self.pattern, text)
+                || (self.pattern.contains("/**/")
+                    && glob_match(&self.pattern.replace("/**/", "/"), text))
+        };
+        if self.anchored {
+            return glob(rel);
+        }
+        if !self.pattern.contains('/') {
+            return glob(rel.rsplit('/').next().unwrap_or(rel));
+        }
+        // 未锚定的多级模式（来自 `**/a/b`）：匹配任意层级的后缀
+        std::iter::once(rel)
+            .chain(rel.match_indices('/').map(|(i, _)| &rel[i + 1..]))
+            .any(glob)
+    }
+}
+
+impl IgnoreRules {
+    /// index path 的初始规则：所在 git 仓库中从仓库根到 `root` 的各级 `.gitignore`，
+    /// 再加上配置的排除列表（相对 `root`，优先级最高）
+    pub fn for_root(root: &Path, excludes: &[String]) -> Self {
+        let mut rules = Self::default();
+        let mut ancestors: Vec<&Path> = Vec::new();
+        for dir in root.ancestors() {
+            ancestors.push(dir);
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+        // 不在 git 仓库中时只读取 root 自己的 .gitignore
+        if !ancestors.last().is_some_and(|d| d.join(".git").exists()) {
+            ancestors.truncate(1);
+        }
+        for dir in ancestors.into_iter().rev() {
+            rules.add_gitignore(dir);
+        }
+        rules
+            .rules
+            .extend(excludes.iter().filter_map(|e| Rule::parse(root, e)));
+        rules
+    }
+
+    /// 追加 `dir/.gitignore` 中的规则（不存在时忽略）
+    pub fn add_gitignore(&mut self, dir: &Path) {
+        if let Ok(content) = std::fs::read_to_string(dir.join(".gitignore")) {
+            self.rules
+                .extend(content.lines().filter_map(|line| Rule::parse(dir, line)));
+        }
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            let Ok(rel) = path.strip_prefix(&rule.base) else {
+                continue;
+            };
+            let rel = rel
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if !rel.is_empty() && rule.matches(&rel, is_dir) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// 隐藏目录（`.git`、`.venv` 等）一律跳过
+pub(super) fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'))
+}
+
+/// `path`（位于 `root` 之下）的生效规则：逐级叠加中间目录的 `.gitignore`；
+/// 任一上级目录被排除时返回 None
+pub(super) fn rules_for_path(root: &Path, excludes: &[String], path: &Path) -> Option<IgnoreRules> {
+    let mut rules = IgnoreRules::for_root(root, excludes);
+    let rel = path.strip_prefix(root).ok()?;
+    let mut dir = root.to_path_buf();
+    let components: Vec<_> = rel.components().collect();
+    for component in components.iter().take(components.len().saturating_sub(1)) {
+        dir.push(component);
+        if is_hidden(&dir) || rules.is_ignored(&dir, true) {
+            return None;
+        }
+        rules.add_gitignore(&dir);
+    }
+    Some(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(lines: &[&str]) -> IgnoreRules {
+        IgnoreRules {
+            rules: lines
+                .iter()
+                .filter_map(|l| Rule::parse(Path::new("/repo"), l))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_gitignore_semantics() {
+        let r = rules(&[
+            "# comment",
+            "target/",
+            "*.log",
+            "!keep.log",
+            "/build",
+            "docs/**/*.tmp",
+        ]);
+        assert!(r.is_ignored(Path::new("/repo/target"), true));
+        assert!(r.is_ignored(Path::new("/repo/crates/x/target"), true));
+        assert!(!r.is_ignored(Path::new("/repo/target"), false));
+        assert!(r.is_ignored(Path::new("/repo/a/debug.log"), false));
+        assert!(!r.is_ignored(Path::new("/repo/keep.log"), false));
+        assert!(r.is_ignored(Path::new("/repo/build"), true));
+        assert!(!r.is_ignored(Path::new("/repo/src/build"), true));
+        assert!(r.is_ignored(Path::new("/repo/docs/a.tmp"), false));
+        assert!(r.is_ignored(Path::new("/repo/docs/x/y/a.tmp"), false));
+        assert!(!r.is_ignored(Path::new("/other/debug.log"), false));
+    }
+
+    #[test]
+    fn test_rules_for_path_reads_nested_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("pkg/gen")).unwrap();
+        std::fs::create_dir_all(root.join("node_modules/x")).unwrap();
+        std::fs::write(root.join("pkg/.gitignore"), "gen/\n").unwrap();
+        let excludes = vec!["node_modules/".to_string()];
+
+        let file = root.join("pkg/lib.rs");
+        let r = rules_for_path(root, &excludes, &file).unwrap();
+        assert!(!r.is_ignored(&file, false));
+        assert!(rules_for_path(root, &excludes, &root.join("pkg/gen/a.rs")).is_none());
+        assert!(rules_for_path(root, &excludes, &root.join("node_modules/x/a.js")).is_none());
+    }
+}
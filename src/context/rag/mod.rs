@@ -0,0 +1,1095 @@
+mod bm25;
+mod chunker;
+mod ignore;
+mod store;
+pub mod watcher;
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::{Mutex, RwLock};
+
+use super::{RagConfig, RetrievalMode};
+use bm25::Bm25Index;
+use chunker::Chunk;
+use ignore::IgnoreRules;
+use store::{IndexStore, IndexedFile};
+
+/// 一次请求最多发送的 embedding 文本数
+const EMBEDDING_BATCH: usize = 32;
+
+/// 参与索引的文件扩展名
+const EXTENSIONS: &[&str] = &[
+    "rs", "ts", "tsx", "js", "jsx", "java", "py", "go", "md", "toml", "yaml", "yml",
+];
+
+#[derive(Debug, Clone)]
+pub struct RagIndex {
+    config: RagConfig,
+    /// 每个 index path 一份索引，与磁盘上的持久化文件一致
+    roots: Arc<RwLock<Vec<RootIndex>>>,
+    /// 串行化启动时的全量同步、watcher 触发的局部同步与写盘
+    sync_lock: Arc<Mutex<()>>,
+    /// 按需构建的 BM25 索引，索引内容变化时清空
+    bm25: Arc<std::sync::Mutex<Option<Arc<Bm25Index>>>>,
+}
+
+/// embedding 接口（RAG profile 的 base_url 与 api_key）
+#[derive(Debug, Clone)]
+pub struct EmbeddingEndpoint {
+    pub base_url: String,
+    pub api_key: String,
+}
+
+impl EmbeddingEndpoint {
+    /// 按 `context.rag.profile` 解析；profile 不存在时返回 None（检索回退到 BM25）
+    pub fn resolve(config: &crate::config::ClaudexConfig) -> Option<Self> {
+        let rag = &config.context.rag;
+        super::resolve_profile_endpoint(config, &rag.profile, &rag.model)
+            .map(|(base_url, api_key, _)| Self { base_url, api_key })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RootIndex {
+    /// 规范化后的 index path，用于匹配文件与 watcher 事件
+    root: PathBuf,
+    /// 结果中展示路径的前缀（配置中的写法；单文件时为其所在目录）
+    base: PathBuf,
+    store_path: Option<PathBuf>,
+    store: IndexStore,
+    /// 内存中的索引已变化、尚未写回磁盘
+    dirty: bool,
+}
+
+/// 文件中的一个分块及其 embedding（embedding 不可用时为空，只参与 BM25）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TextChunk {
+    start_line: usize,
+    content: String,
+    /// 分块所属的条目（如 `fn load_config`、`## Install`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    symbol: Option<String>,
+    embedding: Vec<f32>,
+}
+
+/// 一个检索候选的各项分数
+struct Scored<'a> {
+    chunk: &'a TextChunk,
+    path: PathBuf,
+    score: f32,
+    vector: f32,
+    bm25: f32,
+}
+
+/// 一次增量同步的统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    pub unchanged: usize,
+    pub added: usize,
+    pub updated: usize,
+    pub renamed: usize,
+    pub removed: usize,
+    /// 本次新计算 embedding 的 chunk 数
+    pub embedded_chunks: usize,
+}
+
+/// 待计算 embedding 的文件
+struct PendingFile {
+    rel: String,
+    hash: String,
+    chunks: Vec<Chunk>,
+}
+
+/// 同步计划：在不持有写锁的情况下对比 hash 得出，embedding 完成后一次性应用
+#[derive(Default)]
+struct SyncPlan {
+    removals: Vec<String>,
+    /// (旧路径, 新路径)
+    renames: Vec<(String, String)>,
+    pending: Vec<PendingFile>,
+}
+
+impl SyncPlan {
+    fn is_empty(&self) -> bool {
+        self.removals.is_empty() && self.renames.is_empty() && self.pending.is_empty()
+    }
+}
+
+impl RagIndex {
+    pub fn new(config: RagConfig) -> Self {
+        Self {
+            config,
+            roots: Arc::new(RwLock::new(Vec::new())),
+            sync_lock: Arc::new(Mutex::new(())),
+            bm25: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    pub fn config(&self) -> &RagConfig {
+        &self.config
+    }
+
+    /// 从磁盘加载已持久化的索引（不访问 embedding 接口），返回 chunk 数
+    pub async fn load(&self) -> usize {
+        if !self.config.enabled {
+            return 0;
+        }
+        let this = self.clone();
+        let roots = tokio::task::spawn_blocking(move || {
+            this.config
+                .index_paths
+                .iter()
+                .map(|p| this.open_root(Path::new(p)))
+                .collect::<Vec<RootIndex>>()
+        })
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("failed to load RAG index: {e}");
+            Vec::new()
+        });
+        let count = roots.iter().map(|r| r.store.chunk_count()).sum();
+        tracing::info!(chunks = count, "loaded RAG index from disk");
+        let mut guard = self.roots.write().await;
+        *guard = roots;
+        self.invalidate_bm25();
+        count
+    }
+
+    fn open_root(&self, index_path: &Path) -> RootIndex {
+        let root = std::fs::canonicalize(index_path).unwrap_or_else(|_| index_path.to_path_buf());
+        let base = if root.is_file() {
+            index_path.parent().unwrap_or(Path::new("")).to_path_buf()
+        } else {
+            index_path.to_path_buf()
+        };
+        let store_path = self
+            .config
+            .resolve_index_dir()
+            .map(|dir| store::store_path(&dir, &root));
+        let store = match &store_path {
+            Some(path) => IndexStore::load(
+                path,
+                &self.config.model,
+                self.config.chunk_size,
+                self.config.chunk_overlap,
+            ),
+            None => IndexStore::empty(
+                &self.config.model,
+                self.config.chunk_size,
+                self.config.chunk_overlap,
+            ),
+        };
+        RootIndex {
+            root,
+            base,
+            store_path,
+            store,
+            dirty: false,
+        }
+    }
+
+    /// 增量构建索引：内容 hash 未变的文件直接复用，改名的文件按 hash 复用，
+    /// 已删除的文件从索引移除，只为新增与修改的文件计算 embedding。
+    /// 没有 embedding 接口（或 `retrieval = "bm25"`）时只保存分块供 BM25 使用
+    pub async fn build_index(
+        &self,
+        http_client: &reqwest::Client,
+        endpoint: Option<&EmbeddingEndpoint>,
+    ) -> Result<SyncStats> {
+        let stats = self.sync(None, http_client, endpoint).await?;
+        self.persist().await;
+        Ok(stats)
+    }
+
+    /// 只同步发生变化的路径（文件或目录，可已被删除）。
+    /// 只更新内存中的索引，由调用方合并多次同步后调用 [`Self::persist`] 写盘
+    pub async fn update_paths(
+        &self,
+        paths: &[PathBuf],
+        http_client: &reqwest::Client,
+        endpoint: Option<&EmbeddingEndpoint>,
+    ) -> Result<SyncStats> {
+        self.sync(Some(paths), http_client, endpoint).await
+    }
+
+    async fn sync(
+        &self,
+        changed: Option<&[PathBuf]>,
+        http_client: &reqwest::Client,
+        endpoint: Option<&EmbeddingEndpoint>,
+    ) -> Result<SyncStats> {
+        let mut stats = SyncStats::default();
+        if !self.config.enabled {
+            return Ok(stats);
+        }
+        let _guard = self.sync_lock.lock().await;
+        let endpoint = endpoint.filter(|_| self.config.retrieval != RetrievalMode::Bm25);
+
+        if self.roots.read().await.is_empty() {
+            self.load().await;
+        }
+        let root_count = self.roots.read().await.len();
+
+        for i in 0..root_count {
+            let (root, scope, previous) = {
+                let roots = self.roots.read().await;
+                let entry = &roots[i];
+                let scope = match changed {
+                    None => vec![entry.root.clone()],
+                    Some(paths) => paths
+                        .iter()
+                        .filter(|p| p.starts_with(&entry.root))
+                        .cloned()
+                        .collect(),
+                };
+                if scope.is_empty() {
+                    continue;
+                }
+                let previous: BTreeMap<String, (String, bool)> = entry
+                    .store
+                    .files
+                    .iter()
+                    .filter(|(rel, _)| {
+                        scope.iter().any(|p| {
+                            *p == entry.root || in_scope(rel, &relative_key(&entry.root, p))
+                        })
+                    })
+                    .map(|(rel, f)| (rel.clone(), (f.hash.clone(), f.is_embedded())))
+                    .collect();
+                (entry.root.clone(), scope, previous)
+            };
+
+            // 遍历目录、读取与分块都是阻塞操作，放到阻塞线程上
+            let this = self.clone();
+            let embed = endpoint.is_some();
+            let (mut plan, planned) = tokio::task::spawn_blocking(move || {
+                let current = this.scan_scope(&root, &scope);
+                let mut stats = stats;
+                let plan = this.plan(previous, current, embed, &mut stats);
+                (plan, stats)
+            })
+            .await?;
+            stats = planned;
+            let embeddings = match endpoint {
+                Some(endpoint) => {
+                    self.embed_pending(&plan.pending, http_client, endpoint)
+                        .await
+                }
+                None => Vec::new(),
+            };
+            stats.embedded_chunks += embeddings.len();
+
+            if plan.is_empty() {
+                continue;
+            }
+            let mut roots = self.roots.write().await;
+            let entry = &mut roots[i];
+            apply_plan(&mut entry.store, &mut plan, embeddings);
+            entry.dirty = true;
+            self.invalidate_bm25();
+        }
+
+        let chunks: usize = self
+            .roots
+            .read()
+            .await
+            .iter()
+            .map(|r| r.store.chunk_count())
+            .sum();
+        tracing::info!(
+            chunks,
+            unchanged = stats.unchanged,
+            added = stats.added,
+            updated = stats.updated,
+            renamed = stats.renamed,
+            removed = stats.removed,
+            embedded = stats.embedded_chunks,
+            full = changed.is_none(),
+            "synced RAG index"
+        );
+        Ok(stats)
+    }
+
+    /// 把有变化的索引写回磁盘。序列化与写文件在阻塞线程上进行，期间只持有读锁，不阻塞检索
+    pub async fn persist(&self) {
+        let _guard = self.sync_lock.lock().await;
+        let dirty: Vec<usize> = {
+            let mut roots = self.roots.write().await;
+            roots
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, entry)| std::mem::take(&mut entry.dirty).then_some(i))
+                .collect()
+        };
+        if dirty.is_empty() {
+            return;
+        }
+        let roots = self.roots.clone();
+        let saved = tokio::task::spawn_blocking(move || {
+            let roots = roots.blocking_read();
+            for entry in dirty.into_iter().filter_map(|i| roots.get(i)) {
+                let Some(path) = &entry.store_path else {
+                    continue;
+                };
+                if let Err(e) = entry.store.save(path) {
+                    tracing::warn!(path = %path.display(), "failed to save RAG index: {e}");
+                }
+            }
+        })
+        .await;
+        if let Err(e) = saved {
+            tracing::warn!("failed to save RAG index: {e}");
+        }
+    }
+
+    /// 列出范围内当前可索引的文件（相对 key → 路径）
+    fn scan_scope(&self, root: &Path, scope: &[PathBuf]) -> BTreeMap<String, PathBuf> {
+        let mut files = Vec::new();
+        for path in scope {
+            if path == root {
+                if root.is_dir() {
+                    let mut rules = IgnoreRules::for_root(root, &self.config.exclude);
+                    rules.add_gitignore(root);
+                    collect_files(root, &rules, &mut files);
+                } else if root.is_file() {
+                    files.push(root.to_path_buf());
+                }
+                continue;
+            }
+            let Some(mut rules) = ignore::rules_for_path(root, &self.config.exclude, path) else {
+                continue;
+            };
+            if path.is_dir() {
+                if !ignore::is_hidden(path) && !rules.is_ignored(path, true) {
+                    rules.add_gitignore(path);
+                    collect_files(path, &rules, &mut files);
+                }
+            } else if path.is_file() && is_indexable(path) && !rules.is_ignored(path, false) {
+                files.push(path.clone());
+            }
+        }
+        files
+            .into_iter()
+            .map(|file| (relative_key(root, &file), file))
+            .collect()
+    }
+
+    /// 对比范围内旧文件的 hash 与当前文件，得出复用、改名、删除与待 embedding 的文件。
+    /// `embed` 为真时，之前未能计算 embedding 的文件也重新处理
+    fn plan(
+        &self,
+        mut previous: BTreeMap<String, (String, bool)>,
+        current: BTreeMap<String, PathBuf>,
+        embed: bool,
+        stats: &mut SyncStats,
+    ) -> SyncPlan {
+        let mut plan = SyncPlan::default();
+        // 本次已不存在的旧文件：可能被删除，也可能被改名
+        let mut vanished_by_hash: HashMap<String, String> = previous
+            .iter()
+            .filter(|(rel, _)| !current.contains_key(*rel))
+            .map(|(rel, (hash, _))| (hash.clone(), rel.clone()))
+            .collect();
+
+        for (rel, file) in current {
+            let content = match std::fs::read_to_string(&file) {
+                Ok(content) => content,
+                Err(e) => {
+                    tracing::debug!(path = %file.display(), "skipping unreadable file: {e}");
+                    continue;
+                }
+            };
+            let hash = store::content_hash(content.as_bytes());
+            match previous.remove(&rel) {
+                Some((old, embedded)) if old == hash && (embedded || !embed) => {
+                    stats.unchanged += 1;
+                    continue;
+                }
+                Some(_) => {
+                    // 修改前的分块已过期；embedding 失败时该文件留待下次同步
+                    stats.updated += 1;
+                    plan.removals.push(rel.clone());
+                }
+                None => {
+                    if let Some(old) = vanished_by_hash.remove(&hash) {
+                        if previous.remove(&old).is_some() {
+                            stats.renamed += 1;
+                            plan.renames.push((old, rel));
+                            continue;
+                        }
+                    }
+                    stats.added += 1;
+                }
+            }
+            plan.pending.push(PendingFile {
+                rel,
+                hash,
+                chunks: self.chunk_text(&file, &content),
+            });
+        }
+
+        stats.removed += previous.len();
+        plan.removals.extend(previous.into_keys());
+        plan
+    }
+
+    /// 按批计算 embedding；失败或数量不符时停止，返回已成功的部分
+    async fn embed_pending(
+        &self,
+        pending: &[PendingFile],
+        http_client: &reqwest::Client,
+        endpoint: &EmbeddingEndpoint,
+    ) -> Vec<Vec<f32>> {
+        let texts: Vec<&str> = pending
+            .iter()
+            .flat_map(|f| f.chunks.iter().map(|c| c.content.as_str()))
+            .collect();
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(EMBEDDING_BATCH) {
+            match compute_embeddings(
+                batch,
+                http_client,
+                &endpoint.base_url,
+                &endpoint.api_key,
+                &self.config.model,
+            )
+            .await
+            {
+                // 返回数量不符时无法对应到 chunk，剩余分块留待下次同步
+                Ok(batch_embeddings) if batch_embeddings.len() == batch.len() => {
+                    embeddings.extend(batch_embeddings)
+                }
+                Ok(batch_embeddings) => {
+                    tracing::warn!(
+                        chunks = batch.len(),
+                        embeddings = batch_embeddings.len(),
+                        "chunk/embedding count mismatch, deferring remaining files"
+                    );
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!("failed to compute embeddings: {e}");
+                    break;
+                }
+            }
+        }
+        embeddings
+    }
+
+    /// 检索与查询最相关的 chunk。按 `retrieval` 使用向量、BM25 或二者加权融合；
+    /// 没有 embedding 接口、查询 embedding 失败或索引中没有向量时回退到 BM25
+    pub async fn search(
+        &self,
+        query: &str,
+        http_client: &reqwest::Client,
+        endpoint: Option<&EmbeddingEndpoint>,
+    ) -> Result<Vec<String>> {
+        if !self.config.enabled {
+            return Ok(Vec::new());
+        }
+
+        let requested = self.config.retrieval;
+        let query_vec = match endpoint {
+            Some(endpoint) if requested != RetrievalMode::Bm25 => {
+                match compute_embeddings(
+                    &[query],
+                    http_client,
+                    &endpoint.base_url,
+                    &endpoint.api_key,
+                    &self.config.model,
+                )
+                .await
+                {
+                    Ok(mut embeddings) if !embeddings.is_empty() => Some(embeddings.swap_remove(0)),
+                    Ok(_) => None,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "query embedding failed, falling back to BM25");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let roots = self.roots.read().await;
+        let chunks: Vec<(&Path, &str, &TextChunk)> = roots
+            .iter()
+            .flat_map(|r| {
+                r.store.files.iter().flat_map(move |(rel, file)| {
+                    file.chunks
+                        .iter()
+                        .map(move |chunk| (r.base.as_path(), rel.as_str(), chunk))
+                })
+            })
+            .collect();
+        if chunks.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let has_vectors = chunks.iter().any(|(_, _, c)| !c.embedding.is_empty());
+        let (mode, query_vec) = match query_vec {
+            Some(v) if has_vectors => (requested, Some(v)),
+            _ => (RetrievalMode::Bm25, None),
+        };
+        let bm25_scores = match mode {
+            RetrievalMode::Embedding => Vec::new(),
+            _ => self
+                .bm25_index(chunks.iter().map(|(_, _, c)| c.content.as_str()))
+                .scores(query),
+        };
+        let max_bm25 = bm25_scores.iter().copied().fold(0.0f32, f32::max);
+        let weight = self.config.hybrid_weight.clamp(0.0, 1.0);
+
+        let mut scored: Vec<Scored> = chunks
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (base, rel, chunk))| {
+                let vector = query_vec
+                    .as_ref()
+                    .map_or(0.0, |q| cosine_similarity(q, &chunk.embedding));
+                let bm25 = bm25_scores.get(i).copied().unwrap_or(0.0);
+                let (score, relevant) = match mode {
+                    RetrievalMode::Embedding => (vector, vector > 0.3),
+                    RetrievalMode::Bm25 => (bm25, bm25 > 0.0),
+                    RetrievalMode::Hybrid => {
+                        let lexical = if max_bm25 > 0.0 { bm25 / max_bm25 } else { 0.0 };
+                        (
+                            weight * vector.max(0.0) + (1.0 - weight) * lexical,
+                            vector > 0.3 || bm25 > 0.0,
+                        )
+                    }
+                };
+                relevant.then(|| Scored {
+                    chunk,
+                    path: base.join(rel),
+                    score,
+                    vector,
+                    bm25,
+                })
+            })
+            .collect();
+
+        scored.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        scored.truncate(self.config.top_k);
+
+        tracing::info!(
+            mode = %mode,
+            requested = %requested,
+            candidates = chunks.len(),
+            results = scored.len(),
+            scores = ?scored.iter().map(|s| s.score).collect::<Vec<_>>(),
+            "RAG retrieval"
+        );
+        for s in &scored {
+            tracing::debug!(
+                file = %s.path.display(),
+                line = s.chunk.start_line,
+                score = s.score,
+                vector = s.vector,
+                bm25 = s.bm25,
+                "RAG result"
+            );
+        }
+
+        Ok(scored
+            .iter()
+            .map(|s| {
+                let symbol = s
+                    .chunk
+                    .symbol
+                    .as_ref()
+                    .map(|symbol| format!(" ({symbol})"))
+                    .unwrap_or_default();
+                format!(
+                    "// File: {}:{}{}\n{}",
+                    s.path.display(),
+                    s.chunk.start_line,
+                    symbol,
+                    s.chunk.content
+                )
+            })
+            .collect())
+    }
+
+    /// 当前索引的 BM25（首次检索时构建）
+    fn bm25_index<'a>(&self, docs: impl ExactSizeIterator<Item = &'a str>) -> Arc<Bm25Index> {
+        let mut cached = self.bm25.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(index) = cached.as_ref().filter(|i| i.len() == docs.len()) {
+            return index.clone();
+        }
+        let index = Arc::new(Bm25Index::build(docs));
+        *cached = Some(index.clone());
+        index
+    }
+
+    fn invalidate_bm25(&self) {
+        *self.bm25.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+
+    /// 按语法边界分块（见 [`chunker::chunk_file`]）
+    fn chunk_text(&self, path: &Path, content: &str) -> Vec<Chunk> {
+        chunker::chunk_file(
+            path,
+            content,
+            self.config.chunk_size,
+            self.config.chunk_overlap,
+        )
+    }
+}
+
+/// 递归收集可索引文件：跳过隐藏目录，遵循 `.gitignore` 与配置的排除列表
+fn collect_files(dir: &Path, rules: &IgnoreRules, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::debug!(path = %dir.display(), "failed to read directory: {e}");
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if ignore::is_hidden(&path) || rules.is_ignored(&path, true) {
+                continue;
+            }
+            let mut nested = rules.clone();
+            nested.add_gitignore(&path);
+            collect_files(&path, &nested, files);
+        } else if path.is_file() && is_indexable(&path) && !rules.is_ignored(&path, false) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_indexable(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| EXTENSIONS.contains(&ext))
+}
+
+/// 把同步计划应用到索引：embedding 按顺序分配给待处理文件的分块，不足的分块保持为空
+fn apply_plan(store: &mut IndexStore, plan: &mut SyncPlan, embeddings: Vec<Vec<f32>>) {
+    for rel in &plan.removals {
+        store.files.remove(rel);
+    }
+    for (old, new) in &plan.renames {
+        if let Some(entry) = store.files.remove(old) {
+            store.files.insert(new.clone(), entry);
+        }
+    }
+    let mut embeddings = embeddings.into_iter();
+    for file in std::mem::take(&mut plan.pending) {
+        let chunks = file
+            .chunks
+            .into_iter()
+            .map(|chunk| TextChunk {
+                start_line: chunk.start_line,
+                content: chunk.content,
+                symbol: chunk.symbol,
+                embedding: embeddings.next().unwrap_or_default(),
+            })
+            .collect();
+        store.files.insert(
+            file.rel,
+            IndexedFile {
+                hash: file.hash,
+                chunks,
+            },
+        );
+    }
+}
+
+/// 索引中的文件 key：目录下为相对路径（`/` 分隔），单文件为文件名
+fn relative_key(root: &Path, file: &Path) -> String {
+    let rel = file
+        .strip_prefix(root)
+        .ok()
+        .filter(|rel| !rel.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new(file.file_name().unwrap_or(file.as_os_str())));
+    rel.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `rel` 是否是 `scope`（相对 key）本身或位于其下
+fn in_scope(rel: &str, scope: &str) -> bool {
+    rel == scope
+        || rel
+            .strip_prefix(scope)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+async fn compute_embeddings(
+    texts: &[&str],
+    http_client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+    model: &str,
+) -> Result<Vec<Vec<f32>>> {
+    let url = format!("{}/embeddings", base_url.trim_end_matches('/'));
+
+    let body = json!({
+        "model": model,
+        "input": texts,
+    });
+
+    let mut req = http_client.post(&url).json(&body);
+    if !api_key.is_empty() {
+        req = req.header("Authorization", format!("Bearer {api_key}"));
+    }
+
+    let resp: Value = req.send().await?.json().await?;
+
+    let embeddings = resp
+        .get("data")
+        .and_then(|d| d.as_array())
+        .map(|data| {
+            data.iter()
+                .filter_map(|item| {
+                    item.get("embedding").and_then(|e| e.as_array()).map(|arr| {
+                        arr.iter()
+                            .filter_map(|v| v.as_f64().map(|f| f as f32))
+                            .collect()
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    Ok(embeddings)
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+
+    dot / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_similarity_identical() {
+        let a = vec![1.0, 2.0, 3.0];
+        let result = cosine_similarity(&a, &a);
+        assert!((result - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cosine_similarity_orthogonal() {
+        let a = vec![1.0, 0.0];
+        let b = vec![0.0, 1.0];
+        let result = cosine_similarity(&a, &b);
+        assert!(result.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cosine_similarity_opposite() {
+        let a = vec![1.0, 0.0];
+        let b = vec![-1.0, 0.0];
+        let result = cosine_similarity(&a, &b);
+        assert!((result + 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_cosine_similarity_empty() {
+        let result = cosine_similarity(&[], &[]);
+        assert_eq!(result, 0.0);
+    }
+
+    #[test]
+    fn test_cosine_similarity_zero_vector() {
+        let a = vec![0.0, 0.0, 0.0];
+        let b = vec![1.0, 2.0, 3.0];
+        assert_eq!(cosine_similarity(&a, &b), 0.0);
+    }
+
+    #[test]
+    fn test_cosine_similarity_different_lengths() {
+        let a = vec![1.0, 2.0];
+        let b = vec![1.0, 2.0, 3.0];
+        assert_eq!(cosine_similarity(&a, &b), 0.0);
+    }
+
+    /// 为每个输入返回一个 embedding 的 mock 接口
+    struct EmbeddingResponder;
+
+    impl wiremock::Respond for EmbeddingResponder {
+        fn respond(&self, request: &wiremock::Request) -> wiremock::ResponseTemplate {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let count = body["input"].as_array().map_or(0, |a| a.len());
+            let data: Vec<Value> = (0..count)
+                .map(|i| json!({"index": i, "embedding": [1.0, i as f32]}))
+                .collect();
+            wiremock::ResponseTemplate::new(200).set_body_json(json!({"data": data}))
+        }
+    }
+
+    fn endpoint(server: &wiremock::MockServer) -> EmbeddingEndpoint {
+        EmbeddingEndpoint {
+            base_url: server.uri(),
+            api_key: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_relative_key() {
+        let root = Path::new("./src");
+        assert_eq!(
+            relative_key(root, Path::new("./src/proxy/mod.rs")),
+            "proxy/mod.rs"
+        );
+        assert_eq!(relative_key(Path::new("a.md"), Path::new("a.md")), "a.md");
+    }
+
+    #[tokio::test]
+    async fn test_incremental_build_skips_unchanged_and_tracks_renames() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .respond_with(EmbeddingResponder)
+            .mount(&server)
+            .await;
+
+        let src = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        std::fs::write(src.path().join("a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(src.path().join("b.md"), "# B\n").unwrap();
+        std::fs::create_dir(src.path().join("target")).unwrap();
+        std::fs::write(src.path().join("target/skip.rs"), "fn skip() {}\n").unwrap();
+
+        let config = RagConfig {
+            enabled: true,
+            index_paths: vec![src.path().to_string_lossy().into_owned()],
+            model: "embed".into(),
+            index_dir: Some(cache.path().to_path_buf()),
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let build = |index: RagIndex| {
+            let client = client.clone();
+            let uri = server.uri();
+            async move {
+                let endpoint = EmbeddingEndpoint {
+                    base_url: uri,
+                    api_key: String::new(),
+                };
+                index.build_index(&client, Some(&endpoint)).await.unwrap()
+            }
+        };
+
+        let first = build(RagIndex::new(config.clone())).await;
+        assert_eq!((first.added, first.embedded_chunks), (2, 2));
+
+        let second = build(RagIndex::new(config.clone())).await;
+        assert_eq!((second.unchanged, second.embedded_chunks), (2, 0));
+
+        std::fs::rename(src.path().join("a.rs"), src.path().join("c.rs")).unwrap();
+        std::fs::remove_file(src.path().join("b.md")).unwrap();
+        std::fs::write(src.path().join("d.go"), "package d\n").unwrap();
+        let third = build(RagIndex::new(config.clone())).await;
+        assert_eq!(
+            third,
+            SyncStats {
+                added: 1,
+                renamed: 1,
+                removed: 1,
+                embedded_chunks: 1,
+                ..Default::default()
+            }
+        );
+
+        // 新进程直接从磁盘加载，无需 embedding 接口
+        let loaded = RagIndex::new(config);
+        assert_eq!(loaded.load().await, 2);
+        let files: Vec<String> = loaded.roots.read().await[0]
+            .store
+            .files
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(files, vec!["c.rs", "d.go"]);
+    }
+
+    #[tokio::test]
+    async fn test_update_paths_respects_gitignore_and_excludes() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer};
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .respond_with(EmbeddingResponder)
+            .mount(&server)
+            .await;
+
+        let src = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(src.path()).unwrap();
+        std::fs::write(root.join(".gitignore"), "generated/\n").unwrap();
+        for dir in ["lib", "generated", "node_modules/pkg", "vendor"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::fs::write(root.join("lib/a.rs"), "fn a() {}\n").unwrap();
+        std::fs::write(root.join("generated/g.rs"), "fn g() {}\n").unwrap();
+        std::fs::write(root.join("node_modules/pkg/index.js"), "x\n").unwrap();
+        std::fs::write(root.join("vendor/v.go"), "package v\n").unwrap();
+
+        let config = RagConfig {
+            enabled: true,
+            index_paths: vec![root.to_string_lossy().into_owned()],
+            model: "embed".into(),
+            index_dir: None,
+            exclude: vec!["node_modules/".into(), "vendor/".into()],
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let index = RagIndex::new(config);
+        let endpoint = endpoint(&server);
+        let stats = index.build_index(&client, Some(&endpoint)).await.unwrap();
+        assert_eq!(stats.added, 1);
+
+        // 修改一个文件、新增一个文件、在忽略目录中新增文件
+        std::fs::write(root.join("lib/a.rs"), "fn a() { 1 }\n").unwrap();
+        std::fs::write(root.join("lib/b.rs"), "fn b() {}\n").unwrap();
+        std::fs::write(root.join("generated/h.rs"), "fn h() {}\n").unwrap();
+        let changed = [
+            root.join("lib/a.rs"),
+            root.join("lib/b.rs"),
+            root.join("generated/h.rs"),
+        ];
+        let stats = index
+            .update_paths(&changed, &client, Some(&endpoint))
+            .await
+            .unwrap();
+        assert_eq!(
+            (stats.updated, stats.added, stats.embedded_chunks),
+            (1, 1, 2)
+        );
+
+        // 删除整个目录
+        std::fs::remove_dir_all(root.join("lib")).unwrap();
+        let stats = index
+            .update_paths(&[root.join("lib")], &client, Some(&endpoint))
+            .await
+            .unwrap();
+        assert_eq!(stats.removed, 2);
+        assert!(index.roots.read().await[0].store.files.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_update_paths_defers_save_until_persist() {
+        let src = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(src.path()).unwrap();
+        std::fs::write(root.join("a.rs"), "fn a() {}\n").unwrap();
+
+        let config = RagConfig {
+            enabled: true,
+            index_paths: vec![root.to_string_lossy().into_owned()],
+            model: "embed".into(),
+            index_dir: Some(cache.path().to_path_buf()),
+            retrieval: RetrievalMode::Bm25,
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let index = RagIndex::new(config.clone());
+        index.build_index(&client, None).await.unwrap();
+        let files_on_disk = || async {
+            let loaded = RagIndex::new(config.clone());
+            loaded.load().await;
+            let files: Vec<String> = loaded.roots.read().await[0]
+                .store
+                .files
+                .keys()
+                .cloned()
+                .collect();
+            files
+        };
+        assert_eq!(files_on_disk().await, vec!["a.rs"]);
+
+        // watcher 触发的同步只更新内存，合并后由 persist 统一写盘
+        std::fs::write(root.join("b.rs"), "fn b() {}\n").unwrap();
+        index
+            .update_paths(&[root.join("b.rs")], &client, None)
+            .await
+            .unwrap();
+        assert_eq!(index.roots.read().await[0].store.files.len(), 2);
+        assert_eq!(files_on_disk().await, vec!["a.rs"]);
+
+        index.persist().await;
+        assert_eq!(files_on_disk().await, vec!["a.rs", "b.rs"]);
+        assert!(!index.roots.read().await[0].dirty);
+    }
+
+    #[tokio::test]
+    async fn test_search_falls_back_to_bm25_then_fuses_scores() {
+        use wiremock::matchers::{method, path};
+        use wiremock::{Mock, MockServer};
+
+        let src = tempfile::tempdir().unwrap();
+        std::fs::write(src.path().join("a.rs"), "fn load_config() {}\n").unwrap();
+        std::fs::write(src.path().join("b.rs"), "fn render_dashboard() {}\n").unwrap();
+        let config = RagConfig {
+            enabled: true,
+            index_paths: vec![src.path().to_string_lossy().into_owned()],
+            model: "embed".into(),
+            index_dir: None,
+            ..Default::default()
+        };
+        let client = reqwest::Client::new();
+        let index = RagIndex::new(config);
+
+        // 没有 embedding 接口：仍然建立分块，检索走 BM25
+        let stats = index.build_index(&client, None).await.unwrap();
+        assert_eq!((stats.added, stats.embedded_chunks), (2, 0));
+        let results = index
+            .search("where is config loaded", &client, None)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].starts_with("// File: "));
+        assert!(results[0].contains("a.rs:1 (fn load_config)\n"));
+
+        // embedding 恢复后补算向量，hybrid 融合两种分数
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/embeddings"))
+            .respond_with(EmbeddingResponder)
+            .mount(&server)
+            .await;
+        let endpoint = endpoint(&server);
+        let stats = index.build_index(&client, Some(&endpoint)).await.unwrap();
+        assert_eq!((stats.updated, stats.embedded_chunks), (2, 2));
+        let results = index
+            .search("where is config loaded", &client, Some(&endpoint))
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].contains("load_config"));
+        assert!(results[1].contains("render_dashboard"));
+    }
+}
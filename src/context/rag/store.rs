@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::TextChunk;
+
+/// 磁盘格式版本，不兼容变更时递增以触发重建
+const STORE_VERSION: u32 = 2;
+
+/// 单个 index path 的持久化索引：按相对路径记录文件内容 hash、分块与 embedding
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct IndexStore {
+    version: u32,
+    model: String,
+    chunk_size: usize,
+    #[serde(default)]
+    chunk_overlap: usize,
+    #[serde(default)]
+    pub files: BTreeMap<String, IndexedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct IndexedFile {
+    pub hash: String,
+    pub chunks: Vec<TextChunk>,
+}
+
+impl IndexedFile {
+    /// 所有分块都已有 embedding
+    pub fn is_embedded(&self) -> bool {
+        self.chunks.iter().all(|c| !c.embedding.is_empty())
+    }
+}
+
+impl IndexStore {
+    pub fn empty(model: &str, chunk_size: usize, chunk_overlap: usize) -> Self {
+        Self {
+            version: STORE_VERSION,
+            model: model.to_string(),
+            chunk_size,
+            chunk_overlap,
+            files: BTreeMap::new(),
+        }
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.files.values().map(|f| f.chunks.len()).sum()
+    }
+
+    /// 读取索引文件；不存在、损坏或 embedding 模型 / 分块参数变化时返回空索引
+    pub fn load(path: &Path, model: &str, chunk_size: usize, chunk_overlap: usize) -> Self {
+        let stored = std::fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<IndexStore>(&bytes).ok());
+        match stored {
+            Some(store)
+                if store.version == STORE_VERSION
+                    && store.model == model
+                    && store.chunk_size == chunk_size
+                    && store.chunk_overlap == chunk_overlap =>
+            {
+                store
+            }
+            Some(_) => {
+                tracing::info!(path = %path.display(), "RAG index settings changed, rebuilding");
+                Self::empty(model, chunk_size, chunk_overlap)
+            }
+            None => Self::empty(model, chunk_size, chunk_overlap),
+        }
+    }
+
+    /// 先写临时文件再 rename，避免中断时留下半个索引
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+/// 索引文件路径：`{dir}/{hash(规范化 index path)}.json`
+pub(super) fn store_path(dir: &Path, index_path: &Path) -> PathBuf {
+    let canonical = std::fs::canonicalize(index_path).unwrap_or_else(|_| index_path.to_path_buf());
+    let digest = content_hash(canonical.to_string_lossy().as_bytes());
+    dir.join(format!("{}.json", &digest[..16]))
+}
+
+pub(super) fn content_hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_discards_mismatched_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("index.json");
+        let mut store = IndexStore::empty("nomic-embed-text", 512, 3);
+        store.files.insert(
+            "a.rs".into(),
+            IndexedFile {
+                hash: content_hash(b"fn a() {}"),
+                chunks: Vec::new(),
+            },
+        );
+        store.save(&path).unwrap();
+
+        assert_eq!(
+            IndexStore::load(&path, "nomic-embed-text", 512, 3)
+                .files
+                .len(),
+            1
+        );
+        assert!(IndexStore::load(&path, "other-model", 512, 3)
+            .files
+            .is_empty());
+        assert!(IndexStore::load(&path, "nomic-embed-text", 256, 3)
+            .files
+            .is_empty());
+        assert!(IndexStore::load(&path, "nomic-embed-text", 512, 0)
+            .files
+            .is_empty());
+        assert!(
+            IndexStore::load(&dir.path().join("missing.json"), "m", 1, 0)
+                .files
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use super::{EmbeddingEndpoint, RagIndex};
+
+/// 同步后延迟写盘：期间的多次同步合并为一次保存
+const SAVE_DELAY: Duration = Duration::from_secs(30);
+
+/// 监听 index_paths 的文件变化，防抖后在后台只同步变化的文件。
+/// 返回的 future 在 watcher 存活期间一直运行
+pub async fn watch(
+    index: RagIndex,
+    http_client: reqwest::Client,
+    endpoint: Option<EmbeddingEndpoint>,
+) -> Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel::<PathBuf>();
+    let mut watcher =
+        notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("RAG file watcher error: {e}"),
+        })?;
+
+    for index_path in &index.config().index_paths {
+        let path = std::fs::canonicalize(index_path).unwrap_or_else(|_| index_path.into());
+        if let Err(e) = watcher.watch(&path, RecursiveMode::Recursive) {
+            tracing::warn!(path = %path.display(), "failed to watch RAG index path: {e}");
+        }
+    }
+    tracing::info!(
+        paths = index.config().index_paths.len(),
+        "watching RAG index paths"
+    );
+
+    let debounce = Duration::from_millis(index.config().watch_debounce_ms);
+    let mut save_at: Option<Instant> = None;
+    loop {
+        let first = tokio::select! {
+            path = rx.recv() => match path {
+                Some(path) => path,
+                None => break,
+            },
+            _ = tokio::time::sleep_until(save_at.unwrap_or_else(Instant::now)), if save_at.is_some() => {
+                save_at = None;
+                index.persist().await;
+                continue;
+            }
+        };
+        let mut changed = BTreeSet::from([normalize(first)]);
+        // 连续保存会产生一串事件：直到安静 `debounce` 后才同步
+        while let Ok(Some(path)) = tokio::time::timeout(debounce, rx.recv()).await {
+            changed.insert(normalize(path));
+        }
+        let paths: Vec<PathBuf> = changed.into_iter().collect();
+        tracing::debug!(paths = paths.len(), "RAG index paths changed");
+        if let Err(e) = index
+            .update_paths(&paths, &http_client, endpoint.as_ref())
+            .await
+        {
+            tracing::warn!("failed to update RAG index: {e}");
+        }
+        save_at.get_or_insert_with(|| Instant::now() + SAVE_DELAY);
+    }
+    index.persist().await;
+    drop(watcher);
+    Ok(())
+}
+
+/// `.gitignore` 变化时重新同步其所在目录
+fn normalize(path: PathBuf) -> PathBuf {
+    if path.file_name().is_some_and(|n| n == ".gitignore") {
+        if let Some(parent) = path.parent() {
+            return parent.to_path_buf();
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_gitignore_change_rescans_directory() {
+        assert_eq!(
+            normalize(PathBuf::from("/repo/src/.gitignore")),
+            Path::new("/repo/src")
+        );
+        assert_eq!(
+            normalize(PathBuf::from("/repo/src/lib.rs")),
+            Path::new("/repo/src/lib.rs")
+        );
+    }
+}
@@ -6,7 +6,7 @@ use tokio::sync::RwLock;
 
 use super::SharingConfig;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct SharedContext {
     inner: Arc<RwLock<HashMap<String, Vec<ContextEntry>>>>,
 }
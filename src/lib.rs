@@ -0,0 +1,15 @@
+#![allow(dead_code)]
+
+pub mod cli;
+pub mod config;
+pub mod context;
+pub mod oauth;
+pub mod process;
+pub mod proxy;
+pub mod router;
+pub mod sets;
+pub mod terminal;
+pub mod tokenizer;
+pub mod tui;
+pub mod update;
+pub mod util;
@@ -1,26 +1,12 @@
-#![allow(dead_code)]
-
-mod cli;
-mod config;
-mod context;
-mod oauth;
-mod process;
-mod proxy;
-mod router;
-mod sets;
-mod terminal;
-mod tui;
-mod update;
-mod util;
-
 use anyhow::Result;
 use clap::Parser;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
-use cli::{AuthAction, Cli, Commands, ProfileAction, ProxyAction, SetsAction};
-use config::ClaudexConfig;
+use claudex::cli::{AuthAction, Cli, Commands, ProfileAction, ProxyAction, SetsAction};
+use claudex::config::ClaudexConfig;
+use claudex::{config, oauth, process, proxy, sets, tui, update};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -79,10 +65,14 @@ async fn main() -> Result<()> {
                 tokio::time::sleep(std::time::Duration::from_millis(500)).await;
             }
 
-            let profile = config
-                .find_profile(&profile_name)
-                .ok_or_else(|| anyhow::anyhow!("profile '{}' not found", profile_name))?
-                .clone();
+            // 虚拟 pool 也可作为 run 目标，由 proxy 负责分发
+            let profile = match config.find_profile(&profile_name) {
+                Some(p) => p.clone(),
+                None => config
+                    .find_pool(&profile_name)
+                    .and_then(|pool| pool.launch_profile(&config))
+                    .ok_or_else(|| anyhow::anyhow!("profile '{}' not found", profile_name))?,
+            };
 
             process::launch::launch_claude(&config, &profile, model.as_deref(), &args, hyperlinks)?;
 
@@ -128,6 +118,11 @@ async fn main() -> Result<()> {
             }
             ProxyAction::Status => {
                 process::daemon::proxy_status()?;
+                if process::daemon::is_proxy_running()? {
+                    if let Err(e) = proxy::health::print_health_report(&config).await {
+                        eprintln!("Failed to fetch profile health: {e}");
+                    }
+                }
             }
         },
 
@@ -176,6 +171,23 @@ async fn main() -> Result<()> {
             }
         },
 
+        Some(Commands::Cost {
+            from,
+            to,
+            profile,
+            json,
+        }) => {
+            proxy::usage::print_cost_report(
+                from.as_deref(),
+                to.as_deref(),
+                profile.as_deref(),
+                json,
+            )?;
+        }
+        Some(Commands::Replay { capture, quiet }) => {
+            proxy::capture::run_replay(&capture, quiet).await?;
+        }
+
         Some(Commands::Auth { action }) => match action {
             AuthAction::Login {
                 provider,
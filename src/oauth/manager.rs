@@ -18,11 +18,15 @@ struct CachedToken {
     cached_at: i64,
 }
 
+/// 自定义凭证加载，替代默认的凭证链（嵌入方或测试注入）
+pub type TokenSource = Arc<dyn Fn(&ProfileConfig) -> Result<OAuthToken> + Send + Sync>;
+
 pub struct TokenManager {
     cache: Arc<Mutex<HashMap<String, CachedToken>>>,
     /// per-profile 刷新锁，防止并发刷新
     refresh_locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
     http_client: reqwest::Client,
+    source: Option<TokenSource>,
 }
 
 impl TokenManager {
@@ -31,9 +35,16 @@ impl TokenManager {
             cache: Arc::new(Mutex::new(HashMap::new())),
             refresh_locks: Arc::new(Mutex::new(HashMap::new())),
             http_client,
+            source: None,
         }
     }
 
+    /// 使用自定义凭证加载代替凭证链（不读取环境变量与凭证文件）
+    pub fn with_source(mut self, source: TokenSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
     /// 获取 token，优先从缓存返回，过期时自动刷新
     pub async fn get_token(&self, profile: &ProfileConfig) -> Result<OAuthToken> {
         if profile.auth_type != AuthType::OAuth {
@@ -104,6 +115,18 @@ impl TokenManager {
         self.get_token(profile).await
     }
 
+    /// 直接写入缓存（外部已获取到 token 时使用，如测试）
+    pub async fn insert(&self, profile_name: &str, token: OAuthToken) {
+        let mut cache = self.cache.lock().await;
+        cache.insert(
+            profile_name.to_string(),
+            CachedToken {
+                token,
+                cached_at: chrono::Utc::now().timestamp_millis(),
+            },
+        );
+    }
+
     /// CLI logout 时清除缓存
     pub async fn invalidate(&self, profile_name: &str) {
         let mut cache = self.cache.lock().await;
@@ -116,6 +139,9 @@ impl TokenManager {
         profile: &ProfileConfig,
         provider: &OAuthProvider,
     ) -> Result<OAuthToken> {
+        if let Some(source) = &self.source {
+            return source(profile);
+        }
         match provider {
             OAuthProvider::Chatgpt | OAuthProvider::Openai => {
                 self.load_chatgpt_token(profile).await
@@ -241,6 +267,7 @@ pub fn apply_token_to_profile(profile: &mut ProfileConfig, token: &OAuthToken) {
 }
 
 #[cfg(test)]
+#[allow(clippy::inconsistent_digit_grouping)]
 mod tests {
     use super::*;
 
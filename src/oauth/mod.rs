@@ -36,6 +36,7 @@ pub enum OAuthProvider {
 }
 
 impl OAuthProvider {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
             "claude" => Some(Self::Claude),
@@ -132,6 +133,7 @@ impl OAuthToken {
 }
 
 #[cfg(test)]
+#[allow(clippy::inconsistent_digit_grouping)]
 mod tests {
     use super::*;
 
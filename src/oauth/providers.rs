@@ -791,6 +791,7 @@ fn open_browser(url: &str) -> Result<()> {
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_get_then_check)]
 mod tests {
     use super::*;
 
@@ -49,12 +49,21 @@ pub fn launch_claude(
             .env("ANTHROPIC_MODEL", &model);
     }
 
-    if !profile.custom_headers.is_empty() {
-        let headers: Vec<String> = profile
-            .custom_headers
-            .iter()
-            .map(|(k, v)| format!("{k}:{v}"))
-            .collect();
+    let mut headers: Vec<String> = profile
+        .custom_headers
+        .iter()
+        .map(|(k, v)| format!("{k}:{v}"))
+        .collect();
+    // 工作目录 header：供 proxy 的路由规则按项目匹配
+    if !is_claude_subscription {
+        if let Ok(cwd) = std::env::current_dir() {
+            let cwd = cwd.display().to_string();
+            if !cwd.contains([',', '\n']) {
+                headers.push(format!("{}:{cwd}", crate::router::rules::CWD_HEADER));
+            }
+        }
+    }
+    if !headers.is_empty() {
         cmd.env("ANTHROPIC_CUSTOM_HEADERS", headers.join(","));
     }
 
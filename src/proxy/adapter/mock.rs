@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use figment::providers::Format;
+use figment::Figment;
+use regex::Regex;
+use reqwest::RequestBuilder;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+
+use super::{ByteStream, ProviderAdapter, TranslatedRequest};
+use crate::config::ProfileConfig;
+use crate::proxy::util::{format_sse, ToolNameMap};
+use crate::proxy::ProxyState;
+
+/// Mock provider：按 fixture 文件返回脚本化的 Anthropic 格式响应，不发起任何网络请求。
+/// 用于 CI 中离线测试 hooks / skills / sets。
+pub struct MockAdapter;
+
+impl ProviderAdapter for MockAdapter {
+    fn endpoint_path(&self) -> &str {
+        "/v1/messages"
+    }
+
+    fn translate_request(
+        &self,
+        body: &Value,
+        _profile: &ProfileConfig,
+    ) -> Result<TranslatedRequest> {
+        Ok(TranslatedRequest {
+            body: body.clone(),
+            tool_name_map: ToolNameMap::new(),
+        })
+    }
+
+    fn apply_auth(&self, builder: RequestBuilder, _profile: &ProfileConfig) -> RequestBuilder {
+        builder
+    }
+
+    fn local_response(
+        &self,
+        state: &ProxyState,
+        body: &Value,
+        profile: &ProfileConfig,
+    ) -> Option<Result<reqwest::Response>> {
+        Some(mock_response(&state.mock, body, profile))
+    }
+
+    fn translate_response(&self, body: &Value, _tool_name_map: &ToolNameMap) -> Result<Value> {
+        Ok(body.clone())
+    }
+
+    fn translate_stream(&self, stream: ByteStream, _tool_name_map: ToolNameMap) -> ByteStream {
+        stream
+    }
+}
+
+/// fixture 文件（TOML / YAML / JSON，按扩展名识别）
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockFixtures {
+    /// 匹配方式：match（按最后一条 user 消息匹配）| sequence（按请求顺序）
+    #[serde(default)]
+    pub mode: MockMode,
+    #[serde(default)]
+    pub responses: Vec<MockResponse>,
+    /// 未匹配 / 序列耗尽时的响应
+    #[serde(default)]
+    pub default: Option<MockResponse>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MockMode {
+    #[default]
+    Match,
+    Sequence,
+}
+
+/// 单条脚本化响应
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockResponse {
+    /// match 模式下匹配最后一条 user 消息的正则（省略表示匹配任意消息），加载 fixture 时编译
+    #[serde(default, rename = "match", deserialize_with = "compile_pattern")]
+    pub pattern: Option<Regex>,
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub tool_use: Vec<MockToolUse>,
+    /// 非 2xx 状态码时返回错误（如 429、500、529），`error` 为错误信息
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub error: Option<String>,
+    /// 模拟连接失败（不返回任何响应）
+    #[serde(default)]
+    pub disconnect: bool,
+    /// 首字节前的延迟（毫秒）
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// 流式事件之间的延迟（毫秒）
+    #[serde(default)]
+    pub chunk_delay_ms: u64,
+    /// 流式 text_delta 每块的字符数
+    #[serde(default = "default_chunk_chars")]
+    pub chunk_chars: usize,
+}
+
+fn default_chunk_chars() -> usize {
+    16
+}
+
+/// 无效正则使整个 fixture 加载失败，`config validate` 与连通性测试即可发现
+fn compile_pattern<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Regex>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|p| {
+            Regex::new(&p).map_err(|e| {
+                serde::de::Error::custom(format!("invalid mock match pattern '{p}': {e}"))
+            })
+        })
+        .transpose()
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MockToolUse {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub input: Value,
+}
+
+impl MockFixtures {
+    pub fn load(path: &Path) -> Result<Self> {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let fixtures = match ext {
+            "json" => serde_json::from_str(
+                &std::fs::read_to_string(path)
+                    .with_context(|| format!("cannot read {}", path.display()))?,
+            )?,
+            "yaml" | "yml" => Figment::from(figment::providers::Yaml::file(path)).extract()?,
+            _ => Figment::from(figment::providers::Toml::file(path)).extract()?,
+        };
+        Ok(fixtures)
+    }
+
+    /// 为一次请求选出响应；sequence 模式下 `next_index` 取出该 profile 的下一个序号
+    fn select(&self, body: &Value, next_index: impl FnOnce() -> usize) -> MockResponse {
+        let picked = match self.mode {
+            MockMode::Match => {
+                let message = last_user_text(body);
+                self.responses
+                    .iter()
+                    .find(|r| r.pattern.as_ref().is_none_or(|p| p.is_match(&message)))
+                    .cloned()
+            }
+            MockMode::Sequence => self.responses.get(next_index()).cloned(),
+        };
+        picked
+            .or_else(|| self.default.clone())
+            .unwrap_or_else(|| MockResponse {
+                text: Some("This is a mock response from claudex.".to_string()),
+                chunk_chars: default_chunk_chars(),
+                ..Default::default()
+            })
+    }
+}
+
+/// Mock provider 的运行时状态（保存在 `ProxyState` 中，随 proxy 实例创建与销毁）：
+/// 每个 profile 已加载的 fixture 与 sequence 模式的请求计数
+#[derive(Debug, Default)]
+pub struct MockState {
+    fixtures: Mutex<HashMap<String, LoadedFixtures>>,
+    sequence: Mutex<HashMap<String, usize>>,
+}
+
+#[derive(Debug)]
+struct LoadedFixtures {
+    path: Option<PathBuf>,
+    fixtures: Arc<MockFixtures>,
+}
+
+impl MockState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// profile 的 fixture：首次使用或配置中的 fixture 路径变化时加载并编译一次
+    fn fixtures(&self, profile: &ProfileConfig) -> Result<Arc<MockFixtures>> {
+        let mut map = self.fixtures.lock().unwrap();
+        if let Some(loaded) = map.get(&profile.name) {
+            if loaded.path == profile.mock_fixtures {
+                return Ok(loaded.fixtures.clone());
+            }
+        }
+        let fixtures = Arc::new(match &profile.mock_fixtures {
+            Some(path) => MockFixtures::load(path)?,
+            None => MockFixtures::default(),
+        });
+        map.insert(
+            profile.name.clone(),
+            LoadedFixtures {
+                path: profile.mock_fixtures.clone(),
+                fixtures: fixtures.clone(),
+            },
+        );
+        Ok(fixtures)
+    }
+
+    fn next_sequence_index(&self, profile: &str) -> usize {
+        let mut map = self.sequence.lock().unwrap();
+        let pos = map.entry(profile.to_string()).or_insert(0);
+        let idx = *pos;
+        *pos += 1;
+        idx
+    }
+}
+
+/// 最后一条 user 消息的文本（含 tool_result 内容，便于脚本化工具调用循环）
+fn last_user_text(body: &Value) -> String {
+    let Some(msg) = body
+        .get("messages")
+        .and_then(|m| m.as_array())
+        .and_then(|m| {
+            m.iter()
+                .rev()
+                .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"))
+        })
+    else {
+        return String::new();
+    };
+    match msg.get("content") {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| match p.get("type").and_then(|t| t.as_str()) {
+                Some("text") => p.get("text").and_then(|t| t.as_str()).map(String::from),
+                Some("tool_result") => match p.get("content") {
+                    Some(Value::String(s)) => Some(s.clone()),
+                    Some(Value::Array(items)) => Some(
+                        items
+                            .iter()
+                            .filter_map(|i| i.get("text").and_then(|t| t.as_str()))
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                    ),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// 按 fixture 生成上游响应
+fn mock_response(
+    mock: &MockState,
+    body: &Value,
+    profile: &ProfileConfig,
+) -> Result<reqwest::Response> {
+    let fixtures = mock.fixtures(profile)?;
+    let reply = fixtures.select(body, || mock.next_sequence_index(&profile.name));
+    if reply.disconnect {
+        anyhow::bail!("mock connection error (disconnect = true)");
+    }
+
+    let streaming = body
+        .get("stream")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let model = body
+        .get("model")
+        .and_then(|m| m.as_str())
+        .unwrap_or(&profile.default_model)
+        .to_string();
+    let input_tokens = crate::tokenizer::count_request_tokens(&model, body);
+
+    let (status, content_type, events) = match reply.status.filter(|s| *s >= 300) {
+        Some(status) => {
+            let message = reply
+                .error
+                .clone()
+                .unwrap_or_else(|| format!("mock error {status}"));
+            (status, "text/plain", vec![message])
+        }
+        None if streaming => (
+            200,
+            "text/event-stream",
+            stream_events(&reply, &model, input_tokens),
+        ),
+        None => (
+            200,
+            "application/json",
+            vec![message_json(&reply, &model, input_tokens).to_string()],
+        ),
+    };
+
+    let delay = Duration::from_millis(reply.delay_ms);
+    let chunk_delay = Duration::from_millis(reply.chunk_delay_ms);
+    let stream = async_stream::stream! {
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        for (i, event) in events.into_iter().enumerate() {
+            if i > 0 && !chunk_delay.is_zero() {
+                tokio::time::sleep(chunk_delay).await;
+            }
+            yield Ok::<_, std::io::Error>(Bytes::from(event));
+        }
+    };
+
+    let response = http::Response::builder()
+        .status(status)
+        .header("content-type", content_type)
+        .body(reqwest::Body::wrap_stream(stream))?;
+    Ok(reqwest::Response::from(response))
+}
+
+fn tool_id(tool: &MockToolUse, idx: usize) -> String {
+    tool.id
+        .clone()
+        .unwrap_or_else(|| format!("toolu_mock_{idx:02}"))
+}
+
+fn stop_reason(reply: &MockResponse) -> &'static str {
+    if reply.tool_use.is_empty() {
+        "end_turn"
+    } else {
+        "tool_use"
+    }
+}
+
+fn output_tokens(reply: &MockResponse, model: &str) -> u64 {
+    let tokenizer = crate::tokenizer::Tokenizer::for_model(model);
+    let text = tokenizer.count_text(reply.text.as_deref().unwrap_or(""));
+    let tools: u64 = reply
+        .tool_use
+        .iter()
+        .map(|t| tokenizer.count_text(&t.input.to_string()))
+        .sum();
+    (text + tools).max(1)
+}
+
+/// 非流式 Anthropic message
+fn message_json(reply: &MockResponse, model: &str, input_tokens: u64) -> Value {
+    let mut content = Vec::new();
+    if let Some(text) = &reply.text {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    for (i, tool) in reply.tool_use.iter().enumerate() {
+        content.push(json!({
+            "type": "tool_use",
+            "id": tool_id(tool, i),
+            "name": tool.name,
+            "input": tool.input,
+        }));
+    }
+    json!({
+        "id": "msg_mock",
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": content,
+        "stop_reason": stop_reason(reply),
+        "stop_sequence": null,
+        "usage": {"input_tokens": input_tokens, "output_tokens": output_tokens(reply, model)}
+    })
+}
+
+/// 流式 Anthropic SSE 事件序列
+fn stream_events(reply: &MockResponse, model: &str, input_tokens: u64) -> Vec<String> {
+    let mut events = vec![format_sse(
+        "message_start",
+        &json!({
+            "type": "message_start",
+            "message": {
+                "id": "msg_mock",
+                "type": "message",
+                "role": "assistant",
+                "model": model,
+                "content": [],
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": {"input_tokens": input_tokens, "output_tokens": 0}
+            }
+        }),
+    )];
+
+    let mut index = 0;
+    if let Some(text) = &reply.text {
+        events.push(format_sse(
+            "content_block_start",
+            &json!({"type": "content_block_start", "index": index, "content_block": {"type": "text", "text": ""}}),
+        ));
+        let chars: Vec<char> = text.chars().collect();
+        for piece in chars.chunks(reply.chunk_chars.max(1)) {
+            let piece: String = piece.iter().collect();
+            events.push(format_sse(
+                "content_block_delta",
+                &json!({"type": "content_block_delta", "index": index, "delta": {"type": "text_delta", "text": piece}}),
+            ));
+        }
+        events.push(format_sse(
+            "content_block_stop",
+            &json!({"type": "content_block_stop", "index": index}),
+        ));
+        index += 1;
+    }
+    for (i, tool) in reply.tool_use.iter().enumerate() {
+        events.push(format_sse(
+            "content_block_start",
+            &json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": {"type": "tool_use", "id": tool_id(tool, i), "name": tool.name, "input": {}}
+            }),
+        ));
+        events.push(format_sse(
+            "content_block_delta",
+            &json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "input_json_delta", "partial_json": tool.input.to_string()}
+            }),
+        ));
+        events.push(format_sse(
+            "content_block_stop",
+            &json!({"type": "content_block_stop", "index": index}),
+        ));
+        index += 1;
+    }
+
+    events.push(format_sse(
+        "message_delta",
+        &json!({
+            "type": "message_delta",
+            "delta": {"stop_reason": stop_reason(reply), "stop_sequence": null},
+            "usage": {"output_tokens": output_tokens(reply, model)}
+        }),
+    ));
+    events.push(format_sse("message_stop", &json!({"type": "message_stop"})));
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderType;
+
+    fn fixtures(toml_src: &str) -> MockFixtures {
+        Figment::from(figment::providers::Toml::string(toml_src))
+            .extract()
+            .unwrap()
+    }
+
+    fn request(text: &str) -> Value {
+        json!({"model": "mock-model", "messages": [{"role": "user", "content": text}]})
+    }
+
+    #[test]
+    fn test_match_by_last_user_message() {
+        let f = fixtures(
+            r#"
+            [[responses]]
+            match = "(?i)list files"
+            tool_use = [{ name = "Bash", input = { command = "ls" } }]
+
+            [[responses]]
+            match = "weather"
+            text = "sunny"
+        "#,
+        );
+        let r = f.select(&request("please list files"), || 0);
+        assert_eq!(r.tool_use[0].name, "Bash");
+        let r = f.select(&request("what's the weather"), || 0);
+        assert_eq!(r.text.as_deref(), Some("sunny"));
+        let r = f.select(&request("something else"), || 0);
+        assert!(r.text.unwrap().contains("mock response"));
+    }
+
+    #[test]
+    fn test_match_tool_result_content() {
+        let f = fixtures(
+            r#"
+            [[responses]]
+            match = "Cargo.toml"
+            text = "found it"
+        "#,
+        );
+        let body = json!({"messages": [
+            {"role": "user", "content": "list"},
+            {"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "Bash", "input": {}}]},
+            {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": "Cargo.toml\nsrc"}]}
+        ]});
+        assert_eq!(f.select(&body, || 0).text.as_deref(), Some("found it"));
+    }
+
+    fn fixture_file(dir: &Path, toml_src: &str) -> PathBuf {
+        let path = dir.join("mock.toml");
+        std::fs::write(&path, toml_src).unwrap();
+        path
+    }
+
+    fn mock_profile(name: &str, fixtures: Option<PathBuf>) -> ProfileConfig {
+        ProfileConfig {
+            name: name.to_string(),
+            provider_type: ProviderType::Mock,
+            mock_fixtures: fixtures,
+            ..Default::default()
+        }
+    }
+
+    fn reply_text(mock: &MockState, profile: &ProfileConfig) -> Option<String> {
+        let fixtures = mock.fixtures(profile).unwrap();
+        fixtures
+            .select(&request("x"), || mock.next_sequence_index(&profile.name))
+            .text
+    }
+
+    #[test]
+    fn test_sequence_mode_then_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture_file(
+            dir.path(),
+            r#"
+            mode = "sequence"
+            default = { text = "done" }
+
+            [[responses]]
+            text = "first"
+
+            [[responses]]
+            text = "second"
+        "#,
+        );
+        let profile = mock_profile("seq", Some(path));
+        let mock = MockState::new();
+        assert_eq!(reply_text(&mock, &profile).as_deref(), Some("first"));
+        assert_eq!(reply_text(&mock, &profile).as_deref(), Some("second"));
+        assert_eq!(reply_text(&mock, &profile).as_deref(), Some("done"));
+
+        // 计数属于各自的 proxy 状态，不在实例之间共享
+        let fresh = MockState::new();
+        assert_eq!(reply_text(&fresh, &profile).as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn test_fixtures_compiled_once_per_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture_file(dir.path(), "[[responses]]\ntext = \"v1\"\n");
+        let mock = MockState::new();
+        let profile = mock_profile("m", Some(path.clone()));
+        assert_eq!(reply_text(&mock, &profile).as_deref(), Some("v1"));
+
+        // 同一路径不再读盘；配置改指向其它 fixture 时重新加载
+        std::fs::write(&path, "[[responses]]\ntext = \"v2\"\n").unwrap();
+        assert_eq!(reply_text(&mock, &profile).as_deref(), Some("v1"));
+        let other = dir.path().join("other.toml");
+        std::fs::write(&other, "[[responses]]\ntext = \"v3\"\n").unwrap();
+        let profile = mock_profile("m", Some(other));
+        assert_eq!(reply_text(&mock, &profile).as_deref(), Some("v3"));
+    }
+
+    #[test]
+    fn test_invalid_pattern_fails_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = fixture_file(dir.path(), "[[responses]]\nmatch = \"(unclosed\"\n");
+        let err = MockFixtures::load(&path).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("invalid mock match pattern"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn test_message_json_with_tool_use() {
+        let reply = MockResponse {
+            text: Some("Let me check.".to_string()),
+            tool_use: vec![MockToolUse {
+                id: None,
+                name: "Read".to_string(),
+                input: json!({"file_path": "a.rs"}),
+            }],
+            ..Default::default()
+        };
+        let msg = message_json(&reply, "m", 10);
+        assert_eq!(msg["stop_reason"], "tool_use");
+        assert_eq!(msg["content"][1]["id"], "toolu_mock_00");
+        assert_eq!(msg["content"][1]["input"]["file_path"], "a.rs");
+    }
+
+    #[test]
+    fn test_stream_events_are_well_formed() {
+        let reply = MockResponse {
+            text: Some("hello world".to_string()),
+            chunk_chars: 5,
+            tool_use: vec![MockToolUse {
+                id: Some("toolu_1".to_string()),
+                name: "Bash".to_string(),
+                input: json!({"command": "ls"}),
+            }],
+            ..Default::default()
+        };
+        let events = stream_events(&reply, "m", 10);
+        assert!(events[0].starts_with("event: message_start"));
+        assert!(events.last().unwrap().starts_with("event: message_stop"));
+        // "hello world" 按 5 字符切成 3 块
+        let text_deltas = events.iter().filter(|e| e.contains("text_delta")).count();
+        assert_eq!(text_deltas, 3);
+        assert!(events.iter().any(|e| e.contains("\"index\":1")));
+    }
+
+    #[tokio::test]
+    async fn test_mock_response_error_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mock.toml");
+        std::fs::write(
+            &path,
+            r#"
+            [[responses]]
+            status = 429
+            error = "slow down"
+        "#,
+        )
+        .unwrap();
+        let profile = ProfileConfig {
+            name: "mock".to_string(),
+            provider_type: ProviderType::Mock,
+            mock_fixtures: Some(path),
+            ..Default::default()
+        };
+        let resp = mock_response(&MockState::new(), &request("hi"), &profile).unwrap();
+        assert_eq!(resp.status().as_u16(), 429);
+        assert_eq!(resp.text().await.unwrap(), "slow down");
+    }
+
+    #[tokio::test]
+    async fn test_mock_response_streaming_body() {
+        let profile = ProfileConfig {
+            name: "mock".to_string(),
+            provider_type: ProviderType::Mock,
+            ..Default::default()
+        };
+        let mut body = request("hi");
+        body["stream"] = json!(true);
+        let resp = mock_response(&MockState::new(), &body, &profile).unwrap();
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "text/event-stream"
+        );
+        let text = resp.text().await.unwrap();
+        assert!(text.contains("message_stop"));
+    }
+}
@@ -1,5 +1,6 @@
 mod chat_completions;
 mod direct;
+pub mod mock;
 mod responses;
 
 use anyhow::Result;
@@ -55,6 +56,16 @@ pub trait ProviderAdapter: Send + Sync {
         builder
     }
 
+    /// 本地生成上游响应（如 Mock provider），返回 Some 时不发起 HTTP 请求
+    fn local_response(
+        &self,
+        _state: &crate::proxy::ProxyState,
+        _body: &Value,
+        _profile: &ProfileConfig,
+    ) -> Option<Result<reqwest::Response>> {
+        None
+    }
+
     /// 是否直接透传上游响应（不做错误翻译和响应翻译）
     fn passthrough(&self) -> bool {
         false
@@ -73,5 +84,46 @@ pub fn for_provider(provider_type: &ProviderType) -> Box<dyn ProviderAdapter> {
         ProviderType::DirectAnthropic => Box::new(direct::DirectAnthropicAdapter),
         ProviderType::OpenAICompatible => Box::new(chat_completions::ChatCompletionsAdapter),
         ProviderType::OpenAIResponses => Box::new(responses::ResponsesAdapter),
+        ProviderType::Mock => Box::new(mock::MockAdapter),
+    }
+}
+
+/// 上游完整 URL：base_url + 端点路径 + profile 的 query_params
+pub fn upstream_url(adapter: &dyn ProviderAdapter, profile: &ProfileConfig) -> String {
+    let url = format!(
+        "{}{}",
+        profile.base_url.trim_end_matches('/'),
+        adapter.endpoint_path()
+    );
+    if profile.query_params.is_empty() {
+        return url;
+    }
+    let qs: String = profile
+        .query_params
+        .iter()
+        .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+    if url.contains('?') {
+        format!("{url}&{qs}")
+    } else {
+        format!("{url}?{qs}")
+    }
+}
+
+/// 构造上游请求：认证、额外头、自定义头与请求体
+pub fn build_request(
+    adapter: &dyn ProviderAdapter,
+    client: &reqwest::Client,
+    url: &str,
+    profile: &ProfileConfig,
+    body: &Value,
+) -> RequestBuilder {
+    let mut req = client.post(url).header("content-type", "application/json");
+    req = adapter.apply_auth(req, profile);
+    req = adapter.apply_extra_headers(req, profile);
+    for (k, v) in &profile.custom_headers {
+        req = req.header(k.as_str(), v.as_str());
     }
+    req.json(body)
 }
@@ -0,0 +1,352 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{Datelike, Local, NaiveDate, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use super::pricing::{format_cost, TokenUsage};
+use super::usage::UsageRecord;
+
+/// Profile 预算配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BudgetConfig {
+    /// 统计周期：daily | monthly
+    #[serde(default)]
+    pub period: BudgetPeriod,
+    /// token 上限（input + output + cache，可选）
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    /// 估算费用上限（美元，可选）
+    #[serde(default)]
+    pub max_cost: Option<f64>,
+    /// 软警告阈值（上限的比例，如 0.8）
+    #[serde(default)]
+    pub warn_at: Option<f64>,
+    /// 超出上限时的策略：reject | fallback
+    #[serde(default)]
+    pub on_exceed: BudgetPolicy,
+    /// on_exceed = "fallback" 时改用的 profile
+    #[serde(default)]
+    pub fallback_profile: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPeriod {
+    #[default]
+    Daily,
+    Monthly,
+}
+
+impl std::fmt::Display for BudgetPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BudgetPeriod::Daily => write!(f, "daily"),
+            BudgetPeriod::Monthly => write!(f, "monthly"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetPolicy {
+    #[default]
+    Reject,
+    Fallback,
+}
+
+/// 预算检查结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum BudgetStatus {
+    Ok,
+    /// 已越过软警告阈值
+    Warning(String),
+    /// 已达到上限
+    Exceeded(String),
+}
+
+/// 单个 profile 当前日 / 月的累计用量
+#[derive(Debug, Clone, Default)]
+struct ProfileSpend {
+    day: NaiveDate,
+    day_tokens: u64,
+    day_cost: f64,
+    month: (i32, u32),
+    month_tokens: u64,
+    month_cost: f64,
+    /// 已发出警告的周期标识，避免每个请求重复告警
+    warned_period: Option<String>,
+}
+
+impl ProfileSpend {
+    fn roll(&mut self, today: NaiveDate) {
+        if self.day != today {
+            self.day = today;
+            self.day_tokens = 0;
+            self.day_cost = 0.0;
+        }
+        let month = (today.year(), today.month());
+        if self.month != month {
+            self.month = month;
+            self.month_tokens = 0;
+            self.month_cost = 0.0;
+        }
+    }
+
+    fn add(&mut self, today: NaiveDate, tokens: u64, cost: f64) {
+        self.roll(today);
+        self.day_tokens += tokens;
+        self.day_cost += cost;
+        self.month_tokens += tokens;
+        self.month_cost += cost;
+    }
+
+    fn current(&self, period: BudgetPeriod) -> (u64, f64) {
+        match period {
+            BudgetPeriod::Daily => (self.day_tokens, self.day_cost),
+            BudgetPeriod::Monthly => (self.month_tokens, self.month_cost),
+        }
+    }
+
+    fn period_key(&self, period: BudgetPeriod) -> String {
+        match period {
+            BudgetPeriod::Daily => self.day.to_string(),
+            BudgetPeriod::Monthly => format!("{}-{:02}", self.month.0, self.month.1),
+        }
+    }
+}
+
+/// 按 profile 跟踪预算用量。启动时从用量账本恢复当月数据，因此重启后预算状态不丢失。
+#[derive(Debug, Clone, Default)]
+pub struct BudgetTracker {
+    inner: Arc<Mutex<HashMap<String, ProfileSpend>>>,
+}
+
+impl BudgetTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 从账本加载本月记录
+    pub fn load_from_ledger() -> Self {
+        let tracker = Self::new();
+        let today = Local::now().date_naive();
+        let month_start = today.with_day(1).unwrap_or(today);
+        match super::usage::load_records(month_start, today) {
+            Ok(records) => tracker.seed(&records, today),
+            Err(e) => tracing::warn!(error = %e, "failed to load usage ledger for budgets"),
+        }
+        tracker
+    }
+
+    fn seed(&self, records: &[UsageRecord], today: NaiveDate) {
+        let mut map = self.inner.lock().unwrap();
+        for r in records {
+            let day = match Local.timestamp_millis_opt(r.timestamp).single() {
+                Some(ts) => ts.date_naive(),
+                None => continue,
+            };
+            let spend = map.entry(r.profile.clone()).or_default();
+            spend.roll(today);
+            if (day.year(), day.month()) == (today.year(), today.month()) {
+                spend.month_tokens += r.usage.total();
+                spend.month_cost += r.cost_usd;
+                if day == today {
+                    spend.day_tokens += r.usage.total();
+                    spend.day_cost += r.cost_usd;
+                }
+            }
+        }
+    }
+
+    /// 记录一次请求的用量
+    pub fn record(&self, profile: &str, usage: &TokenUsage, cost: f64) {
+        let today = Local::now().date_naive();
+        let mut map = self.inner.lock().unwrap();
+        map.entry(profile.to_string())
+            .or_default()
+            .add(today, usage.total(), cost);
+    }
+
+    /// 当前周期的 (tokens, cost)
+    pub fn current(&self, profile: &str, period: BudgetPeriod) -> (u64, f64) {
+        let today = Local::now().date_naive();
+        let mut map = self.inner.lock().unwrap();
+        let spend = map.entry(profile.to_string()).or_default();
+        spend.roll(today);
+        spend.current(period)
+    }
+
+    /// 检查 profile 是否仍在预算内
+    pub fn check(&self, profile: &str, budget: &BudgetConfig) -> BudgetStatus {
+        let today = Local::now().date_naive();
+        let mut map = self.inner.lock().unwrap();
+        let spend = map.entry(profile.to_string()).or_default();
+        spend.roll(today);
+        let (tokens, cost) = spend.current(budget.period);
+
+        // 已用比例（取 token 与费用中较高者）
+        let mut ratio: f64 = 0.0;
+        let mut exceeded = Vec::new();
+        if let Some(max) = budget.max_tokens {
+            ratio = ratio.max(tokens as f64 / max.max(1) as f64);
+            if tokens >= max {
+                exceeded.push(format!("{tokens}/{max} tokens"));
+            }
+        }
+        if let Some(max) = budget.max_cost {
+            if max > 0.0 {
+                ratio = ratio.max(cost / max);
+            }
+            if cost >= max {
+                exceeded.push(format!("{}/{}", format_cost(cost), format_cost(max)));
+            }
+        }
+
+        if !exceeded.is_empty() {
+            return BudgetStatus::Exceeded(format!(
+                "{} budget for profile '{profile}' exhausted ({}); resets at the start of the next {}",
+                budget.period,
+                exceeded.join(", "),
+                match budget.period {
+                    BudgetPeriod::Daily => "day",
+                    BudgetPeriod::Monthly => "month",
+                }
+            ));
+        }
+
+        if let Some(warn_at) = budget.warn_at {
+            if ratio >= warn_at {
+                let key = spend.period_key(budget.period);
+                if spend.warned_period.as_deref() != Some(key.as_str()) {
+                    spend.warned_period = Some(key);
+                    return BudgetStatus::Warning(format!(
+                        "profile '{profile}' has used {:.0}% of its {} budget",
+                        ratio * 100.0,
+                        budget.period
+                    ));
+                }
+            }
+        }
+
+        BudgetStatus::Ok
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(max_tokens: Option<u64>, max_cost: Option<f64>) -> BudgetConfig {
+        BudgetConfig {
+            period: BudgetPeriod::Daily,
+            max_tokens,
+            max_cost,
+            warn_at: None,
+            on_exceed: BudgetPolicy::Reject,
+            fallback_profile: None,
+        }
+    }
+
+    fn usage(tokens: u64) -> TokenUsage {
+        TokenUsage {
+            input_tokens: tokens,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_within_budget() {
+        let tracker = BudgetTracker::new();
+        tracker.record("a", &usage(100), 0.1);
+        assert_eq!(
+            tracker.check("a", &budget(Some(1000), None)),
+            BudgetStatus::Ok
+        );
+    }
+
+    #[test]
+    fn test_token_budget_exceeded() {
+        let tracker = BudgetTracker::new();
+        tracker.record("a", &usage(600), 0.0);
+        tracker.record("a", &usage(600), 0.0);
+        match tracker.check("a", &budget(Some(1000), None)) {
+            BudgetStatus::Exceeded(msg) => {
+                assert!(msg.contains("1200/1000 tokens"));
+                assert!(msg.contains("daily"));
+            }
+            other => panic!("expected Exceeded, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_cost_budget_exceeded() {
+        let tracker = BudgetTracker::new();
+        tracker.record("a", &usage(10), 5.5);
+        assert!(matches!(
+            tracker.check("a", &budget(None, Some(5.0))),
+            BudgetStatus::Exceeded(_)
+        ));
+        // 其他 profile 不受影响
+        assert_eq!(
+            tracker.check("b", &budget(None, Some(5.0))),
+            BudgetStatus::Ok
+        );
+    }
+
+    #[test]
+    fn test_warning_emitted_once_per_period() {
+        let tracker = BudgetTracker::new();
+        let mut b = budget(Some(1000), None);
+        b.warn_at = Some(0.8);
+        tracker.record("a", &usage(850), 0.0);
+        assert!(matches!(tracker.check("a", &b), BudgetStatus::Warning(_)));
+        assert_eq!(tracker.check("a", &b), BudgetStatus::Ok);
+    }
+
+    #[test]
+    fn test_seed_from_records() {
+        let today = Local::now().date_naive();
+        let now = Local::now().timestamp_millis();
+        let records = vec![
+            UsageRecord {
+                timestamp: now,
+                profile: "a".to_string(),
+                model: "m".to_string(),
+                usage: usage(300),
+                cost_usd: 1.5,
+            },
+            UsageRecord {
+                timestamp: now,
+                profile: "a".to_string(),
+                model: "m".to_string(),
+                usage: usage(200),
+                cost_usd: 0.5,
+            },
+        ];
+        let tracker = BudgetTracker::new();
+        tracker.seed(&records, today);
+        let (tokens, cost) = tracker.current("a", BudgetPeriod::Monthly);
+        assert_eq!(tokens, 500);
+        assert!((cost - 2.0).abs() < 1e-9);
+        assert_eq!(tracker.current("a", BudgetPeriod::Daily).0, 500);
+    }
+
+    #[test]
+    fn test_parse_budget_config() {
+        let b: BudgetConfig = toml::from_str(
+            r#"
+            period = "monthly"
+            max_cost = 50.0
+            warn_at = 0.8
+            on_exceed = "fallback"
+            fallback_profile = "cheap"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(b.period, BudgetPeriod::Monthly);
+        assert_eq!(b.on_exceed, BudgetPolicy::Fallback);
+        assert_eq!(b.fallback_profile.as_deref(), Some("cheap"));
+        assert!(b.max_tokens.is_none());
+    }
+}
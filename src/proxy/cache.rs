@@ -0,0 +1,689 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use axum::body::Body;
+use axum::response::Response;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::adapter::ByteStream;
+use super::util::{format_sse, Utf8Chunker};
+
+/// 响应头：标记缓存命中情况（hit / miss）
+pub const CACHE_HEADER: &str = "x-claudex-cache";
+
+/// 两次淘汰扫描之间的最短间隔（按缓存目录中标记文件的修改时间判断，多个进程共享）
+const EVICT_INTERVAL: Duration = Duration::from_secs(60);
+const EVICT_MARKER: &str = ".last-evict";
+
+/// 磁盘响应缓存配置（默认关闭）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 缓存目录（默认 ~/.cache/claudex/responses）
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// 条目有效期（秒）
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+    /// 缓存目录总大小上限（MB），超出时淘汰最旧条目
+    #[serde(default = "default_max_size_mb")]
+    pub max_size_mb: u64,
+    /// 只缓存这些 profile（为空表示全部）
+    #[serde(default)]
+    pub profiles: Vec<String>,
+}
+
+fn default_ttl_secs() -> u64 {
+    24 * 3600
+}
+
+fn default_max_size_mb() -> u64 {
+    256
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            ttl_secs: default_ttl_secs(),
+            max_size_mb: default_max_size_mb(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn resolve_dir(&self) -> Option<PathBuf> {
+        self.dir
+            .clone()
+            .or_else(|| dirs::cache_dir().map(|d| d.join("claudex").join("responses")))
+    }
+
+    pub(crate) fn applies_to(&self, profile: &str) -> bool {
+        self.enabled && (self.profiles.is_empty() || self.profiles.iter().any(|p| p == profile))
+    }
+}
+
+/// 采样是否确定：temperature == 0 或 top_k == 1，且未开启 extended thinking
+pub fn is_deterministic(body: &Value) -> bool {
+    let thinking = body
+        .get("thinking")
+        .and_then(|t| t.get("type"))
+        .and_then(|t| t.as_str())
+        .is_some_and(|t| t != "disabled");
+    if thinking {
+        return false;
+    }
+    let greedy_temperature = body
+        .get("temperature")
+        .and_then(|t| t.as_f64())
+        .is_some_and(|t| t == 0.0);
+    let greedy_top_k = body.get("top_k").and_then(|k| k.as_u64()) == Some(1);
+    greedy_temperature || greedy_top_k
+}
+
+/// 缓存 key：profile + 上游模型 + 规范化的翻译后请求体（忽略 stream 相关字段）
+pub fn cache_key(profile: &str, model: &str, translated: &Value) -> String {
+    let mut body = translated.clone();
+    if let Some(obj) = body.as_object_mut() {
+        obj.remove("stream");
+        obj.remove("stream_options");
+    }
+    let mut canonical = String::new();
+    write_canonical(
+        &json!({"profile": profile, "model": model, "body": body}),
+        &mut canonical,
+    );
+    let digest = Sha256::digest(canonical.as_bytes());
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// 按 key 排序输出 JSON，保证 key 与字段顺序无关
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&map[key], out);
+            }
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        other => out.push_str(&other.to_string()),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// 写入时间（秒级时间戳）
+    created_at: u64,
+    profile: String,
+    model: String,
+    /// Anthropic 格式的完整 message
+    response: Value,
+}
+
+/// 单个可缓存请求的缓存句柄
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+    max_bytes: u64,
+    key: String,
+    profile: String,
+    model: String,
+}
+
+impl ResponseCache {
+    /// 请求可缓存时返回句柄（缓存开启、profile 命中、采样确定）
+    pub fn for_request(
+        config: &CacheConfig,
+        profile: &str,
+        model: &str,
+        inbound: &Value,
+        translated: &Value,
+    ) -> Option<Self> {
+        if !config.applies_to(profile) || !is_deterministic(inbound) {
+            return None;
+        }
+        Some(Self {
+            dir: config.resolve_dir()?,
+            ttl: Duration::from_secs(config.ttl_secs),
+            max_bytes: config.max_size_mb.saturating_mul(1024 * 1024),
+            key: cache_key(profile, model, translated),
+            profile: profile.to_string(),
+            model: model.to_string(),
+        })
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", self.key))
+    }
+
+    /// 读取未过期的缓存条目（在阻塞线程上读文件）
+    pub async fn get(&self) -> Option<Value> {
+        let cache = self.clone();
+        tokio::task::spawn_blocking(move || cache.read())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// 同步读取；过期或损坏的条目会被删除
+    fn read(&self) -> Option<Value> {
+        let path = self.path();
+        let content = std::fs::read(&path).ok()?;
+        let entry: CacheEntry = match serde_json::from_slice(&content) {
+            Ok(e) => e,
+            Err(_) => {
+                let _ = std::fs::remove_file(&path);
+                return None;
+            }
+        };
+        if now_secs().saturating_sub(entry.created_at) >= self.ttl.as_secs() {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        Some(entry.response)
+    }
+
+    /// 写入缓存（先写临时文件再 rename），距上次淘汰超过间隔时再按大小上限淘汰
+    fn put(&self, response: &Value) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let entry = CacheEntry {
+            created_at: now_secs(),
+            profile: self.profile.clone(),
+            model: self.model.clone(),
+            response: response.clone(),
+        };
+        let tmp = self.dir.join(format!("{}.tmp", self.key));
+        std::fs::write(&tmp, serde_json::to_vec(&entry)?)
+            .with_context(|| format!("failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, self.path())?;
+        if self.eviction_due() {
+            self.evict();
+        }
+        Ok(())
+    }
+
+    /// 在阻塞线程上写入；失败只记日志，不影响请求
+    pub async fn store(&self, response: Value) {
+        let cache = self.clone();
+        let written = tokio::task::spawn_blocking(move || cache.put(&response))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|r| r);
+        match written {
+            Ok(()) => tracing::debug!(profile = %self.profile, key = %self.key, "response cached"),
+            Err(e) => {
+                tracing::warn!(profile = %self.profile, error = %e, "failed to write response cache")
+            }
+        }
+    }
+
+    /// 标记文件不存在或早于 `EVICT_INTERVAL` 时更新标记并返回 true
+    fn eviction_due(&self) -> bool {
+        let marker = self.dir.join(EVICT_MARKER);
+        let recent = std::fs::metadata(&marker)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| SystemTime::now().duration_since(t).ok())
+            .is_some_and(|age| age < EVICT_INTERVAL);
+        if recent {
+            return false;
+        }
+        let _ = std::fs::write(&marker, b"");
+        true
+    }
+
+    /// 删除过期条目，并按修改时间从旧到新淘汰直到总大小不超过上限
+    fn evict(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        let now = SystemTime::now();
+        let mut files: Vec<(PathBuf, u64, SystemTime)> = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|e| e != "json") {
+                continue;
+            }
+            let Ok(meta) = entry.metadata() else { continue };
+            let modified = meta.modified().unwrap_or(now);
+            if now.duration_since(modified).unwrap_or_default() >= self.ttl {
+                let _ = std::fs::remove_file(&path);
+                continue;
+            }
+            files.push((path, meta.len(), modified));
+        }
+        let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+        files.sort_by_key(|(_, _, modified)| *modified);
+        for (path, len, _) in files {
+            if total <= self.max_bytes {
+                break;
+            }
+            if std::fs::remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+    }
+
+    /// 包装流式输出：完整收到 message_stop 后写入缓存
+    pub fn tap_stream(self, stream: ByteStream) -> ByteStream {
+        use futures::StreamExt;
+
+        let output = async_stream::stream! {
+            let mut stream = stream;
+            let mut decoder = Utf8Chunker::default();
+            let mut assembler = StreamAssembler::default();
+            while let Some(item) = stream.next().await {
+                match item {
+                    Ok(bytes) => {
+                        assembler.push(&decoder.push(&bytes));
+                        yield Ok(bytes);
+                    }
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+            if let Some(message) = assembler.finish() {
+                self.store(message).await;
+            }
+        };
+        Box::pin(output)
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// 从 Anthropic SSE 事件流还原完整 message
+#[derive(Debug, Default)]
+pub struct StreamAssembler {
+    buffer: String,
+    message: Option<Value>,
+    /// tool_use 块的 input_json_delta 累积（按块索引）
+    partial_json: Map<String, Value>,
+    complete: bool,
+}
+
+impl StreamAssembler {
+    pub fn push(&mut self, text: &str) {
+        self.buffer.push_str(text);
+        while let Some(pos) = self.buffer.find('\n') {
+            let line = self.buffer[..pos].trim_end_matches('\r').to_string();
+            self.buffer = self.buffer[pos + 1..].to_string();
+            if let Some(data) = line.strip_prefix("data:") {
+                if let Ok(event) = serde_json::from_str::<Value>(data.trim()) {
+                    self.apply(&event);
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, event: &Value) {
+        let event_type = event.get("type").and_then(|t| t.as_str()).unwrap_or("");
+        if event_type == "message_start" {
+            self.message = event.get("message").cloned();
+            return;
+        }
+        let Some(message) = self.message.as_mut() else {
+            return;
+        };
+        let index = event.get("index").and_then(|i| i.as_u64()).unwrap_or(0) as usize;
+        match event_type {
+            "content_block_start" => {
+                if let (Some(content), Some(block)) = (
+                    message.get_mut("content").and_then(|c| c.as_array_mut()),
+                    event.get("content_block"),
+                ) {
+                    content.push(block.clone());
+                }
+            }
+            "content_block_delta" => {
+                let Some(block) = message.get_mut("content").and_then(|c| c.get_mut(index)) else {
+                    return;
+                };
+                let delta = &event["delta"];
+                let (field, piece) = match delta["type"].as_str().unwrap_or("") {
+                    "text_delta" => ("text", &delta["text"]),
+                    "thinking_delta" => ("thinking", &delta["thinking"]),
+                    "signature_delta" => ("signature", &delta["signature"]),
+                    "input_json_delta" => {
+                        let buf = self
+                            .partial_json
+                            .entry(index.to_string())
+                            .or_insert_with(|| Value::String(String::new()));
+                        if let (Value::String(buf), Some(piece)) =
+                            (buf, delta["partial_json"].as_str())
+                        {
+                            buf.push_str(piece);
+                        }
+                        return;
+                    }
+                    _ => return,
+                };
+                let existing = block[field].as_str().unwrap_or("").to_string();
+                block[field] = Value::String(existing + piece.as_str().unwrap_or(""));
+            }
+            "content_block_stop" => {
+                if let Some(Value::String(json)) = self.partial_json.remove(&index.to_string()) {
+                    if let Some(block) = message.get_mut("content").and_then(|c| c.get_mut(index)) {
+                        block["input"] = serde_json::from_str(&json).unwrap_or_else(|_| json!({}));
+                    }
+                }
+            }
+            "message_delta" => {
+                if let Some(delta) = event.get("delta").and_then(|d| d.as_object()) {
+                    for (k, v) in delta {
+                        message[k] = v.clone();
+                    }
+                }
+                if let Some(usage) = event.get("usage").and_then(|u| u.as_object()) {
+                    for (k, v) in usage {
+                        message["usage"][k] = v.clone();
+                    }
+                }
+            }
+            "message_stop" => self.complete = true,
+            _ => {}
+        }
+    }
+
+    /// 流完整结束时返回 message，否则 None（中途断开的流不缓存）
+    pub fn finish(self) -> Option<Value> {
+        if self.complete {
+            self.message
+        } else {
+            None
+        }
+    }
+}
+
+/// 把完整 message 还原为 Anthropic SSE 事件序列
+pub fn message_to_sse(message: &Value) -> Vec<String> {
+    let mut start = message.clone();
+    start["content"] = json!([]);
+    start["stop_reason"] = Value::Null;
+    start["stop_sequence"] = Value::Null;
+    start["usage"]["output_tokens"] = json!(0);
+    let mut events = vec![format_sse(
+        "message_start",
+        &json!({"type": "message_start", "message": start}),
+    )];
+
+    let blocks = message["content"].as_array().cloned().unwrap_or_default();
+    for (index, block) in blocks.iter().enumerate() {
+        let block_type = block["type"].as_str().unwrap_or("");
+        let (empty_block, delta) = match block_type {
+            "text" => (
+                json!({"type": "text", "text": ""}),
+                Some(json!({"type": "text_delta", "text": block["text"]})),
+            ),
+            "tool_use" => (
+                json!({"type": "tool_use", "id": block["id"], "name": block["name"], "input": {}}),
+                Some(
+                    json!({"type": "input_json_delta", "partial_json": block["input"].to_string()}),
+                ),
+            ),
+            "thinking" => (
+                json!({"type": "thinking", "thinking": ""}),
+                Some(json!({"type": "thinking_delta", "thinking": block["thinking"]})),
+            ),
+            _ => (block.clone(), None),
+        };
+        events.push(format_sse(
+            "content_block_start",
+            &json!({"type": "content_block_start", "index": index, "content_block": empty_block}),
+        ));
+        if let Some(delta) = delta {
+            events.push(format_sse(
+                "content_block_delta",
+                &json!({"type": "content_block_delta", "index": index, "delta": delta}),
+            ));
+        }
+        if block_type == "thinking" {
+            if let Some(signature) = block["signature"].as_str() {
+                events.push(format_sse(
+                    "content_block_delta",
+                    &json!({"type": "content_block_delta", "index": index, "delta": {"type": "signature_delta", "signature": signature}}),
+                ));
+            }
+        }
+        events.push(format_sse(
+            "content_block_stop",
+            &json!({"type": "content_block_stop", "index": index}),
+        ));
+    }
+
+    events.push(format_sse(
+        "message_delta",
+        &json!({
+            "type": "message_delta",
+            "delta": {"stop_reason": message["stop_reason"], "stop_sequence": message["stop_sequence"]},
+            "usage": {"output_tokens": message["usage"]["output_tokens"]}
+        }),
+    ));
+    events.push(format_sse("message_stop", &json!({"type": "message_stop"})));
+    events
+}
+
+/// 构造缓存命中的响应（按请求的流式/非流式格式）
+pub fn hit_response(message: &Value, is_streaming: bool) -> Result<Response> {
+    let builder = Response::builder().status(200).header(CACHE_HEADER, "hit");
+    let response = if is_streaming {
+        let events: Vec<Result<Bytes, std::io::Error>> = message_to_sse(message)
+            .into_iter()
+            .map(|e| Ok(Bytes::from(e)))
+            .collect();
+        builder
+            .header("content-type", "text/event-stream")
+            .header("cache-control", "no-cache")
+            .body(Body::from_stream(futures::stream::iter(events)))
+    } else {
+        builder
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(message)?))
+    };
+    response.map_err(|e| anyhow::anyhow!("failed to build cached response: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Value {
+        json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "gpt-4o",
+            "content": [
+                {"type": "text", "text": "Let me look."},
+                {"type": "tool_use", "id": "call_1", "name": "Read", "input": {"file_path": "a.rs"}}
+            ],
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 7}
+        })
+    }
+
+    fn cache(dir: &std::path::Path, ttl_secs: u64) -> ResponseCache {
+        let config = CacheConfig {
+            enabled: true,
+            dir: Some(dir.to_path_buf()),
+            ttl_secs,
+            ..Default::default()
+        };
+        ResponseCache::for_request(
+            &config,
+            "p",
+            "m",
+            &json!({"temperature": 0}),
+            &json!({"model": "m", "messages": []}),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_deterministic_detection() {
+        assert!(is_deterministic(&json!({"temperature": 0})));
+        assert!(is_deterministic(&json!({"temperature": 0.0})));
+        assert!(is_deterministic(&json!({"top_k": 1})));
+        assert!(!is_deterministic(&json!({})));
+        assert!(!is_deterministic(&json!({"temperature": 0.7})));
+        assert!(!is_deterministic(
+            &json!({"temperature": 0, "thinking": {"type": "enabled", "budget_tokens": 1024}})
+        ));
+    }
+
+    #[test]
+    fn test_cache_key_ignores_key_order_and_stream() {
+        let a = cache_key(
+            "p",
+            "m",
+            &json!({"model": "m", "messages": [], "stream": true}),
+        );
+        let b = cache_key("p", "m", &json!({"messages": [], "model": "m"}));
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 64);
+        assert_ne!(
+            a,
+            cache_key("q", "m", &json!({"messages": [], "model": "m"}))
+        );
+        assert_ne!(
+            a,
+            cache_key("p", "m2", &json!({"messages": [], "model": "m"}))
+        );
+    }
+
+    #[test]
+    fn test_disabled_or_sampling_not_cached() {
+        let config = CacheConfig {
+            enabled: true,
+            dir: Some(PathBuf::from("/tmp")),
+            ..Default::default()
+        };
+        let inbound = json!({"temperature": 1});
+        assert!(ResponseCache::for_request(&config, "p", "m", &inbound, &json!({})).is_none());
+        let disabled = CacheConfig::default();
+        assert!(ResponseCache::for_request(
+            &disabled,
+            "p",
+            "m",
+            &json!({"temperature": 0}),
+            &json!({})
+        )
+        .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_store_get_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 3600);
+        assert!(cache.get().await.is_none());
+        cache.store(message()).await;
+        assert_eq!(cache.get().await.unwrap(), message());
+    }
+
+    #[test]
+    fn test_expired_entry_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache(dir.path(), 0);
+        cache.put(&message()).unwrap();
+        assert!(cache.read().is_none());
+        assert!(!cache.path().exists());
+    }
+
+    #[test]
+    fn test_eviction_respects_max_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old.json");
+        std::fs::write(&old, vec![b' '; 4096]).unwrap();
+        let mut cache = cache(dir.path(), 3600);
+        cache.max_bytes = 1024;
+        cache.put(&message()).unwrap();
+        assert!(!old.exists());
+        assert!(cache.read().is_some());
+    }
+
+    #[test]
+    fn test_eviction_runs_at_most_once_per_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = cache(dir.path(), 3600);
+        cache.max_bytes = 1024;
+        cache.put(&message()).unwrap();
+
+        // 刚扫描过：之后的写入不再遍历目录
+        let old = dir.path().join("old.json");
+        std::fs::write(&old, vec![b' '; 4096]).unwrap();
+        cache.put(&message()).unwrap();
+        assert!(old.exists());
+
+        // 标记过期后的下一次写入再淘汰
+        let stale = SystemTime::now() - EVICT_INTERVAL;
+        std::fs::File::options()
+            .write(true)
+            .open(dir.path().join(EVICT_MARKER))
+            .unwrap()
+            .set_modified(stale)
+            .unwrap();
+        cache.put(&message()).unwrap();
+        assert!(!old.exists());
+    }
+
+    #[test]
+    fn test_sse_roundtrip_through_assembler() {
+        let events = message_to_sse(&message());
+        let mut assembler = StreamAssembler::default();
+        // 逐字节切分，模拟任意 chunk 边界
+        let joined = events.concat();
+        for chunk in joined.as_bytes().chunks(5) {
+            assembler.push(std::str::from_utf8(chunk).unwrap());
+        }
+        assert_eq!(assembler.finish().unwrap(), message());
+    }
+
+    #[test]
+    fn test_incomplete_stream_not_assembled() {
+        let events = message_to_sse(&message());
+        let mut assembler = StreamAssembler::default();
+        for event in &events[..events.len() - 1] {
+            assembler.push(event);
+        }
+        assert!(assembler.finish().is_none());
+    }
+}
@@ -0,0 +1,640 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use axum::http::HeaderMap;
+use bytes::Bytes;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::adapter::{ByteStream, TranslatedRequest};
+use super::util::{ToolNameMap, Utf8Chunker};
+use crate::config::ProfileConfig;
+
+/// 流量录制配置（默认关闭）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CaptureConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// 录制目录（默认 ~/.local/share/claudex/captures）
+    #[serde(default)]
+    pub dir: Option<PathBuf>,
+    /// 只录制这些 profile（为空表示全部）
+    #[serde(default)]
+    pub profiles: Vec<String>,
+}
+
+impl CaptureConfig {
+    pub fn resolve_dir(&self) -> Option<PathBuf> {
+        self.dir
+            .clone()
+            .or_else(|| dirs::data_local_dir().map(|d| d.join("claudex").join("captures")))
+    }
+
+    fn applies_to(&self, profile: &str) -> bool {
+        self.enabled && (self.profiles.is_empty() || self.profiles.iter().any(|p| p == profile))
+    }
+}
+
+const REDACTED: &str = "[REDACTED]";
+
+/// 一次请求的完整录制（入站请求 → 上游请求 → 上游原始响应 → 翻译后输出）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Capture {
+    /// 录制时间（毫秒时间戳）
+    pub timestamp: i64,
+    /// 已脱敏的 profile 配置快照（replay 时用于重新翻译请求）
+    pub profile: Option<ProfileConfig>,
+    pub streaming: bool,
+    /// 已脱敏的入站请求头
+    #[serde(default)]
+    pub inbound_headers: Vec<(String, String)>,
+    /// 入站 Anthropic 请求体
+    pub inbound: Value,
+    pub url: String,
+    /// 翻译后的上游请求体
+    pub translated: Value,
+    #[serde(default)]
+    pub tool_name_map: ToolNameMap,
+    pub upstream_status: u16,
+    /// 非流式上游响应体（JSON 解析失败时为字符串）
+    #[serde(default)]
+    pub upstream_body: Option<Value>,
+    /// 流式上游原始 chunk（保持原始分块边界）
+    #[serde(default)]
+    pub upstream_chunks: Vec<String>,
+    /// 非流式翻译输出
+    #[serde(default)]
+    pub output: Option<Value>,
+    /// 流式翻译输出 chunk
+    #[serde(default)]
+    pub output_chunks: Vec<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// 脱敏：敏感字段名的值替换为占位符，已知密钥在任意字符串中出现时替换
+#[derive(Debug, Clone, Default)]
+struct Redactor {
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    fn for_profile(profile: &ProfileConfig) -> Self {
+        let secrets = std::iter::once(profile.api_key.clone())
+            .chain(profile.custom_headers.values().cloned())
+            .chain(profile.query_params.values().cloned())
+            .filter(|s| s.len() >= 8)
+            .collect();
+        Self { secrets }
+    }
+
+    fn is_sensitive_key(key: &str) -> bool {
+        let key = key.to_ascii_lowercase().replace('-', "_");
+        key == "authorization"
+            || key == "cookie"
+            || key.contains("api_key")
+            || key.contains("apikey")
+            || key.ends_with("token")
+            || key.contains("secret")
+            || key.contains("password")
+    }
+
+    fn redact_str(&self, s: &str) -> String {
+        let mut out = s.to_string();
+        for secret in &self.secrets {
+            if out.contains(secret.as_str()) {
+                out = out.replace(secret.as_str(), REDACTED);
+            }
+        }
+        out
+    }
+
+    fn redact_value(&self, value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (k, v) in map.iter_mut() {
+                    if Self::is_sensitive_key(k) && !v.is_null() && !v.is_object() {
+                        *v = Value::String(REDACTED.to_string());
+                    } else {
+                        self.redact_value(v);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(|v| self.redact_value(v)),
+            Value::String(s) => *s = self.redact_str(s),
+            _ => {}
+        }
+    }
+
+    fn redact_profile(&self, profile: &ProfileConfig) -> ProfileConfig {
+        let mut p = profile.clone();
+        if !p.api_key.is_empty() {
+            p.api_key = REDACTED.to_string();
+        }
+        for (k, v) in p.custom_headers.iter_mut().chain(p.query_params.iter_mut()) {
+            if Self::is_sensitive_key(k) || self.secrets.iter().any(|s| v.contains(s.as_str())) {
+                *v = REDACTED.to_string();
+            }
+        }
+        p
+    }
+}
+
+/// 录制中的请求。所有副本（含流式 tap）释放后写入文件。
+#[derive(Clone)]
+pub struct CaptureRecorder {
+    inner: Arc<CaptureWriter>,
+}
+
+struct CaptureWriter {
+    capture: Mutex<Capture>,
+    redactor: Redactor,
+    path: PathBuf,
+}
+
+impl Drop for CaptureWriter {
+    fn drop(&mut self) {
+        let capture = match self.capture.lock() {
+            Ok(c) => c,
+            Err(_) => return,
+        };
+        if let Err(e) = write_capture(&self.path, &capture) {
+            tracing::warn!(path = %self.path.display(), error = %e, "failed to write capture");
+        } else {
+            tracing::debug!(path = %self.path.display(), "traffic capture written");
+        }
+    }
+}
+
+fn write_capture(path: &Path, capture: &Capture) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, serde_json::to_vec_pretty(capture)?)?;
+    Ok(())
+}
+
+static CAPTURE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+impl CaptureRecorder {
+    /// 如果该 profile 启用了录制，则开始录制一次请求
+    pub fn start(
+        config: &CaptureConfig,
+        profile: &ProfileConfig,
+        headers: &HeaderMap,
+        inbound: &Value,
+        url: &str,
+        translated: &TranslatedRequest,
+        streaming: bool,
+    ) -> Option<Self> {
+        if !config.applies_to(&profile.name) {
+            return None;
+        }
+        let dir = config.resolve_dir()?;
+        let redactor = Redactor::for_profile(profile);
+
+        let now = chrono::Local::now();
+        let seq = CAPTURE_SEQ.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!(
+            "{}-{}-{}-{seq}.json",
+            now.format("%Y%m%d-%H%M%S%.3f"),
+            sanitize_file_component(&profile.name),
+            std::process::id()
+        ));
+
+        let inbound_headers = headers
+            .iter()
+            .map(|(k, v)| {
+                let value = if Redactor::is_sensitive_key(k.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    redactor.redact_str(v.to_str().unwrap_or("(binary)"))
+                };
+                (k.as_str().to_string(), value)
+            })
+            .collect();
+
+        let mut inbound = inbound.clone();
+        redactor.redact_value(&mut inbound);
+        let mut translated_body = translated.body.clone();
+        redactor.redact_value(&mut translated_body);
+
+        let capture = Capture {
+            timestamp: now.timestamp_millis(),
+            profile: Some(redactor.redact_profile(profile)),
+            streaming,
+            inbound_headers,
+            inbound,
+            url: redactor.redact_str(url),
+            translated: translated_body,
+            tool_name_map: translated.tool_name_map.clone(),
+            ..Default::default()
+        };
+
+        Some(Self {
+            inner: Arc::new(CaptureWriter {
+                capture: Mutex::new(capture),
+                redactor,
+                path,
+            }),
+        })
+    }
+
+    fn with<F: FnOnce(&mut Capture, &Redactor)>(&self, f: F) {
+        if let Ok(mut c) = self.inner.capture.lock() {
+            f(&mut c, &self.inner.redactor);
+        }
+    }
+
+    pub fn set_status(&self, status: u16) {
+        self.with(|c, _| c.upstream_status = status);
+    }
+
+    pub fn set_error(&self, error: &str) {
+        self.with(|c, r| c.error = Some(r.redact_str(error)));
+    }
+
+    /// 记录非流式上游响应体
+    pub fn set_upstream_body(&self, body: &[u8]) {
+        self.with(|c, r| {
+            let mut value = serde_json::from_slice::<Value>(body)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned()));
+            r.redact_value(&mut value);
+            c.upstream_body = Some(value);
+        });
+    }
+
+    /// 记录非流式翻译输出
+    pub fn set_output(&self, output: &Value) {
+        self.with(|c, r| {
+            let mut value = output.clone();
+            r.redact_value(&mut value);
+            c.output = Some(value);
+        });
+    }
+
+    /// 透传上游 SSE 字节流，同时记录原始 chunk
+    pub fn tap_upstream(&self, stream: ByteStream) -> ByteStream {
+        self.tap(stream, |c, chunk| c.upstream_chunks.push(chunk))
+    }
+
+    /// 透传翻译后的 SSE 字节流，同时记录输出 chunk
+    pub fn tap_output(&self, stream: ByteStream) -> ByteStream {
+        self.tap(stream, |c, chunk| c.output_chunks.push(chunk))
+    }
+
+    fn tap(&self, stream: ByteStream, push: fn(&mut Capture, String)) -> ByteStream {
+        let recorder = self.clone();
+        let mut chunker = Utf8Chunker::default();
+        Box::pin(stream.map(move |item| {
+            match &item {
+                Ok(bytes) => {
+                    let text = chunker.push(bytes);
+                    if !text.is_empty() {
+                        recorder.with(|c, r| push(c, r.redact_str(&text)));
+                    }
+                }
+                Err(e) => recorder.set_error(&format!("stream error: {e}")),
+            }
+            item
+        }))
+    }
+}
+
+fn sanitize_file_component(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// ─── Replay ─────────────────────────────────────────────
+
+/// 离线重放结果
+#[derive(Debug)]
+pub struct ReplayResult {
+    /// 重新翻译得到的输出（非流式为 JSON，流式为 SSE 文本）
+    pub output: String,
+    /// 与录制时的输出是否一致（忽略随机生成的 message id）
+    pub output_matches: Option<bool>,
+    /// 用录制的 profile 快照重新翻译请求后，是否与录制的上游请求一致
+    pub request_matches: Option<bool>,
+}
+
+pub fn load_capture(path: &Path) -> Result<Capture> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("invalid capture {}", path.display()))
+}
+
+/// 用录制的上游响应离线重跑翻译
+pub async fn replay_capture(capture: &Capture) -> Result<ReplayResult> {
+    let profile = capture
+        .profile
+        .as_ref()
+        .context("capture has no profile snapshot")?;
+    let adapter = super::adapter::for_provider(&profile.provider_type);
+
+    let request_matches = adapter
+        .translate_request(&capture.inbound, profile)
+        .ok()
+        .map(|mut t| {
+            adapter.filter_translated_body(&mut t.body, profile);
+            t.body == capture.translated
+        });
+
+    if capture.streaming {
+        let chunks: Vec<Result<Bytes, reqwest::Error>> = capture
+            .upstream_chunks
+            .iter()
+            .map(|c| Ok(Bytes::from(c.clone())))
+            .collect();
+        let upstream: ByteStream = Box::pin(futures::stream::iter(chunks));
+        let mut stream = if adapter.passthrough() {
+            upstream
+        } else {
+            adapter.translate_stream(upstream, capture.tool_name_map.clone())
+        };
+        let mut output = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            output.extend_from_slice(&chunk?);
+        }
+        let output = String::from_utf8_lossy(&output).into_owned();
+        let recorded = if adapter.passthrough() && capture.output_chunks.is_empty() {
+            capture.upstream_chunks.concat()
+        } else {
+            capture.output_chunks.concat()
+        };
+        let output_matches =
+            (!recorded.is_empty()).then(|| normalize_sse(&output) == normalize_sse(&recorded));
+        Ok(ReplayResult {
+            output,
+            output_matches,
+            request_matches,
+        })
+    } else {
+        let upstream = capture
+            .upstream_body
+            .as_ref()
+            .context("capture has no upstream response body")?;
+        let output = if adapter.passthrough() {
+            upstream.clone()
+        } else {
+            adapter.translate_response(upstream, &capture.tool_name_map)?
+        };
+        let recorded = capture
+            .output
+            .as_ref()
+            .or(adapter.passthrough().then_some(upstream));
+        Ok(ReplayResult {
+            output: serde_json::to_string_pretty(&output)?,
+            output_matches: recorded.map(|r| *r == output),
+            request_matches,
+        })
+    }
+}
+
+/// 把 SSE 文本解析为 (event, data) 列表，并抹掉随机生成的 message id
+fn normalize_sse(text: &str) -> Vec<(String, Value)> {
+    let mut events = Vec::new();
+    for block in text.split("\n\n") {
+        let mut event = String::new();
+        let mut data = String::new();
+        for line in block.lines() {
+            if let Some(e) = line.strip_prefix("event:") {
+                event = e.trim().to_string();
+            } else if let Some(d) = line.strip_prefix("data:") {
+                data.push_str(d.trim());
+            }
+        }
+        if event.is_empty() && data.is_empty() {
+            continue;
+        }
+        let mut value = serde_json::from_str(&data).unwrap_or(Value::String(data));
+        if let Some(id) = value.pointer_mut("/message/id") {
+            *id = Value::String("msg_*".to_string());
+        }
+        events.push((event, value));
+    }
+    events
+}
+
+/// `claudex replay <capture>`
+pub async fn run_replay(path: &Path, quiet: bool) -> Result<()> {
+    let capture = load_capture(path)?;
+    let profile_name = capture
+        .profile
+        .as_ref()
+        .map(|p| p.name.as_str())
+        .unwrap_or("-");
+    let result = replay_capture(&capture).await?;
+
+    if !quiet {
+        println!("{}", result.output);
+        println!();
+    }
+    println!(
+        "Capture:  {} (profile: {profile_name}, {}, upstream HTTP {})",
+        path.display(),
+        if capture.streaming {
+            "streaming"
+        } else {
+            "non-streaming"
+        },
+        capture.upstream_status
+    );
+    let describe = |m: Option<bool>| match m {
+        Some(true) => "matches recording",
+        Some(false) => "DIFFERS from recording",
+        None => "not recorded",
+    };
+    println!("Request:  {}", describe(result.request_matches));
+    println!("Response: {}", describe(result.output_matches));
+
+    if result.output_matches == Some(false) {
+        anyhow::bail!("replayed output differs from recorded output");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProviderType;
+    use serde_json::json;
+
+    fn profile() -> ProfileConfig {
+        ProfileConfig {
+            name: "openrouter".to_string(),
+            provider_type: ProviderType::OpenAICompatible,
+            base_url: "https://openrouter.ai/api/v1".to_string(),
+            api_key: "sk-or-secret-123456".to_string(),
+            default_model: "gpt-4o".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn recorder(dir: &Path, streaming: bool, inbound: &Value) -> CaptureRecorder {
+        let config = CaptureConfig {
+            enabled: true,
+            dir: Some(dir.to_path_buf()),
+            profiles: vec![],
+        };
+        let p = profile();
+        let adapter = crate::proxy::adapter::for_provider(&p.provider_type);
+        let translated = adapter.translate_request(inbound, &p).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "sk-ant-client".parse().unwrap());
+        headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+        CaptureRecorder::start(
+            &config,
+            &p,
+            &headers,
+            inbound,
+            "https://openrouter.ai/api/v1/chat/completions",
+            &translated,
+            streaming,
+        )
+        .unwrap()
+    }
+
+    fn only_capture(dir: &Path) -> Capture {
+        let entries: Vec<_> = std::fs::read_dir(dir).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+        load_capture(&entries[0].as_ref().unwrap().path()).unwrap()
+    }
+
+    #[test]
+    fn test_disabled_or_filtered_profile_not_captured() {
+        let mut config = CaptureConfig::default();
+        assert!(!config.applies_to("a"));
+        config.enabled = true;
+        assert!(config.applies_to("a"));
+        config.profiles = vec!["b".to_string()];
+        assert!(!config.applies_to("a"));
+    }
+
+    #[test]
+    fn test_redaction() {
+        let redactor = Redactor::for_profile(&profile());
+        let mut v = json!({
+            "api_key": "whatever",
+            "nested": {"access_token": "abc", "text": "key is sk-or-secret-123456"},
+            "max_tokens": 100
+        });
+        redactor.redact_value(&mut v);
+        assert_eq!(v["api_key"], REDACTED);
+        assert_eq!(v["nested"]["access_token"], REDACTED);
+        assert_eq!(v["nested"]["text"], "key is [REDACTED]");
+        assert_eq!(v["max_tokens"], 100);
+    }
+
+    #[test]
+    fn test_redaction_covers_query_params() {
+        let mut p = profile();
+        p.query_params
+            .insert("key".to_string(), "AIzaSy-query-secret".to_string());
+        let redactor = Redactor::for_profile(&p);
+        assert_eq!(
+            redactor.redact_str("https://example.com/v1?key=AIzaSy-query-secret"),
+            "https://example.com/v1?key=[REDACTED]"
+        );
+        assert_eq!(redactor.redact_profile(&p).query_params["key"], REDACTED);
+    }
+
+    #[test]
+    fn test_utf8_chunker_keeps_split_characters() {
+        let bytes = "日本".as_bytes();
+        let mut chunker = Utf8Chunker::default();
+        assert_eq!(chunker.push(&bytes[..4]), "日");
+        assert_eq!(chunker.push(&bytes[4..]), "本");
+    }
+
+    #[tokio::test]
+    async fn test_non_streaming_capture_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbound = json!({
+            "model": "claude-sonnet-4",
+            "max_tokens": 100,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        let upstream = json!({
+            "id": "chatcmpl-1",
+            "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "hello"}, "finish_reason": "stop"}],
+            "usage": {"prompt_tokens": 3, "completion_tokens": 1}
+        });
+        {
+            let rec = recorder(dir.path(), false, &inbound);
+            rec.set_status(200);
+            rec.set_upstream_body(&serde_json::to_vec(&upstream).unwrap());
+            let adapter = crate::proxy::adapter::for_provider(&ProviderType::OpenAICompatible);
+            let out = adapter
+                .translate_response(&upstream, &ToolNameMap::new())
+                .unwrap();
+            rec.set_output(&out);
+        }
+
+        let capture = only_capture(dir.path());
+        assert_eq!(capture.profile.as_ref().unwrap().api_key, REDACTED);
+        assert!(capture
+            .inbound_headers
+            .contains(&("x-api-key".to_string(), REDACTED.to_string())));
+        assert_eq!(capture.upstream_status, 200);
+
+        let result = replay_capture(&capture).await.unwrap();
+        assert_eq!(result.output_matches, Some(true));
+        assert_eq!(result.request_matches, Some(true));
+        assert!(result.output.contains("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_streaming_capture_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbound = json!({
+            "model": "claude-sonnet-4",
+            "max_tokens": 100,
+            "stream": true,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        let upstream_chunks = vec![
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\ndata: [DONE]\n\n",
+        ];
+        {
+            let rec = recorder(dir.path(), true, &inbound);
+            rec.set_status(200);
+            let adapter = crate::proxy::adapter::for_provider(&ProviderType::OpenAICompatible);
+            let items: Vec<Result<Bytes, reqwest::Error>> = upstream_chunks
+                .iter()
+                .map(|c| Ok(Bytes::from(*c)))
+                .collect();
+            let upstream = rec.tap_upstream(Box::pin(futures::stream::iter(items)));
+            let mut out = rec.tap_output(adapter.translate_stream(upstream, ToolNameMap::new()));
+            while out.next().await.is_some() {}
+        }
+
+        let capture = only_capture(dir.path());
+        assert_eq!(capture.upstream_chunks, upstream_chunks);
+        assert!(!capture.output_chunks.is_empty());
+
+        let result = replay_capture(&capture).await.unwrap();
+        assert_eq!(result.output_matches, Some(true));
+        assert!(result.output.contains("message_stop"));
+    }
+
+    #[test]
+    fn test_normalize_sse_ignores_message_id() {
+        let a = "event: message_start\ndata: {\"message\":{\"id\":\"msg_1\"}}\n\n";
+        let b = "event: message_start\ndata: {\"message\":{\"id\":\"msg_2\"}}\n\n";
+        assert_eq!(normalize_sse(a), normalize_sse(b));
+    }
+}
@@ -1,19 +1,24 @@
+use std::sync::atomic::Ordering;
+
 use serde_json::Value;
 
 use crate::config::ClaudexConfig;
-use crate::context::resolve_profile_endpoint;
+use crate::context::compression::{compress_messages, Summarizer};
+use crate::context::rag::EmbeddingEndpoint;
 use crate::context::ContextEngineConfig;
 use crate::proxy::ProxyState;
 use crate::router::classifier;
 
 /// Apply context engine pre-processing to the request body.
 /// This handles RAG injection, context sharing, and conversation compression.
+/// `force_compress` compresses regardless of `enabled` / threshold (context window overflow).
 pub async fn apply_context_engine(
     body: &mut Value,
     state: &ProxyState,
     profile: &str,
     context_config: &ContextEngineConfig,
     config: &ClaudexConfig,
+    force_compress: bool,
 ) {
     // 1. RAG injection
     if context_config.rag.enabled {
@@ -37,12 +42,14 @@ pub async fn apply_context_engine(
     }
 
     // 3. Conversation compression
-    if context_config.compression.enabled {
+    if context_config.compression.enabled || force_compress {
         compress_if_needed(
             body,
+            profile,
             &context_config.compression,
-            &state.http_client,
+            state,
             config,
+            force_compress,
         )
         .await;
     }
@@ -61,24 +68,16 @@ async fn inject_rag_context(
         return;
     }
 
-    let endpoint = resolve_profile_endpoint(
-        config,
-        &context_config.rag.profile,
-        &context_config.rag.model,
-    );
-    let (base_url, api_key) = match endpoint {
-        Some((url, key, _)) => (url, key),
-        None => {
-            tracing::debug!(
-                profile = %context_config.rag.profile,
-                "RAG profile not found, skipping search"
-            );
-            return;
-        }
-    };
+    let endpoint = EmbeddingEndpoint::resolve(config);
+    if endpoint.is_none() {
+        tracing::debug!(
+            profile = %context_config.rag.profile,
+            "RAG profile not found, using BM25 only"
+        );
+    }
 
     match rag_index
-        .search(&query, &state.http_client, &base_url, &api_key)
+        .search(&query, &state.http_client, endpoint.as_ref())
         .await
     {
         Ok(results) if !results.is_empty() => {
@@ -115,51 +114,65 @@ fn inject_system_context(body: &mut Value, context: &str) {
 /// Compress conversation if it exceeds the token threshold
 async fn compress_if_needed(
     body: &mut Value,
+    profile: &str,
     compression: &crate::context::CompressionConfig,
-    http_client: &reqwest::Client,
+    state: &ProxyState,
     config: &ClaudexConfig,
+    force: bool,
 ) {
     let messages = match body.get("messages").and_then(|m| m.as_array()) {
         Some(m) => m.clone(),
         None => return,
     };
 
-    // Rough token estimation: ~4 chars per token
-    let total_chars: usize = messages.iter().map(|m| m.to_string().len()).sum();
-    let estimated_tokens = total_chars / 4;
+    // 按该 profile 实际使用的上游模型估算
+    let requested = body.get("model").and_then(|m| m.as_str());
+    let model = match config.find_profile(profile) {
+        Some(p) => crate::proxy::pool::member_model(p, requested),
+        None => requested.unwrap_or_default().to_string(),
+    };
+    let estimated_tokens = crate::tokenizer::count_request_tokens(&model, body) as usize;
+
+    if !force && estimated_tokens <= compression.threshold_tokens {
+        return;
+    }
 
-    if estimated_tokens <= compression.threshold_tokens {
+    if config.find_profile(&compression.profile).is_none() {
+        tracing::warn!(
+            profile = %compression.profile,
+            "compression profile not found, skipping"
+        );
         return;
     }
 
-    let endpoint = resolve_profile_endpoint(config, &compression.profile, &compression.model);
-    let (base_url, api_key, model) = match endpoint {
-        Some(v) => v,
-        None => {
-            tracing::warn!(
-                profile = %compression.profile,
-                "compression profile not found, skipping"
-            );
-            return;
-        }
+    let summarizer = Summarizer {
+        state,
+        profile: &compression.profile,
+        model: &compression.model,
     };
-
-    match crate::context::compression::compress_messages(
-        compression.enabled,
-        compression.keep_recent,
-        &base_url,
-        &api_key,
-        &model,
+    match compress_messages(
         &messages,
-        http_client,
+        compression.keep_recent,
+        compression.summary_step,
+        &state.summary_cache,
+        &summarizer,
     )
     .await
     {
         Ok(compressed) => {
-            body["messages"] = compressed;
+            // 摘要缓存命中计入摘要 profile 的 metrics（摘要模型的花费由转发链路记录）
+            let metrics = state.metrics.get_or_create(&compression.profile);
+            if compressed.cache_hit {
+                metrics.summary_hits.fetch_add(1, Ordering::Relaxed);
+            } else {
+                metrics.summary_misses.fetch_add(1, Ordering::Relaxed);
+            }
+            body["messages"] = compressed.messages;
             tracing::info!(
                 original_messages = messages.len(),
                 estimated_tokens,
+                summary_cache_hit = compressed.cache_hit,
+                summary_tokens = compressed.usage.map(|u| u.total()).unwrap_or(0),
                 "compressed conversation history"
             );
         }
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{ClaudexConfig, ProfileConfig};
+
+/// 超出上下文窗口时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// 强制触发 context engine 压缩（需配置 `[context.compression]` 的 profile）
+    #[default]
+    Compress,
+    /// 改发到长上下文 profile，目标不可用或同样放不下时退回压缩
+    Reroute,
+}
+
+/// 上下文溢出策略
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OverflowConfig {
+    #[serde(default)]
+    pub policy: OverflowPolicy,
+    /// reroute 的目标 profile
+    #[serde(default)]
+    pub profile: Option<String>,
+}
+
+/// 内置上下文窗口表（token，按模型 id 前缀匹配，最长前缀优先）
+const BUILTIN_CONTEXT_WINDOWS: &[(&str, u64)] = &[
+    // Anthropic
+    ("claude-", 200_000),
+    // OpenAI
+    ("gpt-5", 400_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("o4-mini", 200_000),
+    ("o3", 200_000),
+    // DeepSeek
+    ("deepseek-chat", 65_536),
+    ("deepseek-reasoner", 65_536),
+    // xAI
+    ("grok-4", 256_000),
+    ("grok-3", 131_072),
+    // Google
+    ("gemini-2.5-pro", 1_048_576),
+    ("gemini-2.5-flash", 1_048_576),
+    ("gemini-2.0-flash", 1_048_576),
+    // Moonshot / Zhipu / Qwen
+    ("kimi-k2", 131_072),
+    ("glm-4.5", 131_072),
+    ("qwen-max", 32_768),
+    ("qwen-plus", 131_072),
+];
+
+/// 查找模型上下文窗口：用户 `[context_windows]` 覆盖优先，其次内置表。
+/// 匹配规则与价格表相同（最长前缀，带 provider 前缀的 id 再用 `/` 之后部分匹配）
+pub fn lookup_context_window(overrides: &HashMap<String, u64>, model: &str) -> Option<u64> {
+    let bare = model.rsplit('/').next().unwrap_or(model);
+    for candidate in [model, bare] {
+        if let Some(window) =
+            best_prefix_match(overrides.iter().map(|(k, v)| (k.as_str(), *v)), candidate)
+        {
+            return Some(window);
+        }
+    }
+    for candidate in [model, bare] {
+        if let Some(window) = best_prefix_match(BUILTIN_CONTEXT_WINDOWS.iter().copied(), candidate)
+        {
+            return Some(window);
+        }
+    }
+    None
+}
+
+fn best_prefix_match<'a>(table: impl Iterator<Item = (&'a str, u64)>, model: &str) -> Option<u64> {
+    table
+        .filter(|(key, _)| model.starts_with(key))
+        .max_by_key(|(key, _)| key.len())
+        .map(|(_, window)| window)
+}
+
+/// profile 上某模型的上下文窗口：profile 的 `context_window` 优先
+pub fn context_window_for(
+    config: &ClaudexConfig,
+    profile: &ProfileConfig,
+    model: &str,
+) -> Option<u64> {
+    profile
+        .context_window
+        .or_else(|| lookup_context_window(&config.context_windows, model))
+}
+
+/// 估算的 prompt 大小超出窗口
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow {
+    pub prompt_tokens: u64,
+    pub window: u64,
+}
+
+/// 估算发往 profile 的请求是否超出其模型的上下文窗口（窗口未知时视为不超出）
+pub fn check_overflow(
+    config: &ClaudexConfig,
+    profile: &ProfileConfig,
+    body: &Value,
+) -> Option<Overflow> {
+    let model = body
+        .get("model")
+        .and_then(|m| m.as_str())
+        .unwrap_or(&profile.default_model);
+    let window = context_window_for(config, profile, model)?;
+    let prompt_tokens = crate::tokenizer::count_request_tokens(model, body);
+    (prompt_tokens > window).then_some(Overflow {
+        prompt_tokens,
+        window,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn profile(model: &str) -> ProfileConfig {
+        ProfileConfig {
+            name: "p".to_string(),
+            default_model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn body(model: &str, chars: usize) -> Value {
+        json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "x".repeat(chars)}]
+        })
+    }
+
+    #[test]
+    fn test_builtin_lookup() {
+        let none = HashMap::new();
+        assert_eq!(lookup_context_window(&none, "deepseek-chat"), Some(65_536));
+        assert_eq!(lookup_context_window(&none, "gpt-4o-mini"), Some(128_000));
+        assert_eq!(
+            lookup_context_window(&none, "anthropic/claude-sonnet-4"),
+            Some(200_000)
+        );
+        assert_eq!(lookup_context_window(&none, "my-local-llama"), None);
+    }
+
+    #[test]
+    fn test_override_and_profile_precedence() {
+        let mut config = ClaudexConfig::default();
+        config
+            .context_windows
+            .insert("deepseek-chat".to_string(), 131_072);
+        let p = profile("deepseek-chat");
+        assert_eq!(
+            context_window_for(&config, &p, "deepseek-chat"),
+            Some(131_072)
+        );
+        let p = ProfileConfig {
+            context_window: Some(32_000),
+            ..p
+        };
+        assert_eq!(
+            context_window_for(&config, &p, "deepseek-chat"),
+            Some(32_000)
+        );
+    }
+
+    #[test]
+    fn test_check_overflow() {
+        let config = ClaudexConfig::default();
+        let p = ProfileConfig {
+            context_window: Some(1_000),
+            ..profile("small")
+        };
+        assert!(check_overflow(&config, &p, &body("small", 100)).is_none());
+        let overflow = check_overflow(&config, &p, &body("small", 8_000)).unwrap();
+        assert_eq!(overflow.window, 1_000);
+        assert!(overflow.prompt_tokens > 1_000);
+        // 未知模型不做判断
+        let unknown = profile("my-local-llama");
+        assert!(check_overflow(&config, &unknown, &body("my-local-llama", 8_000_000)).is_none());
+    }
+
+    #[test]
+    fn test_deserialize_overflow_config() {
+        let c: OverflowConfig = toml::from_str(
+            r#"
+            policy = "reroute"
+            profile = "gemini"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(c.policy, OverflowPolicy::Reroute);
+        assert_eq!(c.profile.as_deref(), Some("gemini"));
+        assert_eq!(OverflowConfig::default().policy, OverflowPolicy::Compress);
+    }
+}
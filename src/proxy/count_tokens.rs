@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::{json, Value};
+
+use crate::proxy::ProxyState;
+
+/// `POST /proxy/{profile}/v1/messages/count_tokens`：本地估算输入 token 数，不访问上游。
+/// 按 profile 实际使用的上游模型估算：请求模型不属于该 profile 时按 models slot 映射，
+/// 再回退到 default_model（pool 取首个成员）
+pub async fn count_tokens(
+    State(state): State<Arc<ProxyState>>,
+    Path(profile_name): Path<String>,
+    body: axum::body::Bytes,
+) -> Response {
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(v) => v,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("invalid JSON: {e}")).into_response();
+        }
+    };
+    let requested = body.get("model").and_then(|m| m.as_str());
+    let model = {
+        let config = state.config.read().await;
+        let profile = config.find_profile(&profile_name).cloned().or_else(|| {
+            config
+                .find_pool(&profile_name)
+                .and_then(|pool| pool.launch_profile(&config))
+        });
+        match profile {
+            Some(profile) => super::pool::member_model(&profile, requested),
+            None => requested.unwrap_or_default().to_string(),
+        }
+    };
+    let input_tokens = crate::tokenizer::count_request_tokens(&model, &body);
+    tracing::debug!(profile = %profile_name, model = %model, input_tokens, "count_tokens");
+    (
+        StatusCode::OK,
+        Json(json!({ "input_tokens": input_tokens })),
+    )
+        .into_response()
+}
@@ -26,6 +26,9 @@ pub enum ProxyError {
 
     #[error("invalid request: {0}")]
     BadRequest(String),
+
+    #[error("{0}")]
+    RateLimited(String),
 }
 
 impl IntoResponse for ProxyError {
@@ -44,6 +47,7 @@ impl IntoResponse for ProxyError {
             ProxyError::OAuthError(_) => (StatusCode::UNAUTHORIZED, self.to_string()),
             ProxyError::Request(_) => (StatusCode::BAD_GATEWAY, self.to_string()),
             ProxyError::BadRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            ProxyError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, self.to_string()),
         };
         (status, message).into_response()
     }
@@ -94,6 +98,13 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
     }
 
+    #[test]
+    fn test_into_response_rate_limited() {
+        let err = ProxyError::RateLimited("slow down".to_string());
+        let resp = err.into_response();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
     #[test]
     fn test_into_response_unauthorized() {
         let err = ProxyError::OAuthError("expired".to_string());
@@ -1,68 +1,275 @@
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+use axum::extract::State;
+use axum::response::Response;
+use axum::Json;
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
-#[derive(Debug, Clone, PartialEq)]
+use super::error::ProxyError;
+use super::ProxyState;
+
+/// 每个 profile 的熔断器配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// 窗口内连续可用性失败达到此次数时熔断
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// 失败计数窗口（秒），距上次失败超过窗口则重新计数
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    /// 熔断后首次允许探测的等待时间（秒）
+    #[serde(default = "default_recovery_secs")]
+    pub recovery_secs: u64,
+    /// 半开探测失败后恢复时间的增长倍数
+    #[serde(default = "default_backoff_multiplier")]
+    pub backoff_multiplier: f64,
+    /// 恢复时间上限（秒）
+    #[serde(default = "default_max_recovery_secs")]
+    pub max_recovery_secs: u64,
+    /// 窗口内 429 达到此次数视为限流风暴，同样熔断
+    #[serde(default = "default_rate_limit_threshold")]
+    pub rate_limit_threshold: u32,
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_window_secs() -> u64 {
+    60
+}
+
+fn default_recovery_secs() -> u64 {
+    30
+}
+
+fn default_backoff_multiplier() -> f64 {
+    2.0
+}
+
+fn default_max_recovery_secs() -> u64 {
+    600
+}
+
+fn default_rate_limit_threshold() -> u32 {
+    5
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            window_secs: default_window_secs(),
+            recovery_secs: default_recovery_secs(),
+            backoff_multiplier: default_backoff_multiplier(),
+            max_recovery_secs: default_max_recovery_secs(),
+            rate_limit_threshold: default_rate_limit_threshold(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum CircuitState {
     Closed,
     Open,
     HalfOpen,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CircuitBreaker {
     pub state: CircuitState,
     pub failure_count: u32,
     pub last_failure: Option<Instant>,
     pub threshold: u32,
+    /// 当前恢复等待时间（半开探测失败后按倍数增长）
     pub recovery_timeout: Duration,
+    pub window: Duration,
+    pub base_recovery: Duration,
+    pub max_recovery: Duration,
+    pub backoff_multiplier: f64,
+    pub rate_limit_threshold: u32,
+    /// 窗口内的 429 时间点
+    pub rate_limited: VecDeque<Instant>,
+    /// 半开状态下是否已有探测请求在途
+    pub probe_in_flight: bool,
 }
 
 impl CircuitBreaker {
     pub fn new(threshold: u32, recovery_timeout: Duration) -> Self {
-        Self {
+        Self::from_config(&CircuitBreakerConfig {
+            failure_threshold: threshold,
+            recovery_secs: 0,
+            ..Default::default()
+        })
+        .with_recovery(recovery_timeout)
+    }
+
+    pub fn from_config(config: &CircuitBreakerConfig) -> Self {
+        let mut cb = Self {
             state: CircuitState::Closed,
             failure_count: 0,
             last_failure: None,
-            threshold,
-            recovery_timeout,
+            threshold: 0,
+            recovery_timeout: Duration::ZERO,
+            window: Duration::ZERO,
+            base_recovery: Duration::ZERO,
+            max_recovery: Duration::ZERO,
+            backoff_multiplier: 1.0,
+            rate_limit_threshold: 0,
+            rate_limited: VecDeque::new(),
+            probe_in_flight: false,
+        };
+        cb.apply_config(config);
+        cb
+    }
+
+    fn with_recovery(mut self, recovery: Duration) -> Self {
+        self.base_recovery = recovery;
+        self.recovery_timeout = recovery;
+        self.max_recovery = self.max_recovery.max(recovery);
+        self
+    }
+
+    /// 更新参数（配置热更新），不改变当前状态
+    pub fn apply_config(&mut self, config: &CircuitBreakerConfig) {
+        let base = Duration::from_secs(config.recovery_secs);
+        if self.base_recovery != base || self.state == CircuitState::Closed {
+            self.recovery_timeout = base;
         }
+        self.threshold = config.failure_threshold.max(1);
+        self.window = Duration::from_secs(config.window_secs);
+        self.base_recovery = base;
+        self.max_recovery = Duration::from_secs(config.max_recovery_secs).max(base);
+        self.backoff_multiplier = config.backoff_multiplier.max(1.0);
+        self.rate_limit_threshold = config.rate_limit_threshold;
     }
 
+    /// 是否允许本次请求：Open 到期后转为 HalfOpen，HalfOpen 同时只放行一个探测
     pub fn can_attempt(&mut self) -> bool {
         match self.state {
             CircuitState::Closed => true,
             CircuitState::Open => {
-                if let Some(last) = self.last_failure {
-                    if last.elapsed() >= self.recovery_timeout {
-                        self.state = CircuitState::HalfOpen;
-                        true
-                    } else {
-                        false
-                    }
+                if self.recovery_elapsed() {
+                    self.state = CircuitState::HalfOpen;
+                    self.probe_in_flight = true;
+                    true
                 } else {
+                    false
+                }
+            }
+            CircuitState::HalfOpen => {
+                if self.probe_in_flight {
+                    false
+                } else {
+                    self.probe_in_flight = true;
                     true
                 }
             }
-            CircuitState::HalfOpen => true,
         }
     }
 
+    /// 不改变状态地判断当前是否允许请求
+    pub fn would_admit(&self) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => self.recovery_elapsed(),
+            CircuitState::HalfOpen => !self.probe_in_flight,
+        }
+    }
+
+    fn recovery_elapsed(&self) -> bool {
+        self.last_failure
+            .is_none_or(|last| last.elapsed() >= self.recovery_timeout)
+    }
+
     pub fn record_success(&mut self) {
         self.failure_count = 0;
         self.state = CircuitState::Closed;
+        self.recovery_timeout = self.base_recovery;
+        self.probe_in_flight = false;
+        self.rate_limited.clear();
     }
 
+    /// 可用性失败（5xx、超时、连接错误）
     pub fn record_failure(&mut self) {
+        self.record_failure_at(Instant::now());
+    }
+
+    fn record_failure_at(&mut self, now: Instant) {
+        match self.state {
+            // 熔断期间仍在途的请求失败，不再推迟恢复
+            CircuitState::Open => return,
+            CircuitState::HalfOpen => {
+                // 探测失败：重新熔断并延长恢复时间
+                self.recovery_timeout = self
+                    .recovery_timeout
+                    .mul_f64(self.backoff_multiplier)
+                    .min(self.max_recovery);
+                self.open(now);
+                return;
+            }
+            CircuitState::Closed => {}
+        }
+        if self
+            .last_failure
+            .is_some_and(|last| now.saturating_duration_since(last) > self.window)
+        {
+            self.failure_count = 0;
+        }
         self.failure_count += 1;
-        self.last_failure = Some(Instant::now());
+        self.last_failure = Some(now);
 
         if self.failure_count >= self.threshold {
-            self.state = CircuitState::Open;
             tracing::warn!(failures = self.failure_count, "circuit breaker opened");
+            self.open(now);
+        }
+    }
+
+    /// 429：窗口内累计达到阈值（限流风暴）时按可用性失败处理
+    pub fn record_rate_limited(&mut self) {
+        self.record_rate_limited_at(Instant::now());
+    }
+
+    fn record_rate_limited_at(&mut self, now: Instant) {
+        match self.state {
+            CircuitState::Open => return,
+            CircuitState::HalfOpen => return self.record_failure_at(now),
+            CircuitState::Closed => {}
+        }
+        while self
+            .rate_limited
+            .front()
+            .is_some_and(|t| now.saturating_duration_since(*t) > self.window)
+        {
+            self.rate_limited.pop_front();
         }
+        self.rate_limited.push_back(now);
+        if self.rate_limit_threshold > 0
+            && self.rate_limited.len() as u32 >= self.rate_limit_threshold
+        {
+            tracing::warn!(
+                rate_limited = self.rate_limited.len(),
+                "circuit breaker opened on 429 storm"
+            );
+            self.last_failure = Some(now);
+            self.open(now);
+        }
+    }
+
+    /// 与上游可用性无关的结果（如客户端错误）：不计数，仅释放半开探测名额
+    pub fn record_ignored(&mut self) {
+        self.probe_in_flight = false;
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open;
+        self.last_failure = Some(now);
+        self.probe_in_flight = false;
+        self.rate_limited.clear();
     }
 
     pub fn is_open(&self) -> bool {
@@ -72,7 +279,7 @@ impl CircuitBreaker {
 
 impl Default for CircuitBreaker {
     fn default() -> Self {
-        Self::new(3, Duration::from_secs(30))
+        Self::from_config(&CircuitBreakerConfig::default())
     }
 }
 
@@ -82,35 +289,195 @@ pub fn new_circuit_breaker_map() -> CircuitBreakerMap {
     Arc::new(RwLock::new(HashMap::new()))
 }
 
+/// 半开探测名额守卫：持有名额的请求在记录结果前被取消（客户端断开、上层超时）时
+/// 释放名额，避免 profile 一直停留在 HalfOpen 拒绝所有请求
+pub struct ProbeGuard {
+    map: CircuitBreakerMap,
+    profile: String,
+    armed: bool,
+}
+
+impl ProbeGuard {
+    pub fn new(map: &CircuitBreakerMap, profile: &str) -> Self {
+        Self {
+            map: map.clone(),
+            profile: profile.to_string(),
+            armed: true,
+        }
+    }
+
+    /// 结果已记录，名额由 record_* 释放
+    pub fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for ProbeGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        fn release(map: &mut HashMap<String, CircuitBreaker>, profile: &str) {
+            if let Some(cb) = map.get_mut(profile) {
+                if cb.state == CircuitState::HalfOpen {
+                    cb.record_ignored();
+                }
+            }
+        }
+        tracing::debug!(profile = %self.profile, "half-open probe cancelled, releasing slot");
+        if let Ok(mut map) = self.map.try_write() {
+            release(&mut map, &self.profile);
+            return;
+        }
+        // 锁被占用时异步释放
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let map = self.map.clone();
+            let profile = std::mem::take(&mut self.profile);
+            handle.spawn(async move { release(&mut *map.write().await, &profile) });
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub async fn get_or_create(map: &CircuitBreakerMap, profile: &str) -> CircuitBreaker {
     let read = map.read().await;
     if let Some(cb) = read.get(profile) {
-        return CircuitBreaker {
-            state: cb.state.clone(),
-            failure_count: cb.failure_count,
-            last_failure: cb.last_failure,
-            threshold: cb.threshold,
-            recovery_timeout: cb.recovery_timeout,
-        };
+        return cb.clone();
     }
     drop(read);
 
     let mut write = map.write().await;
     write
         .entry(profile.to_string())
-        .or_insert_with(CircuitBreaker::default);
-    let cb = write.get(profile).unwrap();
-    CircuitBreaker {
-        state: cb.state.clone(),
-        failure_count: cb.failure_count,
-        last_failure: cb.last_failure,
-        threshold: cb.threshold,
-        recovery_timeout: cb.recovery_timeout,
+        .or_insert_with(CircuitBreaker::default)
+        .clone()
+}
+
+/// 请求结果对熔断器的意义
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerOutcome {
+    Success,
+    /// 上游不可用：5xx、超时、连接错误
+    Unavailable,
+    /// 429
+    RateLimited,
+    /// 不说明上游是否可用（请求翻译失败、429 以外的 4xx，包括 key 失效的 401 / 403）：
+    /// 既不计为失败，也不重置失败计数或关闭 HalfOpen
+    Ignored,
+}
+
+/// 按结果分类：仅上游可用性问题计入熔断
+pub fn classify(result: &anyhow::Result<Response>) -> BreakerOutcome {
+    match result {
+        Ok(resp) => classify_status(resp.status().as_u16()),
+        Err(e) => match e.downcast_ref::<ProxyError>() {
+            Some(ProxyError::UpstreamError { status, .. }) => classify_status(*status),
+            Some(ProxyError::Translation(_) | ProxyError::BadRequest(_)) => BreakerOutcome::Ignored,
+            _ => BreakerOutcome::Unavailable,
+        },
+    }
+}
+
+fn classify_status(status: u16) -> BreakerOutcome {
+    match status {
+        429 => BreakerOutcome::RateLimited,
+        400..=499 => BreakerOutcome::Ignored,
+        500..=599 => BreakerOutcome::Unavailable,
+        _ => BreakerOutcome::Success,
+    }
+}
+
+/// 熔断器状态变化事件
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CircuitEvent {
+    pub profile: String,
+    pub from: CircuitState,
+    pub to: CircuitState,
+    /// Unix 时间戳（秒）
+    pub at: i64,
+    /// 下次允许探测前的等待时间（仅转为 Open 时）
+    pub recovery_secs: Option<u64>,
+}
+
+const MAX_EVENTS: usize = 100;
+
+/// 最近的熔断事件（环形缓冲）
+#[derive(Debug, Clone, Default)]
+pub struct CircuitEventLog {
+    inner: Arc<Mutex<VecDeque<CircuitEvent>>>,
+}
+
+impl CircuitEventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 记录状态变化（前后相同时忽略）并输出日志
+    pub fn transition(&self, profile: &str, from: CircuitState, cb: &CircuitBreaker) {
+        if from == cb.state {
+            return;
+        }
+        let event = CircuitEvent {
+            profile: profile.to_string(),
+            from,
+            to: cb.state,
+            at: chrono::Utc::now().timestamp(),
+            recovery_secs: (cb.state == CircuitState::Open)
+                .then_some(cb.recovery_timeout.as_secs()),
+        };
+        tracing::warn!(
+            profile = %event.profile,
+            from = ?event.from,
+            to = ?event.to,
+            recovery_secs = ?event.recovery_secs,
+            "circuit breaker state changed"
+        );
+        let mut events = self.inner.lock().unwrap();
+        if events.len() >= MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
     }
+
+    pub fn recent(&self) -> Vec<CircuitEvent> {
+        self.inner.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// 熔断器当前状态
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CircuitSnapshot {
+    pub state: CircuitState,
+    pub failure_count: u32,
+    pub recovery_secs: u64,
+}
+
+/// `GET /health/circuits`：各 profile 的熔断器状态与最近的状态变化事件
+pub async fn circuit_status(State(state): State<Arc<ProxyState>>) -> Json<serde_json::Value> {
+    let breakers: BTreeMap<String, CircuitSnapshot> = state
+        .circuit_breakers
+        .read()
+        .await
+        .iter()
+        .map(|(name, cb)| {
+            (
+                name.clone(),
+                CircuitSnapshot {
+                    state: cb.state,
+                    failure_count: cb.failure_count,
+                    recovery_secs: cb.recovery_timeout.as_secs(),
+                },
+            )
+        })
+        .collect();
+    Json(serde_json::json!({
+        "breakers": breakers,
+        "events": state.circuit_events.recent(),
+    }))
 }
 
 #[cfg(test)]
+#[allow(clippy::field_reassign_with_default)]
 mod tests {
     use super::*;
 
@@ -216,4 +583,161 @@ mod tests {
         cb.state = CircuitState::HalfOpen;
         assert!(!cb.is_open());
     }
+
+    #[test]
+    fn test_halfopen_admits_single_probe() {
+        let mut cb = CircuitBreaker::new(1, Duration::from_millis(0));
+        cb.record_failure();
+        assert!(cb.can_attempt());
+        assert_eq!(cb.state, CircuitState::HalfOpen);
+        // 探测在途时其余请求被拒绝
+        assert!(!cb.can_attempt());
+        assert!(!cb.would_admit());
+        // 与可用性无关的结果释放探测名额
+        cb.record_ignored();
+        assert!(cb.can_attempt());
+        cb.record_success();
+        assert_eq!(cb.state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_failed_probe_grows_recovery() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 1,
+            recovery_secs: 10,
+            backoff_multiplier: 3.0,
+            max_recovery_secs: 60,
+            ..Default::default()
+        };
+        let mut cb = CircuitBreaker::from_config(&config);
+        cb.record_failure();
+        assert_eq!(cb.recovery_timeout, Duration::from_secs(10));
+        for expected in [30, 60, 60] {
+            cb.state = CircuitState::HalfOpen;
+            cb.record_failure();
+            assert!(cb.is_open());
+            assert_eq!(cb.recovery_timeout, Duration::from_secs(expected));
+        }
+        // 成功后恢复到初始等待时间
+        cb.record_success();
+        assert_eq!(cb.recovery_timeout, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_failures_outside_window_reset_count() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            window_secs: 60,
+            ..Default::default()
+        };
+        let mut cb = CircuitBreaker::from_config(&config);
+        let start = Instant::now();
+        cb.record_failure_at(start);
+        cb.record_failure_at(start + Duration::from_secs(61));
+        assert_eq!(cb.state, CircuitState::Closed);
+        assert_eq!(cb.failure_count, 1);
+        cb.record_failure_at(start + Duration::from_secs(70));
+        assert!(cb.is_open());
+    }
+
+    #[test]
+    fn test_rate_limit_storm_opens() {
+        let config = CircuitBreakerConfig {
+            rate_limit_threshold: 3,
+            window_secs: 10,
+            ..Default::default()
+        };
+        let mut cb = CircuitBreaker::from_config(&config);
+        let start = Instant::now();
+        cb.record_rate_limited_at(start);
+        cb.record_rate_limited_at(start + Duration::from_secs(20));
+        cb.record_rate_limited_at(start + Duration::from_secs(21));
+        // 第一次 429 已出窗口
+        assert_eq!(cb.state, CircuitState::Closed);
+        cb.record_rate_limited_at(start + Duration::from_secs(22));
+        assert!(cb.is_open());
+    }
+
+    #[test]
+    fn test_classify_only_availability_failures() {
+        let status = |code: u16| -> anyhow::Result<Response> {
+            Ok(Response::builder()
+                .status(code)
+                .body(axum::body::Body::empty())
+                .unwrap())
+        };
+        assert_eq!(classify(&status(200)), BreakerOutcome::Success);
+        assert_eq!(classify(&status(400)), BreakerOutcome::Ignored);
+        assert_eq!(classify(&status(403)), BreakerOutcome::Ignored);
+        assert_eq!(classify(&status(429)), BreakerOutcome::RateLimited);
+        assert_eq!(classify(&status(503)), BreakerOutcome::Unavailable);
+
+        let upstream = |code: u16| -> anyhow::Result<Response> {
+            Err(ProxyError::UpstreamError {
+                status: code,
+                body: String::new(),
+            }
+            .into())
+        };
+        assert_eq!(classify(&upstream(502)), BreakerOutcome::Unavailable);
+        assert_eq!(classify(&upstream(429)), BreakerOutcome::RateLimited);
+        // key pool 因 401 耗尽时不能当作上游可用
+        assert_eq!(classify(&upstream(401)), BreakerOutcome::Ignored);
+
+        let translation: anyhow::Result<Response> =
+            Err(ProxyError::Translation(anyhow::anyhow!("bad tool schema")).into());
+        assert_eq!(classify(&translation), BreakerOutcome::Ignored);
+        let connection: anyhow::Result<Response> = Err(anyhow::anyhow!("connection refused"));
+        assert_eq!(classify(&connection), BreakerOutcome::Unavailable);
+    }
+
+    #[test]
+    fn test_client_errors_do_not_reset_failures() {
+        let mut cb = CircuitBreaker::default();
+        cb.record_failure();
+        // 4xx 按 Ignored 处理，不清零此前的可用性失败
+        cb.record_ignored();
+        assert_eq!(cb.failure_count, 1);
+    }
+
+    #[test]
+    fn test_event_log_records_transitions() {
+        let log = CircuitEventLog::new();
+        let mut cb = CircuitBreaker::new(1, Duration::from_secs(30));
+        log.transition("p", CircuitState::Closed, &cb);
+        assert!(log.recent().is_empty());
+        cb.record_failure();
+        log.transition("p", CircuitState::Closed, &cb);
+        let events = log.recent();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].to, CircuitState::Open);
+        assert_eq!(events[0].recovery_secs, Some(30));
+    }
+
+    #[tokio::test]
+    async fn test_probe_guard_releases_slot_on_drop() {
+        let map = new_circuit_breaker_map();
+        let mut cb = CircuitBreaker::new(1, Duration::ZERO);
+        cb.record_failure();
+        assert!(cb.can_attempt());
+        map.write().await.insert("p".to_string(), cb);
+
+        // 锁空闲时同步释放
+        drop(ProbeGuard::new(&map, "p"));
+        assert!(!map.read().await["p"].probe_in_flight);
+
+        // 锁被占用时异步释放
+        let mut held = map.write().await;
+        assert!(held.get_mut("p").unwrap().can_attempt());
+        drop(ProbeGuard::new(&map, "p"));
+        assert!(held["p"].probe_in_flight);
+        drop(held);
+        tokio::task::yield_now().await;
+        assert!(!map.read().await["p"].probe_in_flight);
+
+        // 已记录结果的守卫不再改动状态
+        assert!(map.write().await.get_mut("p").unwrap().can_attempt());
+        ProbeGuard::new(&map, "p").disarm();
+        assert!(map.read().await["p"].probe_in_flight);
+    }
 }
@@ -1,5 +1,8 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use axum::body::Body;
 use axum::extract::{Path, State};
@@ -9,14 +12,39 @@ use serde_json::Value;
 
 use crate::config::ProfileConfig;
 use crate::oauth::AuthType;
+use crate::proxy::adapter::TranslatedRequest;
+use crate::proxy::budget::{BudgetPolicy, BudgetStatus};
+use crate::proxy::context_window::OverflowPolicy;
+use crate::proxy::error::ProxyError;
+use crate::proxy::fallback::{self, BreakerOutcome, CircuitBreaker};
+use crate::proxy::metrics::{InFlightGuard, KeyOutcome};
+use crate::proxy::rate_limit::RateLimitPermit;
+use crate::proxy::route_info::{set_model_header, RouteDecision};
+use crate::proxy::util::anthropic_error_response;
 use crate::proxy::ProxyState;
 use crate::router::classifier;
+use crate::router::rules::{self, RequestFeatures};
+use crate::router::sticky;
 
 pub async fn handle_messages(
     State(state): State<Arc<ProxyState>>,
     Path(profile_name): Path<String>,
     headers: HeaderMap,
     body: axum::body::Bytes,
+) -> Response {
+    // 路由响应头统一在此写入，代理自身的错误响应（400 / 404 / 429 / 503 等）同样带上
+    let mut route = RouteDecision::new(&profile_name);
+    let mut response = route_messages(state, profile_name, headers, body, &mut route).await;
+    route.apply(&mut response);
+    response
+}
+
+async fn route_messages(
+    state: Arc<ProxyState>,
+    profile_name: String,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+    route: &mut RouteDecision,
 ) -> Response {
     let start = Instant::now();
 
@@ -61,11 +89,53 @@ pub async fn handle_messages(
 
     // --- Smart Routing: resolve "auto" profile ---
     let resolved_profile_name = if profile_name == "auto" {
-        resolve_auto_profile(&state, &body_value).await
+        let (resolved, reason) = resolve_auto_profile(&state, &body_value, &headers).await;
+        route.push_reason(format!("auto:{reason}"));
+        resolved
     } else {
         profile_name.clone()
     };
 
+    // --- Model routes: 按请求模型转发到其他 profile（在故障转移与计量之前）---
+    let (resolved_profile_name, model_routed) =
+        match resolve_model_route(&state, &resolved_profile_name, &body_value).await {
+            Some(target) => {
+                route.push_reason("model_route");
+                (target, true)
+            }
+            None => (resolved_profile_name, false),
+        };
+
+    // --- Virtual pool: 按策略排列成员，首个为主 profile，其余依次故障转移；预算用尽的成员跳过 ---
+    let mut pool_members = resolve_pool_members(&state, &resolved_profile_name).await;
+    if let Some(members) = pool_members.as_mut() {
+        let available = members.len();
+        members.retain(|p| !budget_exceeded(&state, p));
+        if members.is_empty() && available > 0 {
+            return anthropic_error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                &format!("all members of pool '{resolved_profile_name}' exhausted their budget"),
+            );
+        }
+    }
+    let resolved_profile_name = match &pool_members {
+        Some(members) => match members.first() {
+            Some(first) => {
+                route.push_reason(format!("pool={resolved_profile_name}"));
+                first.name.clone()
+            }
+            None => {
+                return anthropic_error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    &format!("no available members in pool '{resolved_profile_name}'"),
+                );
+            }
+        },
+        None => resolved_profile_name,
+    };
+    // 换了 profile 的请求需要按目标 profile 改写模型名
+    let mut rewrite_model = pool_members.is_some() || model_routed;
+
     let config = state.config.read().await;
 
     let mut profile = match config.find_profile(&resolved_profile_name) {
@@ -87,17 +157,120 @@ pub async fn handle_messages(
             .into_response();
     }
 
-    // Collect backup provider profiles
-    let backup_profiles: Vec<ProfileConfig> = profile
-        .backup_providers
-        .iter()
-        .filter_map(|name| config.find_profile(name).cloned())
-        .filter(|p| p.enabled)
+    // --- Budget enforcement ---
+    if let Some(budget) = profile.budget.clone() {
+        match state.budgets.check(&profile.name, &budget) {
+            BudgetStatus::Ok => {}
+            BudgetStatus::Warning(msg) => {
+                tracing::warn!(profile = %profile.name, "{msg}");
+            }
+            BudgetStatus::Exceeded(msg) => {
+                let fallback = match (budget.on_exceed, &budget.fallback_profile) {
+                    (BudgetPolicy::Fallback, Some(name)) => config
+                        .find_profile(name)
+                        .filter(|p| p.enabled)
+                        .filter(|p| !budget_exceeded(&state, p))
+                        .cloned(),
+                    _ => None,
+                };
+                match fallback {
+                    Some(fb) => {
+                        tracing::warn!(
+                            profile = %profile.name,
+                            fallback = %fb.name,
+                            "{msg}, routing to fallback profile"
+                        );
+                        route.push_reason("budget_fallback");
+                        profile = fb;
+                    }
+                    None => {
+                        tracing::warn!(profile = %profile.name, "{msg}, rejecting request");
+                        return anthropic_error_response(StatusCode::TOO_MANY_REQUESTS, &msg);
+                    }
+                }
+            }
+        }
+    }
+
+    // --- Context window: 估算 prompt 超出模型窗口时改发长上下文 profile 或强制压缩 ---
+    let mut force_compress = false;
+    if let Some(overflow) = super::context_window::check_overflow(
+        &config,
+        &profile,
+        &body_for_member(&body_value, &profile, rewrite_model),
+    ) {
+        let policy = profile.overflow.clone().unwrap_or_default();
+        let target = match policy.policy {
+            OverflowPolicy::Reroute => policy
+                .profile
+                .as_deref()
+                .and_then(|name| config.find_profile(name))
+                .filter(|p| p.enabled)
+                .filter(|p| {
+                    super::context_window::check_overflow(
+                        &config,
+                        p,
+                        &body_for_member(&body_value, p, true),
+                    )
+                    .is_none()
+                })
+                .cloned(),
+            OverflowPolicy::Compress => None,
+        };
+        match target {
+            Some(target) => {
+                tracing::warn!(
+                    profile = %profile.name,
+                    target = %target.name,
+                    prompt_tokens = overflow.prompt_tokens,
+                    context_window = overflow.window,
+                    "prompt exceeds context window, rerouting to long-context profile"
+                );
+                route.push_reason("context_overflow");
+                profile = target;
+                rewrite_model = true;
+                pool_members = None;
+            }
+            None => {
+                tracing::warn!(
+                    profile = %profile.name,
+                    policy = ?policy.policy,
+                    prompt_tokens = overflow.prompt_tokens,
+                    context_window = overflow.window,
+                    "prompt exceeds context window, compressing conversation"
+                );
+                force_compress = true;
+            }
+        }
+    }
+
+    // Collect backup provider profiles（pool 的其余成员优先，跳过预算用尽的 profile）
+    let mut backup_profiles: Vec<ProfileConfig> = pool_members
+        .into_iter()
+        .flatten()
+        .skip(1)
+        .chain(
+            profile
+                .backup_providers
+                .iter()
+                .filter_map(|name| config.find_profile(name).cloned())
+                .filter(|p| p.enabled),
+        )
+        .filter(|p| {
+            let exceeded = budget_exceeded(&state, p);
+            if exceeded {
+                tracing::warn!(backup = %p.name, "backup profile budget exhausted, skipping");
+            }
+            !exceeded
+        })
         .collect();
+    let mut seen = HashSet::from([profile.name.clone()]);
+    backup_profiles.retain(|p| seen.insert(p.name.clone()));
 
     let context_config = config.context.clone();
     let full_config = config.clone();
-    let metrics = state.metrics.get_or_create(&resolved_profile_name);
+    let metrics = state.metrics.get_or_create(&profile.name);
+    let in_flight = InFlightGuard::new(metrics.clone());
     drop(config);
 
     // OAuth token lazy refresh via TokenManager
@@ -113,13 +286,14 @@ pub async fn handle_messages(
         }
     }
 
-    // --- Context Engine: apply pre-processing ---
+    // --- Context Engine: apply pre-processing（按预算回退 / 超窗改发后的实际 profile）---
     super::context_engine::apply_context_engine(
         &mut body_value,
         &state,
-        &resolved_profile_name,
+        &profile.name,
         &context_config,
         &full_config,
+        force_compress,
     )
     .await;
 
@@ -130,11 +304,19 @@ pub async fn handle_messages(
 
     // --- Circuit Breaker + Failover ---
     // Try primary provider
-    let mut primary_result =
-        try_with_circuit_breaker(&state, &profile, &headers, &body_value, is_streaming).await;
+    route.attempt(&profile.name);
+    let mut served_by = profile.name.clone();
+    let mut primary_result = try_with_rate_limit(
+        &state,
+        &profile,
+        &headers,
+        &body_for_member(&body_value, &profile, rewrite_model),
+        is_streaming,
+    )
+    .await;
 
     // 401 retry: OAuth profile 的 token 可能已过期，清除缓存重试一次
-    if let Ok(ref response) = primary_result {
+    if let Ok((ref response, _)) = primary_result {
         if response.status() == StatusCode::UNAUTHORIZED && profile.auth_type == AuthType::OAuth {
             tracing::info!(
                 profile = %profile.name,
@@ -143,11 +325,11 @@ pub async fn handle_messages(
             match state.token_manager.invalidate_and_retry(&profile).await {
                 Ok(new_token) => {
                     crate::oauth::manager::apply_token_to_profile(&mut profile, &new_token);
-                    primary_result = try_with_circuit_breaker(
+                    primary_result = try_with_rate_limit(
                         &state,
                         &profile,
                         &headers,
-                        &body_value,
+                        &body_for_member(&body_value, &profile, rewrite_model),
                         is_streaming,
                     )
                     .await;
@@ -177,7 +359,21 @@ pub async fn handle_messages(
             let mut success = None;
 
             for backup in &backup_profiles {
-                match try_with_circuit_breaker(&state, backup, &headers, &body_value, is_streaming)
+                let backup_body = body_for_member(&body_value, backup, rewrite_model);
+                // 上下文窗口按每个候选的模型分别检查，放不下的 backup 直接跳过
+                if let Some(overflow) =
+                    super::context_window::check_overflow(&full_config, backup, &backup_body)
+                {
+                    tracing::warn!(
+                        backup = %backup.name,
+                        prompt_tokens = overflow.prompt_tokens,
+                        context_window = overflow.window,
+                        "prompt exceeds backup context window, skipping"
+                    );
+                    continue;
+                }
+                route.attempt(&backup.name);
+                match try_with_rate_limit(&state, backup, &headers, &backup_body, is_streaming)
                     .await
                 {
                     Ok(response) => {
@@ -185,6 +381,8 @@ pub async fn handle_messages(
                             backup = %backup.name,
                             "failover succeeded"
                         );
+                        route.push_reason("failover");
+                        served_by = backup.name.clone();
                         success = Some(response);
                         break;
                     }
@@ -209,21 +407,150 @@ pub async fn handle_messages(
     let latency = start.elapsed();
 
     match result {
-        Ok(response) => {
+        Ok((mut response, rate_permit)) => {
             metrics.record_request(true, latency, 0);
-            response
+            route.served(&served_by);
+            if full_config.rewrite_response_model {
+                response = super::route_info::rewrite_response_model(response).await;
+            }
+            let response = in_flight.hold_until_body_end(response);
+            match rate_permit {
+                Some(permit) => permit.hold_until_body_end(response),
+                None => response,
+            }
         }
         Err(e) => {
             metrics.record_request(false, latency, 0);
-            tracing::error!(profile = %resolved_profile_name, error = %e, "proxy request failed");
-            (StatusCode::BAD_GATEWAY, format!("proxy error: {e}")).into_response()
+            tracing::error!(profile = %profile.name, error = %e, "proxy request failed");
+            match e.downcast_ref::<ProxyError>() {
+                Some(ProxyError::RateLimited(msg)) => {
+                    anthropic_error_response(StatusCode::TOO_MANY_REQUESTS, msg)
+                }
+                // key pool 的所有 key 都被限流或拒绝：透传最后的状态，客户端据此退避重试
+                Some(ProxyError::UpstreamError {
+                    status: status @ (401 | 429),
+                    body,
+                }) => anthropic_error_response(
+                    StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_GATEWAY),
+                    body,
+                ),
+                _ => (StatusCode::BAD_GATEWAY, format!("proxy error: {e}")).into_response(),
+            }
         }
     }
 }
 
-/// Resolve "auto" profile via smart router
-async fn resolve_auto_profile(state: &ProxyState, body: &Value) -> String {
+/// profile 配置了预算且当前周期已用尽
+fn budget_exceeded(state: &ProxyState, profile: &ProfileConfig) -> bool {
+    profile.budget.as_ref().is_some_and(|budget| {
+        matches!(
+            state.budgets.check(&profile.name, budget),
+            BudgetStatus::Exceeded(_)
+        )
+    })
+}
+
+/// 虚拟 pool：按策略排列可用成员（跳过禁用与熔断中的 profile）。
+/// 名称不是 pool（或与普通 profile 同名）时返回 None
+async fn resolve_pool_members(state: &ProxyState, name: &str) -> Option<Vec<ProfileConfig>> {
+    let config = state.config.read().await;
+    if config.find_profile(name).is_some() {
+        return None;
+    }
+    let pool = config.find_pool(name)?;
+    let breakers = state.circuit_breakers.read().await;
+    let health = state.health_status.read().await;
+    let members = super::pool::order_members(
+        pool,
+        &config,
+        &breakers,
+        &health,
+        &state.metrics,
+        &state.pool_cursors,
+    );
+    tracing::info!(
+        pool = %name,
+        strategy = ?pool.strategy,
+        members = ?members.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(),
+        "pool dispatch"
+    );
+    Some(members)
+}
+
+/// 请求模型命中 profile 的 `model_routes` 时返回目标 profile（或 pool）名
+async fn resolve_model_route(state: &ProxyState, name: &str, body: &Value) -> Option<String> {
+    let model = body.get("model")?.as_str()?;
     let config = state.config.read().await;
+    let target = config.find_profile(name)?.model_route(model)?;
+    if config.find_profile(target).is_none() && config.find_pool(target).is_none() {
+        tracing::warn!(profile = %name, model = %model, target = %target, "model route target not found, ignoring");
+        return None;
+    }
+    tracing::info!(profile = %name, model = %model, target = %target, "model route matched");
+    Some(target.to_string())
+}
+
+/// 经 pool 或 model route 分发时，按目标 profile 改写请求模型
+fn body_for_member<'a>(
+    body: &'a Value,
+    profile: &ProfileConfig,
+    rewrite_model: bool,
+) -> Cow<'a, Value> {
+    if !rewrite_model {
+        return Cow::Borrowed(body);
+    }
+    let mut body = body.clone();
+    if let Some(obj) = body.as_object_mut() {
+        let model = super::pool::member_model(profile, obj.get("model").and_then(|v| v.as_str()));
+        obj.insert("model".to_string(), Value::String(model));
+    }
+    Cow::Owned(body)
+}
+
+/// Resolve "auto" profile: 先按确定性规则匹配，未命中再走 smart router 分类器。
+/// 返回 (profile, 路由原因)
+async fn resolve_auto_profile(
+    state: &ProxyState,
+    body: &Value,
+    headers: &HeaderMap,
+) -> (String, String) {
+    // 会话粘性：同一会话沿用首次路由结果（规则或分类），除非该 profile 已不可用；
+    // 先于规则检查，避免会话随 token 数增长跨过 min_tokens 等阈值后被改路由
+    let sticky = state.config.read().await.router.sticky.clone();
+    let ttl = Duration::from_secs(sticky.ttl_secs);
+    let fingerprint = if sticky.enabled {
+        sticky::fingerprint(body)
+    } else {
+        None
+    };
+    if let Some(ref fp) = fingerprint {
+        if let Some(profile) = state.sticky_routes.get(fp, ttl) {
+            if profile_available(state, &profile).await {
+                tracing::info!(profile = %profile, "sticky routing resolved");
+                return (profile, "sticky".to_string());
+            }
+            tracing::info!(profile = %profile, "sticky profile unavailable, re-routing");
+            state.sticky_routes.remove(fp);
+        }
+    }
+
+    let config = state.config.read().await;
+
+    if !config.router.routes.is_empty() {
+        let features = RequestFeatures::extract(body, headers);
+        if let Some(rule) = rules::first_match(&config.router.routes, &features) {
+            tracing::info!(
+                rule = %rule.label(),
+                profile = %rule.profile,
+                est_tokens = features.est_tokens,
+                "rule routing resolved"
+            );
+            if let Some(ref fp) = fingerprint {
+                state.sticky_routes.insert(fp, &rule.profile, ttl);
+            }
+            return (rule.profile.clone(), format!("rule={}", rule.label()));
+        }
+    }
 
     if !config.router.enabled {
         let default = config.router.resolve_profile("default").unwrap_or_else(|| {
@@ -233,46 +560,78 @@ async fn resolve_auto_profile(state: &ProxyState, body: &Value) -> String {
                 .map(|p| p.name.clone())
                 .unwrap_or_else(|| "default".to_string())
         });
-        return default;
+        return (default, "default".to_string());
     }
+    drop(config);
+
+    let (profile, reason) = classify_auto_profile(state, body).await;
+    if let Some(fp) = fingerprint {
+        state.sticky_routes.insert(&fp, &profile, ttl);
+    }
+    (profile, reason)
+}
+
+/// 粘性路由目标是否仍可用：存在且启用（或为 pool）、未被判定不健康、熔断器放行
+async fn profile_available(state: &ProxyState, name: &str) -> bool {
+    {
+        let config = state.config.read().await;
+        match config.find_profile(name) {
+            Some(p) if !p.enabled => return false,
+            Some(_) => {}
+            None => return config.find_pool(name).is_some(),
+        }
+    }
+    if state
+        .health_status
+        .read()
+        .await
+        .get(name)
+        .is_some_and(|h| !h.healthy)
+    {
+        return false;
+    }
+    state
+        .circuit_breakers
+        .read()
+        .await
+        .get(name)
+        .is_none_or(|cb| cb.would_admit())
+}
 
+/// smart router 分类器：按意图选择 profile，返回 (profile, 路由原因)
+async fn classify_auto_profile(state: &ProxyState, body: &Value) -> (String, String) {
+    let config = state.config.read().await;
     let router_config = config.router.clone();
 
-    // Resolve classifier profile endpoint
-    let endpoint = crate::context::resolve_profile_endpoint(
-        &config,
-        &router_config.profile,
-        &router_config.model,
-    );
+    let classifier_found = config.find_profile(&router_config.profile).is_some();
     drop(config);
 
     let user_message = classifier::extract_last_user_message(body).unwrap_or_default();
 
-    if user_message.is_empty() {
-        return router_config
+    let default = || {
+        let profile = router_config
             .resolve_profile("default")
             .unwrap_or_else(|| "default".to_string());
+        (profile, "default".to_string())
+    };
+
+    if user_message.is_empty() {
+        return default();
     }
 
-    let (base_url, api_key, model) = match endpoint {
-        Some(v) => v,
-        None => {
-            tracing::warn!(
-                profile = %router_config.profile,
-                "router classifier profile not found, using default"
-            );
-            return router_config
-                .resolve_profile("default")
-                .unwrap_or_else(|| "default".to_string());
-        }
-    };
+    if !classifier_found {
+        tracing::warn!(
+            profile = %router_config.profile,
+            "router classifier profile not found, using default"
+        );
+        return default();
+    }
 
     match classifier::classify_intent(
-        &base_url,
-        &api_key,
-        &model,
+        state,
+        &router_config.profile,
+        &router_config.model,
         &user_message,
-        &state.http_client,
     )
     .await
     {
@@ -283,84 +642,268 @@ async fn resolve_auto_profile(state: &ProxyState, body: &Value) -> String {
                     .unwrap_or_else(|| "default".to_string())
             });
             tracing::info!(intent = %intent, profile = %profile_name, "smart routing resolved");
-            profile_name
+            (profile_name, format!("intent={intent}"))
         }
         Err(e) => {
             tracing::warn!(error = %e, "intent classification failed, using default");
-            router_config
-                .resolve_profile("default")
-                .unwrap_or_else(|| "default".to_string())
+            default()
         }
     }
 }
 
+/// 按 profile 自身的 rate_limit 取得许可后转发（超限排队，等待超时返回
+/// `ProxyError::RateLimited`，交由下一个 profile 接管）；许可随响应返回，直到响应体结束才释放。
+/// 缓存命中直接返回，不占用许可
+async fn try_with_rate_limit(
+    state: &ProxyState,
+    profile: &ProfileConfig,
+    headers: &HeaderMap,
+    body: &Value,
+    is_streaming: bool,
+) -> anyhow::Result<(Response, Option<RateLimitPermit>)> {
+    let translated = translate_for(profile, body)?;
+    if let Some(response) = cached_response(state, profile, body, &translated, is_streaming).await {
+        return Ok((response, None));
+    }
+    let permit = match &profile.rate_limit {
+        Some(limit) => {
+            let limiter = state.rate_limiters.get(&profile.name, limit);
+            let metrics = state.metrics.get_or_create(&profile.name);
+            let est_tokens =
+                super::rate_limit::estimate_request_tokens(body, &profile.default_model);
+            match limiter.acquire(&profile.name, est_tokens, &metrics).await {
+                Ok(permit) => Some(permit),
+                Err(msg) => {
+                    tracing::warn!(profile = %profile.name, "{msg}");
+                    return Err(ProxyError::RateLimited(msg).into());
+                }
+            }
+        }
+        None => None,
+    };
+    let response =
+        forward_with_breaker(state, profile, headers, body, &translated, is_streaming).await?;
+    Ok((response, permit))
+}
+
 /// Try forwarding to a single provider with circuit breaker protection
-async fn try_with_circuit_breaker(
+pub(crate) async fn try_with_circuit_breaker(
+    state: &ProxyState,
+    profile: &ProfileConfig,
+    headers: &HeaderMap,
+    body: &Value,
+    is_streaming: bool,
+) -> anyhow::Result<Response> {
+    let translated = translate_for(profile, body)?;
+    if let Some(response) = cached_response(state, profile, body, &translated, is_streaming).await {
+        return Ok(response);
+    }
+    forward_with_breaker(state, profile, headers, body, &translated, is_streaming).await
+}
+
+/// 按 profile 翻译请求并过滤参数；缓存查询与各次转发（含 key pool 换 key 重试）共用这一份结果
+fn translate_for(profile: &ProfileConfig, body: &Value) -> Result<TranslatedRequest, ProxyError> {
+    let adapter = super::adapter::for_provider(&profile.provider_type);
+    let mut translated = adapter
+        .translate_request(body, profile)
+        .map_err(ProxyError::Translation)?;
+    adapter.filter_translated_body(&mut translated.body, profile);
+    Ok(translated)
+}
+
+/// 查询响应缓存（opt-in，仅确定性采样）：在限流与熔断之前进行，命中不消耗上游配额，
+/// 也不占用半开状态的探测名额
+async fn cached_response(
+    state: &ProxyState,
+    profile: &ProfileConfig,
+    body: &Value,
+    translated: &TranslatedRequest,
+    is_streaming: bool,
+) -> Option<Response> {
+    let config = state.config.read().await;
+    if !config.cache.applies_to(&profile.name) {
+        return None;
+    }
+    let upstream_model = upstream_model(&translated.body, profile);
+    let cache = super::cache::ResponseCache::for_request(
+        &config.cache,
+        &profile.name,
+        &upstream_model,
+        body,
+        &translated.body,
+    )?;
+    drop(config);
+
+    let message = cache.get().await?;
+    let mut response = super::cache::hit_response(&message, is_streaming).ok()?;
+    state
+        .metrics
+        .get_or_create(&profile.name)
+        .cache_hits
+        .fetch_add(1, Ordering::Relaxed);
+    tracing::info!(
+        profile = %profile.name,
+        key = %cache.key(),
+        streaming = %is_streaming,
+        "response cache hit"
+    );
+    set_model_header(&mut response, &upstream_model);
+    Some(response)
+}
+
+/// 实际发往上游的模型名（用量统计与缓存 key 均按此计）
+fn upstream_model(translated: &Value, profile: &ProfileConfig) -> String {
+    translated
+        .get("model")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&profile.default_model)
+        .to_string()
+}
+
+/// 熔断器准入后转发，并按结果更新被动健康与熔断状态
+async fn forward_with_breaker(
     state: &ProxyState,
     profile: &ProfileConfig,
     headers: &HeaderMap,
     body: &Value,
+    translated: &TranslatedRequest,
     is_streaming: bool,
 ) -> anyhow::Result<Response> {
+    let breaker_config = profile.circuit_breaker.clone().unwrap_or_default();
+
     // Check circuit breaker (single lock scope to avoid race condition)
-    {
+    let probe = {
         let mut map = state.circuit_breakers.write().await;
         let cb = map
             .entry(profile.name.clone())
-            .or_insert_with(Default::default);
-        if !cb.can_attempt() {
-            anyhow::bail!("circuit breaker open for profile '{}'", profile.name);
+            .or_insert_with(|| CircuitBreaker::from_config(&breaker_config));
+        cb.apply_config(&breaker_config);
+        let before = cb.state;
+        let admitted = cb.can_attempt();
+        state.circuit_events.transition(&profile.name, before, cb);
+        if !admitted {
+            return Err(ProxyError::CircuitBreakerOpen(profile.name.clone()).into());
         }
-    }
+        // 取得了半开探测名额：请求被取消时由守卫归还
+        (cb.state == fallback::CircuitState::HalfOpen)
+            .then(|| fallback::ProbeGuard::new(&state.circuit_breakers, &profile.name))
+    };
     // Lock is released here — forward can take seconds, don't hold it
 
-    let result = try_forward(state, profile, headers, body, is_streaming).await;
+    let started = Instant::now();
+    let result =
+        forward_with_key_pool(state, profile, headers, body, translated, is_streaming).await;
 
-    // Record result atomically
+    let outcome = fallback::classify(&result);
+
+    // 被动健康：按真实请求结果更新，只有上游 5xx / 传输错误计为不健康；
+    // 翻译失败、限流等与上游可用性无关的错误不更新
+    let health = match (&result, outcome) {
+        (Ok(resp), BreakerOutcome::Unavailable) => Some(Err(format!("HTTP {}", resp.status()))),
+        (Ok(_), _) => Some(Ok(started.elapsed().as_millis())),
+        (Err(e), BreakerOutcome::Unavailable) => Some(Err(e.to_string())),
+        (Err(_), _) => None,
+    };
+    if let Some(health) = health {
+        super::health::record_traffic(state, &profile.name, health).await;
+    }
+
+    // Record result atomically：只有上游可用性问题计入熔断
     let mut map = state.circuit_breakers.write().await;
     let cb = map
         .entry(profile.name.clone())
-        .or_insert_with(Default::default);
-    match &result {
-        Ok(_) => cb.record_success(),
-        Err(_) => cb.record_failure(),
+        .or_insert_with(|| CircuitBreaker::from_config(&breaker_config));
+    let before = cb.state;
+    match outcome {
+        BreakerOutcome::Success => cb.record_success(),
+        BreakerOutcome::Unavailable => cb.record_failure(),
+        BreakerOutcome::RateLimited => cb.record_rate_limited(),
+        BreakerOutcome::Ignored => cb.record_ignored(),
     }
+    state.circuit_events.transition(&profile.name, before, cb);
     drop(map);
+    if let Some(probe) = probe {
+        probe.disarm();
+    }
 
     result
 }
 
-/// Forward request to a single provider (used for both primary and backup).
-/// Uses ProviderAdapter trait to handle provider-specific translation.
-async fn try_forward(
+/// 配置了 key 池的 profile：按策略选 key 转发，401 / 429 时暂停该 key 并换下一个重试；
+/// 所有 key 都失败时返回错误，交由 backup_providers 接管
+async fn forward_with_key_pool(
     state: &ProxyState,
     profile: &ProfileConfig,
-    _headers: &HeaderMap,
+    headers: &HeaderMap,
     body: &Value,
+    translated: &TranslatedRequest,
     is_streaming: bool,
 ) -> anyhow::Result<Response> {
-    let adapter = super::adapter::for_provider(&profile.provider_type);
-    let mut translated = adapter.translate_request(body, profile)?;
-    adapter.filter_translated_body(&mut translated.body, profile);
+    let pool = match &profile.key_pool {
+        Some(pool_config) if profile.auth_type == AuthType::ApiKey => {
+            state.key_pools.get(&profile.name, pool_config)
+        }
+        _ => return try_forward(state, profile, headers, body, translated, is_streaming).await,
+    };
+    if pool.is_empty() {
+        return try_forward(state, profile, headers, body, translated, is_streaming).await;
+    }
 
-    let mut url = format!(
-        "{}{}",
-        profile.base_url.trim_end_matches('/'),
-        adapter.endpoint_path()
-    );
-    if !profile.query_params.is_empty() {
-        let qs: String = profile
-            .query_params
-            .iter()
-            .map(|(k, v)| format!("{}={}", urlencoding::encode(k), urlencoding::encode(v)))
-            .collect::<Vec<_>>()
-            .join("&");
-        url = if url.contains('?') {
-            format!("{url}&{qs}")
-        } else {
-            format!("{url}?{qs}")
+    let metrics = state.metrics.get_or_create(&profile.name);
+    let mut tried = Vec::new();
+    let mut last_status = None;
+    while let Some(key) = pool.select(&tried) {
+        tried.push(key.index);
+        let mut keyed = profile.clone();
+        keyed.api_key = key.secret;
+
+        let result = try_forward(state, &keyed, headers, body, translated, is_streaming).await;
+        let outcome = match &result {
+            Ok(resp) if resp.status() == StatusCode::UNAUTHORIZED => KeyOutcome::Unauthorized,
+            Ok(resp) if resp.status() == StatusCode::TOO_MANY_REQUESTS => KeyOutcome::RateLimited,
+            Ok(_) => KeyOutcome::Success,
+            Err(_) => KeyOutcome::Failure,
         };
+        metrics.record_key(&key.label, outcome);
+        if !matches!(outcome, KeyOutcome::Unauthorized | KeyOutcome::RateLimited) {
+            return result;
+        }
+
+        pool.bench(key.index);
+        let status = result.map(|r| r.status()).unwrap_or_default();
+        tracing::warn!(
+            profile = %profile.name,
+            key = %key.label,
+            status = %status,
+            "API key benched, retrying with next key"
+        );
+        last_status = Some(status);
     }
+
+    let status = last_status.unwrap_or_default();
+    Err(ProxyError::UpstreamError {
+        status: status.as_u16(),
+        body: format!(
+            "all {} API keys for profile '{}' exhausted (last status {status})",
+            tried.len(),
+            profile.name,
+        ),
+    }
+    .into())
+}
+
+/// Forward request to a single provider (used for both primary and backup).
+/// `translated` is the request already translated for this profile by its ProviderAdapter.
+async fn try_forward(
+    state: &ProxyState,
+    profile: &ProfileConfig,
+    headers: &HeaderMap,
+    body: &Value,
+    translated: &TranslatedRequest,
+    is_streaming: bool,
+) -> anyhow::Result<Response> {
+    let adapter = super::adapter::for_provider(&profile.provider_type);
+    let url = super::adapter::upstream_url(adapter.as_ref(), profile);
     let key_preview = super::util::format_key_preview(&profile.api_key);
 
     tracing::info!(
@@ -391,22 +934,70 @@ async fn try_forward(
         );
     }
 
-    let mut req = state
-        .http_client
-        .post(&url)
-        .header("content-type", "application/json");
-
-    req = adapter.apply_auth(req, profile);
-    req = adapter.apply_extra_headers(req, profile);
+    // 用量统计与缓存 key：按实际响应的 profile 与上游模型
+    let upstream_model = upstream_model(&translated.body, profile);
 
-    for (k, v) in &profile.custom_headers {
-        req = req.header(k.as_str(), v.as_str());
+    // 响应缓存（opt-in，仅确定性采样）：命中已在限流与熔断之前返回，此处只计未命中并在成功后写入
+    let cache = super::cache::ResponseCache::for_request(
+        &state.config.read().await.cache,
+        &profile.name,
+        &upstream_model,
+        body,
+        &translated.body,
+    );
+    if cache.is_some() {
+        state
+            .metrics
+            .get_or_create(&profile.name)
+            .cache_misses
+            .fetch_add(1, Ordering::Relaxed);
     }
 
-    req = req.json(&translated.body);
+    // 流量录制（opt-in）
+    let capture = super::capture::CaptureRecorder::start(
+        &state.config.read().await.capture,
+        profile,
+        headers,
+        body,
+        &url,
+        translated,
+        is_streaming,
+    );
 
-    let resp = req.send().await?;
+    let req = super::adapter::build_request(
+        adapter.as_ref(),
+        &state.http_client,
+        &url,
+        profile,
+        &translated.body,
+    );
+
+    let sent = match adapter.local_response(state, &translated.body, profile) {
+        Some(local) => local,
+        None => req.send().await.map_err(Into::into),
+    };
+    let resp = match sent {
+        Ok(resp) => resp,
+        Err(e) => {
+            if let Some(ref c) = capture {
+                c.set_error(&e.to_string());
+            }
+            return Err(e);
+        }
+    };
     let status = resp.status();
+    if let Some(ref c) = capture {
+        c.set_status(status.as_u16());
+    }
+
+    let usage_recorder = super::usage::UsageRecorder {
+        metrics: state.metrics.get_or_create(&profile.name),
+        budgets: state.budgets.clone(),
+        ledger: state.usage_ledger.clone(),
+        profile: profile.name.clone(),
+        price: state.config.read().await.model_price(&upstream_model),
+        model: upstream_model.clone(),
+    };
 
     tracing::info!(
         profile = %profile.name,
@@ -426,35 +1017,58 @@ async fn try_forward(
         );
 
         if is_streaming {
-            let stream = resp.bytes_stream();
-            let response = Response::builder()
+            let mut stream: super::adapter::ByteStream = Box::pin(resp.bytes_stream());
+            if let Some(ref c) = capture {
+                stream = c.tap_upstream(stream);
+            }
+            let mut stream = usage_recorder.tap_stream(stream);
+            let cache = cache.filter(|_| status.is_success());
+            if let Some(ref cache) = cache {
+                stream = cache.clone().tap_stream(stream);
+            }
+            let mut response = Response::builder()
                 .status(status.as_u16())
                 .header("content-type", "text/event-stream")
                 .header("cache-control", "no-cache")
                 .body(Body::from_stream(stream))
                 .map_err(|e| anyhow::anyhow!("failed to build response: {e}"))?;
+            mark_cache_miss(&mut response, &cache);
+            set_model_header(&mut response, &upstream_model);
             Ok(response)
         } else {
             let resp_bytes = resp.bytes().await?;
+            if let Some(ref c) = capture {
+                c.set_upstream_body(&resp_bytes);
+            }
             tracing::debug!(
                 profile = %profile.name,
                 len = resp_bytes.len(),
                 "passthrough: non-streaming response received"
             );
+            let cache = cache.filter(|_| status.is_success());
             if let Ok(resp_json) = serde_json::from_slice::<Value>(&resp_bytes) {
+                usage_recorder.record_response(&resp_json);
                 extract_and_store_context(state, &profile.name, &resp_json);
+                if let Some(ref cache) = cache {
+                    cache.store(resp_json).await;
+                }
             }
-            let response = Response::builder()
+            let mut response = Response::builder()
                 .status(status.as_u16())
                 .header("content-type", "application/json")
                 .body(Body::from(resp_bytes))
                 .map_err(|e| anyhow::anyhow!("failed to build response: {e}"))?;
+            mark_cache_miss(&mut response, &cache);
+            set_model_header(&mut response, &upstream_model);
             Ok(response)
         }
     } else {
         // Translated path: handle errors, then translate response
         if !status.is_success() {
             let err_body = resp.text().await.unwrap_or_default();
+            if let Some(ref c) = capture {
+                c.set_upstream_body(err_body.as_bytes());
+            }
 
             if status.is_client_error() {
                 // 4xx: non-retryable, translate to Anthropic error format
@@ -465,13 +1079,17 @@ async fn try_forward(
                     "client error (non-retryable)"
                 );
                 let anthropic_err = super::util::to_anthropic_error(status.as_u16(), &err_body);
-                let response = Response::builder()
+                if let Some(ref c) = capture {
+                    c.set_output(&anthropic_err);
+                }
+                let mut response = Response::builder()
                     .status(status.as_u16())
                     .header("content-type", "application/json")
                     .body(Body::from(
                         serde_json::to_vec(&anthropic_err).unwrap_or_default(),
                     ))
                     .map_err(|e| anyhow::anyhow!("failed to build error response: {e}"))?;
+                set_model_header(&mut response, &upstream_model);
                 return Ok(response);
             }
 
@@ -482,35 +1100,75 @@ async fn try_forward(
                 body = %err_body,
                 "upstream error"
             );
-            anyhow::bail!("upstream returned HTTP {status}: {err_body}");
+            return Err(ProxyError::UpstreamError {
+                status: status.as_u16(),
+                body: err_body,
+            }
+            .into());
         }
 
         if is_streaming {
-            let stream = resp.bytes_stream();
-            let translated_stream =
-                adapter.translate_stream(Box::pin(stream), translated.tool_name_map);
-            let response = Response::builder()
+            let mut stream: super::adapter::ByteStream = Box::pin(resp.bytes_stream());
+            if let Some(ref c) = capture {
+                stream = c.tap_upstream(stream);
+            }
+            let mut translated_stream =
+                adapter.translate_stream(stream, translated.tool_name_map.clone());
+            if let Some(ref c) = capture {
+                translated_stream = c.tap_output(translated_stream);
+            }
+            let mut translated_stream = usage_recorder.tap_stream(translated_stream);
+            if let Some(ref cache) = cache {
+                translated_stream = cache.clone().tap_stream(translated_stream);
+            }
+            let mut response = Response::builder()
                 .status(200)
                 .header("content-type", "text/event-stream")
                 .header("cache-control", "no-cache")
                 .body(Body::from_stream(translated_stream))
                 .map_err(|e| anyhow::anyhow!("failed to build response: {e}"))?;
+            mark_cache_miss(&mut response, &cache);
+            set_model_header(&mut response, &upstream_model);
             Ok(response)
         } else {
-            let resp_json: Value = resp.json().await?;
+            let resp_bytes = resp.bytes().await?;
+            if let Some(ref c) = capture {
+                c.set_upstream_body(&resp_bytes);
+            }
+            let resp_json: Value = serde_json::from_slice(&resp_bytes)?;
             let anthropic_resp =
                 adapter.translate_response(&resp_json, &translated.tool_name_map)?;
+            if let Some(ref c) = capture {
+                c.set_output(&anthropic_resp);
+            }
+            usage_recorder.record_response(&anthropic_resp);
             extract_and_store_context(state, &profile.name, &anthropic_resp);
-            let response = Response::builder()
+            let response_body = serde_json::to_vec(&anthropic_resp)?;
+            if let Some(ref cache) = cache {
+                cache.store(anthropic_resp).await;
+            }
+            let mut response = Response::builder()
                 .status(200)
                 .header("content-type", "application/json")
-                .body(Body::from(serde_json::to_vec(&anthropic_resp)?))
+                .body(Body::from(response_body))
                 .map_err(|e| anyhow::anyhow!("failed to build response: {e}"))?;
+            mark_cache_miss(&mut response, &cache);
+            set_model_header(&mut response, &upstream_model);
             Ok(response)
         }
     }
 }
 
+/// 可缓存但未命中的成功响应标记 x-claudex-cache: miss
+fn mark_cache_miss(response: &mut Response, cache: &Option<super::cache::ResponseCache>) {
+    if cache.is_some() {
+        response.headers_mut().insert(
+            super::cache::CACHE_HEADER,
+            axum::http::HeaderValue::from_static("miss"),
+        );
+    }
+}
+
 /// Extract assistant text from an Anthropic-format response and store for sharing.
 fn extract_and_store_context(state: &ProxyState, profile_name: &str, resp_body: &Value) {
     let text = resp_body
@@ -1,52 +1,465 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use anyhow::{bail, Context, Result};
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::config::{ClaudexConfig, ProfileConfig, ProviderType};
+use crate::oauth::AuthType;
 use crate::proxy::ProxyState;
 
+/// 主动探测的调度粒度：每个 tick 检查哪些 profile 已空闲满一个探测间隔
+const PROBE_TICK: Duration = Duration::from_secs(5);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 每个 profile 的健康检查配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HealthConfig {
+    /// 是否主动探测（被动的真实流量统计始终生效）
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 探测间隔（秒）；此期间内有真实流量的 profile 不再探测
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub probe: ProbeKind,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_interval_secs() -> u64 {
+    30
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            interval_secs: default_interval_secs(),
+            probe: ProbeKind::default(),
+        }
+    }
+}
+
+/// 探测方式
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeKind {
+    /// GET 模型列表（不消耗 token）
+    #[default]
+    Models,
+    /// 发送 max_tokens = 1 的补全请求，验证完整链路
+    Completion,
+    /// 仅建立 TCP 连接
+    Tcp,
+}
+
+/// 健康状态的来源
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthSource {
+    /// 真实请求结果
+    Traffic,
+    /// 主动探测
+    Probe,
+}
+
 #[derive(Debug, Clone)]
 pub struct HealthStatus {
     pub healthy: bool,
     pub latency_ms: Option<u128>,
-    pub last_check: Option<std::time::Instant>,
+    pub last_check: Option<Instant>,
     pub error: Option<String>,
+    pub source: HealthSource,
+    pub consecutive_failures: u32,
 }
 
 pub type HealthMap = HashMap<String, HealthStatus>;
 
+/// 记录一次结果（成功时为延迟毫秒数）
+pub fn apply_outcome(
+    map: &mut HealthMap,
+    profile: &str,
+    outcome: Result<u128, String>,
+    source: HealthSource,
+    now: Instant,
+) {
+    let failures = map.get(profile).map_or(0, |s| s.consecutive_failures);
+    let status = match outcome {
+        Ok(latency) => HealthStatus {
+            healthy: true,
+            latency_ms: Some(latency),
+            last_check: Some(now),
+            error: None,
+            source,
+            consecutive_failures: 0,
+        },
+        Err(e) => HealthStatus {
+            healthy: false,
+            latency_ms: None,
+            last_check: Some(now),
+            error: Some(e),
+            source,
+            consecutive_failures: failures + 1,
+        },
+    };
+    map.insert(profile.to_string(), status);
+}
+
+/// 被动健康：记录真实请求的结果
+pub async fn record_traffic(state: &ProxyState, profile: &str, outcome: Result<u128, String>) {
+    let mut map = state.health_status.write().await;
+    apply_outcome(
+        &mut map,
+        profile,
+        outcome,
+        HealthSource::Traffic,
+        Instant::now(),
+    );
+}
+
+/// 是否需要主动探测：从未检查过，或最近一次结果（含真实流量）已超过探测间隔
+fn probe_due(status: Option<&HealthStatus>, config: &HealthConfig, now: Instant) -> bool {
+    config.enabled
+        && status
+            .and_then(|s| s.last_check)
+            .is_none_or(|t| now.duration_since(t) >= Duration::from_secs(config.interval_secs))
+}
+
 pub fn spawn_health_checker(state: Arc<ProxyState>) {
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        let mut interval = tokio::time::interval(PROBE_TICK);
         loop {
             interval.tick().await;
-            check_all_profiles(&state).await;
+            probe_idle_profiles(&state).await;
         }
     });
 }
 
-async fn check_all_profiles(state: &ProxyState) {
+/// 探测到期（最近没有真实流量）的已启用 profile
+pub async fn probe_idle_profiles(state: &ProxyState) {
     let config = state.config.read().await;
     let profiles: Vec<_> = config.enabled_profiles().into_iter().cloned().collect();
     drop(config);
 
     for profile in &profiles {
-        let result = crate::config::profile::test_connectivity(profile).await;
-        let status = match result {
-            Ok(latency) => HealthStatus {
-                healthy: true,
-                latency_ms: Some(latency),
-                last_check: Some(std::time::Instant::now()),
-                error: None,
-            },
-            Err(e) => HealthStatus {
-                healthy: false,
-                latency_ms: None,
-                last_check: Some(std::time::Instant::now()),
-                error: Some(e.to_string()),
-            },
+        let health = profile.health.clone().unwrap_or_default();
+        let due = {
+            let map = state.health_status.read().await;
+            probe_due(map.get(&profile.name), &health, Instant::now())
         };
+        if !due {
+            continue;
+        }
+
+        let outcome = probe(state, profile, health.probe)
+            .await
+            .map_err(|e| e.to_string());
+        tracing::debug!(profile = %profile.name, probe = ?health.probe, ?outcome, "health probe");
 
         let mut map = state.health_status.write().await;
-        map.insert(profile.name.clone(), status);
+        apply_outcome(
+            &mut map,
+            &profile.name,
+            outcome,
+            HealthSource::Probe,
+            Instant::now(),
+        );
+    }
+}
+
+/// 执行一次探测，返回延迟（毫秒）
+pub async fn probe(state: &ProxyState, profile: &ProfileConfig, kind: ProbeKind) -> Result<u128> {
+    // Mock provider 不走网络
+    if profile.provider_type == ProviderType::Mock {
+        return crate::config::profile::test_connectivity(profile).await;
+    }
+
+    let mut profile = profile.clone();
+    if kind != ProbeKind::Tcp && profile.auth_type == AuthType::OAuth {
+        let token = state.token_manager.get_token(&profile).await?;
+        crate::oauth::manager::apply_token_to_profile(&mut profile, &token);
+    }
+    // 只配置了 key_pool 的 profile：与真实请求一样从池中选一个 key
+    if kind != ProbeKind::Tcp && profile.auth_type == AuthType::ApiKey {
+        if let Some(pool_config) = &profile.key_pool {
+            if let Some(key) = state.key_pools.get(&profile.name, pool_config).select(&[]) {
+                profile.api_key = key.secret;
+            }
+        }
+    }
+
+    match kind {
+        ProbeKind::Models => crate::config::profile::test_connectivity(&profile).await,
+        ProbeKind::Completion => probe_completion(state, &profile).await,
+        ProbeKind::Tcp => probe_tcp(&profile).await,
+    }
+}
+
+async fn probe_completion(state: &ProxyState, profile: &ProfileConfig) -> Result<u128> {
+    let adapter = super::adapter::for_provider(&profile.provider_type);
+    let body = json!({
+        "model": profile.default_model,
+        "max_tokens": 1,
+        "messages": [{"role": "user", "content": "ping"}]
+    });
+    let mut translated = adapter.translate_request(&body, profile)?;
+    adapter.filter_translated_body(&mut translated.body, profile);
+    let url = super::adapter::upstream_url(adapter.as_ref(), profile);
+
+    let start = Instant::now();
+    let resp = super::adapter::build_request(
+        adapter.as_ref(),
+        &state.http_client,
+        &url,
+        profile,
+        &translated.body,
+    )
+    .timeout(PROBE_TIMEOUT)
+    .send()
+    .await?;
+    let latency = start.elapsed().as_millis();
+    if !resp.status().is_success() {
+        bail!("HTTP {}", resp.status());
+    }
+    Ok(latency)
+}
+
+async fn probe_tcp(profile: &ProfileConfig) -> Result<u128> {
+    let url = reqwest::Url::parse(&profile.base_url).context("invalid base_url")?;
+    let host = url.host_str().context("base_url has no host")?.to_string();
+    let port = url
+        .port_or_known_default()
+        .context("base_url has no port")?;
+
+    let start = Instant::now();
+    tokio::time::timeout(PROBE_TIMEOUT, tokio::net::TcpStream::connect((host, port)))
+        .await
+        .context("connect timed out")??;
+    Ok(start.elapsed().as_millis())
+}
+
+/// HTTP 暴露的健康状态
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub latency_ms: Option<u128>,
+    /// 距最近一次结果的秒数
+    pub age_secs: Option<u64>,
+    pub error: Option<String>,
+    pub source: HealthSource,
+    pub consecutive_failures: u32,
+}
+
+pub fn snapshot(map: &HealthMap, now: Instant) -> BTreeMap<String, HealthReport> {
+    map.iter()
+        .map(|(name, s)| {
+            (
+                name.clone(),
+                HealthReport {
+                    healthy: s.healthy,
+                    latency_ms: s.latency_ms,
+                    age_secs: s.last_check.map(|t| now.duration_since(t).as_secs()),
+                    error: s.error.clone(),
+                    source: s.source,
+                    consecutive_failures: s.consecutive_failures,
+                },
+            )
+        })
+        .collect()
+}
+
+/// `GET /health/profiles`
+pub async fn profiles_health(
+    State(state): State<Arc<ProxyState>>,
+) -> Json<BTreeMap<String, HealthReport>> {
+    let map = state.health_status.read().await;
+    Json(snapshot(&map, Instant::now()))
+}
+
+/// 从运行中的 proxy 拉取健康状态
+pub async fn fetch_remote(config: &ClaudexConfig) -> Result<BTreeMap<String, HealthReport>> {
+    let url = format!(
+        "http://{}:{}/health/profiles",
+        config.proxy_host, config.proxy_port
+    );
+    let resp = reqwest::Client::new()
+        .get(&url)
+        .timeout(Duration::from_secs(2))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(resp.json().await?)
+}
+
+/// 远端状态转回 HealthMap（dashboard 使用）
+pub fn from_reports(reports: BTreeMap<String, HealthReport>, now: Instant) -> HealthMap {
+    reports
+        .into_iter()
+        .map(|(name, r)| {
+            let status = HealthStatus {
+                healthy: r.healthy,
+                latency_ms: r.latency_ms,
+                last_check: r
+                    .age_secs
+                    .and_then(|age| now.checked_sub(Duration::from_secs(age))),
+                error: r.error,
+                source: r.source,
+                consecutive_failures: r.consecutive_failures,
+            };
+            (name, status)
+        })
+        .collect()
+}
+
+/// `claudex proxy status` 的健康表
+pub async fn print_health_report(config: &ClaudexConfig) -> Result<()> {
+    let reports = fetch_remote(config).await?;
+    if reports.is_empty() {
+        println!("No health data yet.");
+        return Ok(());
+    }
+    println!();
+    println!(
+        "{:<16} {:<8} {:>9} {:>8} {:<8} ERROR",
+        "PROFILE", "STATUS", "LATENCY", "AGE", "SOURCE"
+    );
+    for (name, r) in &reports {
+        let status = if r.healthy { "ok" } else { "DOWN" };
+        let latency = r
+            .latency_ms
+            .map(|l| format!("{l}ms"))
+            .unwrap_or_else(|| "--".to_string());
+        let age = r
+            .age_secs
+            .map(|a| format!("{a}s"))
+            .unwrap_or_else(|| "--".to_string());
+        let source = match r.source {
+            HealthSource::Traffic => "traffic",
+            HealthSource::Probe => "probe",
+        };
+        println!(
+            "{name:<16} {status:<8} {latency:>9} {age:>8} {source:<8} {}",
+            r.error.as_deref().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_outcome_tracks_failures() {
+        let mut map = HealthMap::new();
+        let now = Instant::now();
+        apply_outcome(
+            &mut map,
+            "p",
+            Err("HTTP 503".into()),
+            HealthSource::Traffic,
+            now,
+        );
+        apply_outcome(
+            &mut map,
+            "p",
+            Err("timeout".into()),
+            HealthSource::Probe,
+            now,
+        );
+        let s = &map["p"];
+        assert!(!s.healthy);
+        assert_eq!(s.consecutive_failures, 2);
+        assert_eq!(s.error.as_deref(), Some("timeout"));
+
+        apply_outcome(&mut map, "p", Ok(42), HealthSource::Traffic, now);
+        let s = &map["p"];
+        assert!(s.healthy);
+        assert_eq!(s.latency_ms, Some(42));
+        assert_eq!(s.consecutive_failures, 0);
+        assert_eq!(s.source, HealthSource::Traffic);
+    }
+
+    #[test]
+    fn test_probe_only_idle_profiles() {
+        let config = HealthConfig {
+            interval_secs: 30,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        assert!(probe_due(None, &config, start));
+
+        let mut map = HealthMap::new();
+        apply_outcome(&mut map, "p", Ok(10), HealthSource::Traffic, start);
+        // 最近有真实流量，不探测
+        assert!(!probe_due(
+            map.get("p"),
+            &config,
+            start + Duration::from_secs(10)
+        ));
+        assert!(probe_due(
+            map.get("p"),
+            &config,
+            start + Duration::from_secs(31)
+        ));
+
+        let disabled = HealthConfig {
+            enabled: false,
+            ..Default::default()
+        };
+        assert!(!probe_due(None, &disabled, start));
+    }
+
+    #[test]
+    fn test_report_roundtrip() {
+        let mut map = HealthMap::new();
+        let start = Instant::now();
+        apply_outcome(&mut map, "a", Ok(120), HealthSource::Probe, start);
+        apply_outcome(
+            &mut map,
+            "b",
+            Err("HTTP 500".into()),
+            HealthSource::Traffic,
+            start,
+        );
+
+        let now = start + Duration::from_secs(7);
+        let reports = snapshot(&map, now);
+        assert_eq!(reports["a"].age_secs, Some(7));
+        let json = serde_json::to_string(&reports).unwrap();
+        assert!(json.contains("\"source\":\"traffic\""));
+
+        let restored = from_reports(serde_json::from_str(&json).unwrap(), now);
+        assert_eq!(restored["a"].latency_ms, Some(120));
+        assert!(!restored["b"].healthy);
+        assert_eq!(restored["b"].consecutive_failures, 1);
+    }
+
+    #[test]
+    fn test_health_config_defaults() {
+        let cfg: HealthConfig = toml::from_str("probe = \"tcp\"").unwrap();
+        assert!(cfg.enabled);
+        assert_eq!(cfg.interval_secs, 30);
+        assert_eq!(cfg.probe, ProbeKind::Tcp);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let profile = ProfileConfig {
+            base_url: format!("http://{addr}/v1"),
+            ..Default::default()
+        };
+        assert!(probe_tcp(&profile).await.is_ok());
+        drop(listener);
+        assert!(probe_tcp(&profile).await.is_err());
     }
 }
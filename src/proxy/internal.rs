@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use axum::http::{HeaderMap, StatusCode};
+use serde_json::{json, Value};
+
+use crate::oauth::AuthType;
+use crate::proxy::pricing::TokenUsage;
+use crate::proxy::ProxyState;
+
+/// 代理内部调用（对话摘要、意图分类）的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct InternalReply {
+    pub text: String,
+    pub usage: Option<TokenUsage>,
+}
+
+/// 内部调用的请求参数
+#[derive(Debug, Clone)]
+pub struct InternalRequest<'a> {
+    /// 使用的 profile
+    pub profile: &'a str,
+    /// 覆盖 profile 的 default_model（空字符串表示不覆盖）
+    pub model: &'a str,
+    pub system: &'a str,
+    pub prompt: &'a str,
+    pub max_tokens: u32,
+    pub temperature: f64,
+}
+
+/// 以指定 profile 发起一次非流式补全：与用户流量走同一条 ProviderAdapter 链路
+/// （协议翻译、OAuth token 刷新、key 池、custom headers、query params 与熔断器），
+/// 用量记在该 profile 上
+pub async fn complete(state: &ProxyState, req: &InternalRequest<'_>) -> Result<InternalReply> {
+    let mut profile = state
+        .config
+        .read()
+        .await
+        .find_profile(req.profile)
+        .cloned()
+        .with_context(|| format!("profile '{}' not found", req.profile))?;
+    if !profile.enabled {
+        bail!("profile '{}' is disabled", profile.name);
+    }
+    if profile.auth_type == AuthType::OAuth {
+        let token = state.token_manager.get_token(&profile).await?;
+        crate::oauth::manager::apply_token_to_profile(&mut profile, &token);
+    }
+
+    let model = if req.model.is_empty() {
+        profile.default_model.as_str()
+    } else {
+        req.model
+    };
+    let body = json!({
+        "model": model,
+        "system": req.system,
+        "messages": [{"role": "user", "content": req.prompt}],
+        "max_tokens": req.max_tokens,
+        "temperature": req.temperature,
+    });
+
+    let headers = HeaderMap::new();
+    let mut response =
+        super::handler::try_with_circuit_breaker(state, &profile, &headers, &body, false).await?;
+
+    // OAuth token 可能已过期：清除缓存重试一次
+    if response.status() == StatusCode::UNAUTHORIZED && profile.auth_type == AuthType::OAuth {
+        let token = state.token_manager.invalidate_and_retry(&profile).await?;
+        crate::oauth::manager::apply_token_to_profile(&mut profile, &token);
+        response =
+            super::handler::try_with_circuit_breaker(state, &profile, &headers, &body, false)
+                .await?;
+    }
+
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await?;
+    if !status.is_success() {
+        bail!(
+            "profile '{}' returned HTTP {status}: {}",
+            profile.name,
+            String::from_utf8_lossy(&bytes)
+        );
+    }
+    let message: Value = serde_json::from_slice(&bytes)?;
+    Ok(reply_from_message(&message))
+}
+
+/// 从 Anthropic 格式的响应中取出文本与用量
+fn reply_from_message(message: &Value) -> InternalReply {
+    let text = message
+        .get("content")
+        .and_then(|c| c.as_array())
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter(|b| b.get("type").and_then(|t| t.as_str()) == Some("text"))
+                .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+                .collect::<Vec<_>>()
+                .join("")
+        })
+        .unwrap_or_default();
+    InternalReply {
+        text,
+        usage: message.get("usage").map(TokenUsage::from_anthropic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reply_from_message() {
+        let reply = reply_from_message(&json!({
+            "content": [
+                {"type": "thinking", "thinking": "hmm"},
+                {"type": "text", "text": "co"},
+                {"type": "text", "text": "de"}
+            ],
+            "usage": {"input_tokens": 20, "output_tokens": 1}
+        }));
+        assert_eq!(reply.text, "code");
+        assert_eq!(reply.usage.unwrap().total(), 21);
+        assert_eq!(reply_from_message(&json!({})).text, "");
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::util::format_key_preview;
+
+/// keyring 引用前缀：`keyring:<entry>` 从系统 keyring（service "claudex"）读取
+const KEYRING_PREFIX: &str = "keyring:";
+const KEYRING_SERVICE: &str = "claudex";
+
+/// 多 API key 轮换配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct KeyPoolConfig {
+    /// API key 列表，`keyring:<entry>` 表示从系统 keyring 读取
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub strategy: KeyStrategy,
+    /// 遇到 401 / 429 的 key 暂停使用的时长（秒）
+    #[serde(default = "default_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_cooldown_secs() -> u64 {
+    60
+}
+
+impl Default for KeyPoolConfig {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            strategy: KeyStrategy::default(),
+            cooldown_secs: default_cooldown_secs(),
+        }
+    }
+}
+
+/// key 选择策略
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyStrategy {
+    /// 依次轮换
+    #[default]
+    RoundRobin,
+    /// 优先使用最久未被限流的 key
+    LeastRecentlyLimited,
+}
+
+/// 选中的 key
+#[derive(Debug, Clone)]
+pub struct SelectedKey {
+    pub index: usize,
+    /// 用于日志与 metrics 的标识（不含完整密钥）
+    pub label: String,
+    pub secret: String,
+}
+
+#[derive(Debug)]
+struct KeyState {
+    label: String,
+    secret: String,
+    benched_until: Option<Instant>,
+    last_limited: Option<Instant>,
+}
+
+impl KeyState {
+    fn is_benched(&self, now: Instant) -> bool {
+        self.benched_until.is_some_and(|until| until > now)
+    }
+}
+
+#[derive(Debug)]
+struct PoolState {
+    keys: Vec<KeyState>,
+    cursor: usize,
+}
+
+/// 单个 profile 的 key 池
+#[derive(Debug)]
+pub struct KeyPool {
+    config: KeyPoolConfig,
+    state: Mutex<PoolState>,
+}
+
+impl KeyPool {
+    pub fn new(config: KeyPoolConfig) -> Self {
+        let keys = config
+            .keys
+            .iter()
+            .filter_map(|entry| match resolve_key(entry) {
+                Ok(secret) => Some(KeyState {
+                    label: key_label(entry, &secret),
+                    secret,
+                    benched_until: None,
+                    last_limited: None,
+                }),
+                Err(e) => {
+                    tracing::warn!(key = %entry, error = %e, "skipping unusable pool key");
+                    None
+                }
+            })
+            .collect();
+        Self {
+            config,
+            state: Mutex::new(PoolState { keys, cursor: 0 }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 选择下一个 key，跳过本次请求已尝试过的 key。
+    /// 优先选未被暂停的 key；首次选择时若全部暂停，退而使用最早恢复的那个。
+    pub fn select(&self, tried: &[usize]) -> Option<SelectedKey> {
+        self.select_at(tried, Instant::now())
+    }
+
+    fn select_at(&self, tried: &[usize], now: Instant) -> Option<SelectedKey> {
+        let mut state = self.state.lock().unwrap();
+        let n = state.keys.len();
+        if n == 0 {
+            return None;
+        }
+        // 从游标开始的轮换顺序
+        let order: Vec<usize> = (0..n)
+            .map(|i| (state.cursor + i) % n)
+            .filter(|i| !tried.contains(i))
+            .collect();
+        let available: Vec<usize> = order
+            .iter()
+            .copied()
+            .filter(|&i| !state.keys[i].is_benched(now))
+            .collect();
+
+        let index = if !available.is_empty() {
+            match self.config.strategy {
+                KeyStrategy::RoundRobin => available[0],
+                // min_by_key 保留第一个最小值，None（从未限流）排在最前
+                KeyStrategy::LeastRecentlyLimited => *available
+                    .iter()
+                    .min_by_key(|&&i| state.keys[i].last_limited)
+                    .unwrap(),
+            }
+        } else if tried.is_empty() {
+            *order
+                .iter()
+                .min_by_key(|&&i| state.keys[i].benched_until)
+                .unwrap()
+        } else {
+            return None;
+        };
+
+        state.cursor = (index + 1) % n;
+        let key = &state.keys[index];
+        Some(SelectedKey {
+            index,
+            label: key.label.clone(),
+            secret: key.secret.clone(),
+        })
+    }
+
+    /// 暂停 key（401 / 429 后冷却）
+    pub fn bench(&self, index: usize) {
+        self.bench_at(index, Instant::now());
+    }
+
+    fn bench_at(&self, index: usize, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        if let Some(key) = state.keys.get_mut(index) {
+            key.benched_until = Some(now + cooldown);
+            key.last_limited = Some(now);
+        }
+    }
+}
+
+/// 解析 key 条目：字面量或 keyring 引用
+fn resolve_key(entry: &str) -> anyhow::Result<String> {
+    match entry.strip_prefix(KEYRING_PREFIX) {
+        Some(name) => {
+            let secret = keyring::Entry::new(KEYRING_SERVICE, name)?.get_password()?;
+            Ok(secret)
+        }
+        None if entry.is_empty() => anyhow::bail!("empty key"),
+        None => Ok(entry.to_string()),
+    }
+}
+
+fn key_label(entry: &str, secret: &str) -> String {
+    if entry.starts_with(KEYRING_PREFIX) {
+        entry.to_string()
+    } else {
+        format_key_preview(secret)
+    }
+}
+
+/// 所有 profile 的 key 池（配置变化时重建）
+#[derive(Default, Clone)]
+pub struct KeyPoolMap {
+    inner: Arc<Mutex<HashMap<String, Arc<KeyPool>>>>,
+}
+
+impl KeyPoolMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, profile: &str, config: &KeyPoolConfig) -> Arc<KeyPool> {
+        let mut map = self.inner.lock().unwrap();
+        match map.get(profile) {
+            Some(pool) if pool.config == *config => pool.clone(),
+            _ => {
+                let pool = Arc::new(KeyPool::new(config.clone()));
+                map.insert(profile.to_string(), pool.clone());
+                pool
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(strategy: KeyStrategy) -> KeyPool {
+        KeyPool::new(KeyPoolConfig {
+            keys: vec![
+                "sk-key-aaaa-1111".to_string(),
+                "sk-key-bbbb-2222".to_string(),
+                "sk-key-cccc-3333".to_string(),
+            ],
+            strategy,
+            cooldown_secs: 60,
+        })
+    }
+
+    #[test]
+    fn test_round_robin_rotates() {
+        let pool = pool(KeyStrategy::RoundRobin);
+        let picks: Vec<usize> = (0..4).map(|_| pool.select(&[]).unwrap().index).collect();
+        assert_eq!(picks, vec![0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn test_benched_key_skipped_until_cooldown() {
+        let pool = pool(KeyStrategy::RoundRobin);
+        let now = Instant::now();
+        pool.bench_at(1, now);
+        let picks: Vec<usize> = (0..3)
+            .map(|_| pool.select_at(&[], now).unwrap().index)
+            .collect();
+        assert_eq!(picks, vec![0, 2, 0]);
+        let later = now + Duration::from_secs(61);
+        let picks: Vec<usize> = (0..3)
+            .map(|_| pool.select_at(&[], later).unwrap().index)
+            .collect();
+        assert!(picks.contains(&1));
+    }
+
+    #[test]
+    fn test_retry_skips_tried_and_exhausts() {
+        let pool = pool(KeyStrategy::RoundRobin);
+        let first = pool.select(&[]).unwrap();
+        pool.bench(first.index);
+        let second = pool.select(&[first.index]).unwrap();
+        assert_ne!(first.index, second.index);
+        pool.bench(second.index);
+        let third = pool.select(&[first.index, second.index]).unwrap();
+        pool.bench(third.index);
+        assert!(pool
+            .select(&[first.index, second.index, third.index])
+            .is_none());
+    }
+
+    #[test]
+    fn test_all_benched_first_pick_uses_earliest_recovery() {
+        let pool = pool(KeyStrategy::RoundRobin);
+        let now = Instant::now();
+        pool.bench_at(0, now + Duration::from_secs(2));
+        pool.bench_at(1, now);
+        pool.bench_at(2, now + Duration::from_secs(1));
+        assert_eq!(pool.select_at(&[], now).unwrap().index, 1);
+        // 重试时不再使用仍在冷却中的 key
+        assert!(pool.select_at(&[1], now).is_none());
+    }
+
+    #[test]
+    fn test_least_recently_limited_prefers_never_limited() {
+        let pool = pool(KeyStrategy::LeastRecentlyLimited);
+        let start = Instant::now();
+        pool.bench_at(0, start);
+        pool.bench_at(1, start + Duration::from_secs(100));
+        let now = start + Duration::from_secs(300);
+        // key 2 从未限流，优先
+        assert_eq!(pool.select_at(&[], now).unwrap().index, 2);
+        // 排除 key 2 后选最早被限流的 key 0
+        assert_eq!(pool.select_at(&[2], now).unwrap().index, 0);
+    }
+
+    #[test]
+    fn test_labels_do_not_leak_secrets() {
+        let pool = pool(KeyStrategy::RoundRobin);
+        let key = pool.select(&[]).unwrap();
+        assert_eq!(key.secret, "sk-key-aaaa-1111");
+        assert_eq!(key.label, "sk-k...1111");
+    }
+
+    #[test]
+    fn test_empty_entries_skipped() {
+        let pool = KeyPool::new(KeyPoolConfig {
+            keys: vec![String::new(), "sk-only".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_map_rebuilds_on_config_change() {
+        let map = KeyPoolMap::new();
+        let mut cfg = KeyPoolConfig {
+            keys: vec!["a-key".to_string()],
+            ..Default::default()
+        };
+        let first = map.get("p", &cfg);
+        assert!(Arc::ptr_eq(&first, &map.get("p", &cfg)));
+        cfg.keys.push("b-key".to_string());
+        let rebuilt = map.get("p", &cfg);
+        assert!(!Arc::ptr_eq(&first, &rebuilt));
+        assert_eq!(rebuilt.len(), 2);
+    }
+}
@@ -1,17 +1,60 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use axum::body::Body;
+use axum::response::Response;
+use futures::StreamExt;
+
+use super::pricing::TokenUsage;
+
 #[derive(Debug)]
 pub struct ProfileMetrics {
     pub total_requests: AtomicU64,
     pub total_tokens: AtomicU64,
     pub success_count: AtomicU64,
     pub failure_count: AtomicU64,
+    pub input_tokens: AtomicU64,
+    pub output_tokens: AtomicU64,
+    /// 估算费用，单位为百万分之一美元
+    pub cost_micros: AtomicU64,
+    /// 当前因限流而排队等待的请求数
+    pub queue_depth: AtomicU64,
+    /// 因限流被拒绝的请求数
+    pub rate_limited: AtomicU64,
+    /// 响应缓存命中 / 未命中次数（仅统计可缓存请求）
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    /// 对话摘要缓存命中 / 调用摘要模型次数（记在摘要 profile 上）
+    pub summary_hits: AtomicU64,
+    pub summary_misses: AtomicU64,
+    /// 当前进行中的请求数（响应体传输完毕才减一）
+    pub in_flight: AtomicU64,
+    /// key 池中各 API key 的统计（按 key 标识）
+    pub keys: Mutex<BTreeMap<String, KeyStats>>,
     pub latencies: Mutex<VecDeque<Duration>>,
 }
 
+/// 单个 API key 的请求统计
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyStats {
+    pub requests: u64,
+    pub successes: u64,
+    pub failures: u64,
+    pub unauthorized: u64,
+    pub rate_limited: u64,
+}
+
+/// 单次请求在某个 key 上的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOutcome {
+    Success,
+    Failure,
+    Unauthorized,
+    RateLimited,
+}
+
 impl ProfileMetrics {
     pub fn new() -> Self {
         Self {
@@ -19,6 +62,17 @@ impl ProfileMetrics {
             total_tokens: AtomicU64::new(0),
             success_count: AtomicU64::new(0),
             failure_count: AtomicU64::new(0),
+            input_tokens: AtomicU64::new(0),
+            output_tokens: AtomicU64::new(0),
+            cost_micros: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            summary_hits: AtomicU64::new(0),
+            summary_misses: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            keys: Mutex::new(BTreeMap::new()),
             latencies: Mutex::new(VecDeque::with_capacity(100)),
         }
     }
@@ -41,6 +95,38 @@ impl ProfileMetrics {
         }
     }
 
+    /// 记录 key 池中某个 key 的请求结果
+    pub fn record_key(&self, label: &str, outcome: KeyOutcome) {
+        if let Ok(mut keys) = self.keys.lock() {
+            let stats = keys.entry(label.to_string()).or_default();
+            stats.requests += 1;
+            match outcome {
+                KeyOutcome::Success => stats.successes += 1,
+                KeyOutcome::Failure => stats.failures += 1,
+                KeyOutcome::Unauthorized => stats.unauthorized += 1,
+                KeyOutcome::RateLimited => stats.rate_limited += 1,
+            }
+        }
+    }
+
+    /// 记录上游返回的 token 用量与估算费用
+    pub fn record_usage(&self, usage: &TokenUsage, cost_usd: f64) {
+        self.total_tokens
+            .fetch_add(usage.total(), Ordering::Relaxed);
+        self.input_tokens.fetch_add(
+            usage.input_tokens + usage.cache_read_tokens + usage.cache_write_tokens,
+            Ordering::Relaxed,
+        );
+        self.output_tokens
+            .fetch_add(usage.output_tokens, Ordering::Relaxed);
+        self.cost_micros
+            .fetch_add((cost_usd * 1_000_000.0).round() as u64, Ordering::Relaxed);
+    }
+
+    pub fn total_cost(&self) -> f64 {
+        self.cost_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+    }
+
     pub fn avg_latency(&self) -> Option<Duration> {
         let lat = self.latencies.lock().ok()?;
         if lat.is_empty() {
@@ -60,7 +146,41 @@ impl ProfileMetrics {
     }
 }
 
-#[derive(Debug, Clone)]
+/// 进行中请求计数：创建时加一，drop 时减一
+pub struct InFlightGuard {
+    metrics: Arc<ProfileMetrics>,
+}
+
+impl InFlightGuard {
+    pub fn new(metrics: Arc<ProfileMetrics>) -> Self {
+        metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self { metrics }
+    }
+
+    /// 绑定到响应体上，流式响应结束后才计为完成
+    pub fn hold_until_body_end(self, response: Response) -> Response {
+        let (parts, body) = response.into_parts();
+        let stream = body.into_data_stream().map(move |chunk| {
+            let _held = &self;
+            chunk
+        });
+        Response::from_parts(parts, Body::from_stream(stream))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for ProfileMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct MetricsStore {
     inner: Arc<Mutex<HashMap<String, Arc<ProfileMetrics>>>>,
 }
@@ -118,6 +238,25 @@ mod tests {
         assert_eq!(m.failure_count.load(Ordering::Relaxed), 1);
     }
 
+    #[test]
+    fn test_record_key_outcomes() {
+        let m = ProfileMetrics::new();
+        m.record_key("sk-a...1111", KeyOutcome::RateLimited);
+        m.record_key("sk-a...1111", KeyOutcome::Success);
+        m.record_key("sk-b...2222", KeyOutcome::Unauthorized);
+        let keys = m.keys.lock().unwrap();
+        assert_eq!(
+            keys["sk-a...1111"],
+            KeyStats {
+                requests: 2,
+                successes: 1,
+                rate_limited: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(keys["sk-b...2222"].unauthorized, 1);
+    }
+
     #[test]
     fn test_avg_latency_empty() {
         let m = ProfileMetrics::new();
@@ -169,6 +308,23 @@ mod tests {
         assert!((rate - 66.67).abs() < 0.1);
     }
 
+    #[test]
+    fn test_record_usage_accumulates_cost() {
+        let m = ProfileMetrics::new();
+        let usage = TokenUsage {
+            input_tokens: 100,
+            output_tokens: 20,
+            cache_read_tokens: 50,
+            cache_write_tokens: 0,
+        };
+        m.record_usage(&usage, 0.0125);
+        m.record_usage(&usage, 0.0125);
+        assert_eq!(m.total_tokens.load(Ordering::Relaxed), 340);
+        assert_eq!(m.input_tokens.load(Ordering::Relaxed), 300);
+        assert_eq!(m.output_tokens.load(Ordering::Relaxed), 40);
+        assert!((m.total_cost() - 0.025).abs() < 1e-9);
+    }
+
     #[test]
     fn test_metrics_store_get_or_create() {
         let store = MetricsStore::new();
@@ -1,12 +1,24 @@
 pub mod adapter;
+pub mod budget;
+pub mod cache;
+pub mod capture;
 pub mod context_engine;
+pub mod context_window;
+pub mod count_tokens;
 pub mod error;
 pub mod fallback;
 pub mod handler;
 pub mod health;
+pub mod internal;
+pub mod key_pool;
 pub mod metrics;
 pub mod models;
+pub mod pool;
+pub mod pricing;
+pub mod rate_limit;
+pub mod route_info;
 pub mod translate;
+pub mod usage;
 pub mod util;
 
 use std::sync::Arc;
@@ -17,8 +29,9 @@ use axum::Router;
 use tokio::sync::RwLock;
 
 use crate::config::ClaudexConfig;
-use crate::context::rag::RagIndex;
+use crate::context::rag::{EmbeddingEndpoint, RagIndex};
 use crate::context::sharing::SharedContext;
+use crate::context::RetrievalMode;
 use metrics::MetricsStore;
 
 pub struct ProxyState {
@@ -27,9 +40,67 @@ pub struct ProxyState {
     pub http_client: reqwest::Client,
     pub health_status: Arc<RwLock<health::HealthMap>>,
     pub circuit_breakers: fallback::CircuitBreakerMap,
+    pub circuit_events: fallback::CircuitEventLog,
     pub shared_context: SharedContext,
     pub rag_index: Option<RagIndex>,
     pub token_manager: crate::oauth::manager::TokenManager,
+    pub budgets: budget::BudgetTracker,
+    pub rate_limiters: rate_limit::RateLimiterMap,
+    pub key_pools: key_pool::KeyPoolMap,
+    pub pool_cursors: pool::PoolCursors,
+    /// auto 路由的会话粘性缓存
+    pub sticky_routes: crate::router::sticky::StickyRoutes,
+    /// 对话压缩的摘要缓存（按被摘要前缀的哈希）
+    pub summary_cache: crate::context::compression::SummaryCache,
+    /// 用量账本路径（None 表示不落盘，测试默认如此）
+    pub usage_ledger: Option<std::path::PathBuf>,
+    /// Mock provider 已编译的 fixture 与 sequence 计数
+    pub mock: adapter::mock::MockState,
+}
+
+impl ProxyState {
+    /// 构造 proxy 状态：不构建 RAG 索引、不加载用量账本、不启动健康检查，
+    /// `start_proxy` 在此基础上补齐，测试可直接使用
+    pub fn new(config: ClaudexConfig, http_client: reqwest::Client) -> Self {
+        let token_manager = crate::oauth::manager::TokenManager::new(http_client.clone());
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            metrics: MetricsStore::new(),
+            http_client,
+            health_status: Arc::new(RwLock::new(health::HealthMap::new())),
+            circuit_breakers: fallback::new_circuit_breaker_map(),
+            circuit_events: fallback::CircuitEventLog::new(),
+            shared_context: SharedContext::new(),
+            rag_index: None,
+            token_manager,
+            budgets: budget::BudgetTracker::new(),
+            rate_limiters: rate_limit::RateLimiterMap::new(),
+            key_pools: key_pool::KeyPoolMap::new(),
+            pool_cursors: pool::PoolCursors::new(),
+            sticky_routes: crate::router::sticky::StickyRoutes::new(),
+            summary_cache: crate::context::compression::SummaryCache::new(),
+            usage_ledger: None,
+            mock: adapter::mock::MockState::new(),
+        }
+    }
+}
+
+/// 构建 proxy 路由
+pub fn build_router(state: Arc<ProxyState>) -> Router {
+    Router::new()
+        .route("/v1/models", get(models::list_models))
+        .route(
+            "/proxy/{profile}/v1/messages",
+            post(handler::handle_messages),
+        )
+        .route(
+            "/proxy/{profile}/v1/messages/count_tokens",
+            post(count_tokens::count_tokens),
+        )
+        .route("/health", get(|| async { "ok" }))
+        .route("/health/profiles", get(health::profiles_health))
+        .route("/health/circuits", get(fallback::circuit_status))
+        .with_state(state)
 }
 
 /// 获取 proxy 日志文件路径（~/.cache/claudex/proxy-{timestamp}-{pid}.log）
@@ -50,51 +121,50 @@ pub async fn start_proxy(config: ClaudexConfig, port_override: Option<u16>) -> R
         .timeout(std::time::Duration::from_secs(300))
         .build()?;
 
-    // Build RAG index if enabled
+    // RAG 索引：先加载磁盘上的持久化索引，再在后台增量同步（只为变化的文件计算 embedding）；
+    // embedding profile 不可用时仍建立分块供 BM25 检索
     let rag_index = if config.context.rag.enabled {
         let index = RagIndex::new(config.context.rag.clone());
-        if let Some((base_url, api_key, _)) = crate::context::resolve_profile_endpoint(
-            &config,
-            &config.context.rag.profile,
-            &config.context.rag.model,
-        ) {
-            if let Err(e) = index.build_index(&http_client, &base_url, &api_key).await {
-                tracing::warn!("failed to build RAG index: {e}");
-            }
-        } else {
+        index.load().await;
+        let endpoint = EmbeddingEndpoint::resolve(&config);
+        if endpoint.is_none() && config.context.rag.retrieval != RetrievalMode::Bm25 {
             tracing::warn!(
                 profile = %config.context.rag.profile,
-                "RAG profile not found, skipping index build"
+                "RAG profile not found, indexing for BM25 only"
             );
         }
+        if config.context.rag.watch {
+            let watched = index.clone();
+            let (http_client, endpoint) = (http_client.clone(), endpoint.clone());
+            tokio::spawn(async move {
+                if let Err(e) =
+                    crate::context::rag::watcher::watch(watched, http_client, endpoint).await
+                {
+                    tracing::warn!("failed to watch RAG index paths: {e}");
+                }
+            });
+        }
+        let built = index.clone();
+        let http_client = http_client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = built.build_index(&http_client, endpoint.as_ref()).await {
+                tracing::warn!("failed to build RAG index: {e}");
+            }
+        });
         Some(index)
     } else {
         None
     };
 
-    let token_manager = crate::oauth::manager::TokenManager::new(http_client.clone());
-
-    let state = Arc::new(ProxyState {
-        config: Arc::new(RwLock::new(config)),
-        metrics: MetricsStore::new(),
-        http_client,
-        health_status: Arc::new(RwLock::new(health::HealthMap::new())),
-        circuit_breakers: fallback::new_circuit_breaker_map(),
-        shared_context: SharedContext::new(),
-        rag_index,
-        token_manager,
-    });
+    let mut state = ProxyState::new(config, http_client);
+    state.rag_index = rag_index;
+    state.budgets = budget::BudgetTracker::load_from_ledger();
+    state.usage_ledger = usage::ledger_path();
+    let state = Arc::new(state);
 
     health::spawn_health_checker(state.clone());
 
-    let app = Router::new()
-        .route("/v1/models", get(models::list_models))
-        .route(
-            "/proxy/{profile}/v1/messages",
-            post(handler::handle_messages),
-        )
-        .route("/health", get(|| async { "ok" }))
-        .with_state(state);
+    let app = build_router(state);
 
     let bind_addr = format!("{host}:{port}");
     let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
@@ -27,6 +27,7 @@ pub async fn list_models(State(state): State<Arc<ProxyState>>) -> impl IntoRespo
                 ProviderType::DirectAnthropic => "anthropic",
                 ProviderType::OpenAICompatible => "openai-compatible",
                 ProviderType::OpenAIResponses => "openai-responses",
+                ProviderType::Mock => "mock",
             },
         }));
     }
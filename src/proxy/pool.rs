@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use super::fallback::CircuitBreaker;
+use super::health::HealthMap;
+use super::metrics::MetricsStore;
+use crate::config::{ClaudexConfig, ProfileConfig};
+
+/// 虚拟负载均衡 profile：按策略在成员 profile 间分发
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PoolConfig {
+    pub name: String,
+    #[serde(default)]
+    pub strategy: PoolStrategy,
+    pub members: Vec<PoolMember>,
+}
+
+/// 分发策略
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolStrategy {
+    /// 按成员 profile 的 priority 降序（数值越大越优先，与 profile 配置说明一致）
+    #[default]
+    Priority,
+    /// 按 weight 加权随机
+    WeightedRandom,
+    /// 依次轮换
+    RoundRobin,
+    /// 健康检查延迟最低优先
+    LeastLatency,
+    /// 当前进行中请求数最少优先
+    LeastInFlight,
+}
+
+/// 池成员：profile 名，或带权重的 `{ profile, weight }`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum PoolMember {
+    Name(String),
+    Weighted {
+        profile: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl PoolMember {
+    pub fn profile(&self) -> &str {
+        match self {
+            PoolMember::Name(name) => name,
+            PoolMember::Weighted { profile, .. } => profile,
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        match self {
+            PoolMember::Name(_) => default_weight(),
+            PoolMember::Weighted { weight, .. } => *weight,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// `claudex run <pool>` 启动 Claude Code 用的占位 profile（模型取首个成员）
+    pub fn launch_profile(&self, config: &ClaudexConfig) -> Option<ProfileConfig> {
+        let first = self
+            .members
+            .iter()
+            .find_map(|m| config.find_profile(m.profile()))?;
+        Some(ProfileConfig {
+            name: self.name.clone(),
+            default_model: first.default_model.clone(),
+            models: first.models.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// 各成员在选择时刻的状态快照
+#[derive(Debug, Clone)]
+struct Candidate {
+    profile: ProfileConfig,
+    weight: u32,
+    latency_ms: Option<u128>,
+    in_flight: u64,
+}
+
+/// round-robin 游标（按 pool 名）
+#[derive(Debug, Default, Clone)]
+pub struct PoolCursors {
+    inner: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl PoolCursors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn next(&self, pool: &str) -> usize {
+        let mut map = self.inner.lock().unwrap();
+        let cursor = map.entry(pool.to_string()).or_insert(0);
+        let current = *cursor;
+        *cursor = cursor.wrapping_add(1);
+        current
+    }
+}
+
+/// 按策略排列可用成员：首个为主 profile，其余作为故障转移候选。
+/// 跳过未知、禁用以及熔断器打开的成员。
+pub fn order_members(
+    pool: &PoolConfig,
+    config: &ClaudexConfig,
+    breakers: &HashMap<String, CircuitBreaker>,
+    health: &HealthMap,
+    metrics: &MetricsStore,
+    cursors: &PoolCursors,
+) -> Vec<ProfileConfig> {
+    let candidates: Vec<Candidate> = pool
+        .members
+        .iter()
+        .filter_map(|member| {
+            let profile = config.find_profile(member.profile())?;
+            if !profile.enabled || profile.name == pool.name {
+                return None;
+            }
+            if breakers
+                .get(&profile.name)
+                .is_some_and(|cb| !cb.would_admit())
+            {
+                tracing::debug!(
+                    pool = %pool.name,
+                    member = %profile.name,
+                    "skipping member with open circuit"
+                );
+                return None;
+            }
+            Some(Candidate {
+                profile: profile.clone(),
+                weight: member.weight(),
+                latency_ms: health
+                    .get(&profile.name)
+                    .filter(|h| h.healthy)
+                    .and_then(|h| h.latency_ms),
+                in_flight: metrics
+                    .get_or_create(&profile.name)
+                    .in_flight
+                    .load(Ordering::Relaxed),
+            })
+        })
+        .collect();
+
+    let ordered = match pool.strategy {
+        PoolStrategy::Priority => {
+            let mut c = candidates;
+            c.sort_by_key(|c| std::cmp::Reverse(c.profile.priority));
+            c
+        }
+        PoolStrategy::RoundRobin => {
+            let mut c = candidates;
+            if !c.is_empty() {
+                let start = cursors.next(&pool.name) % c.len();
+                c.rotate_left(start);
+            }
+            c
+        }
+        PoolStrategy::LeastLatency => {
+            let mut c = candidates;
+            // 无延迟数据（未检查或不健康）的成员排在最后
+            c.sort_by_key(|c| (c.latency_ms.is_none(), c.latency_ms));
+            c
+        }
+        PoolStrategy::LeastInFlight => {
+            let mut c = candidates;
+            c.sort_by_key(|c| (c.in_flight, std::cmp::Reverse(c.profile.priority)));
+            c
+        }
+        PoolStrategy::WeightedRandom => weighted_shuffle(candidates, rand::random::<f64>),
+    };
+
+    ordered.into_iter().map(|c| c.profile).collect()
+}
+
+/// 加权无放回抽样：依次按剩余权重随机抽取
+fn weighted_shuffle(mut remaining: Vec<Candidate>, mut rng: impl FnMut() -> f64) -> Vec<Candidate> {
+    let mut ordered = Vec::with_capacity(remaining.len());
+    while !remaining.is_empty() {
+        let total: u64 = remaining.iter().map(|c| c.weight as u64).sum();
+        let index = if total == 0 {
+            0
+        } else {
+            let mut target = rng() * total as f64;
+            remaining
+                .iter()
+                .position(|c| {
+                    target -= c.weight as f64;
+                    target < 0.0
+                })
+                .unwrap_or(remaining.len() - 1)
+        };
+        ordered.push(remaining.remove(index));
+    }
+    ordered
+}
+
+/// 经 pool 分发时，请求模型不属于成员 profile 时：Claude 模型名按 haiku / sonnet / opus
+/// 映射到成员对应 slot 的模型，未配置该 slot 时改用成员的默认模型
+pub fn member_model(profile: &ProfileConfig, requested: Option<&str>) -> String {
+    let known = [
+        Some(profile.default_model.as_str()),
+        profile.models.haiku.as_deref(),
+        profile.models.sonnet.as_deref(),
+        profile.models.opus.as_deref(),
+    ];
+    match requested {
+        Some(model) if known.contains(&Some(model)) => model.to_string(),
+        Some(model) => profile
+            .models
+            .for_claude_model(model)
+            .unwrap_or(&profile.default_model)
+            .to_string(),
+        None => profile.default_model.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::health::{HealthSource, HealthStatus};
+    use std::time::Duration;
+
+    fn profile(name: &str, priority: u32) -> ProfileConfig {
+        ProfileConfig {
+            name: name.to_string(),
+            base_url: format!("https://{name}.example.com"),
+            default_model: format!("{name}-model"),
+            priority,
+            ..Default::default()
+        }
+    }
+
+    fn setup(strategy: PoolStrategy) -> (PoolConfig, ClaudexConfig) {
+        let pool = PoolConfig {
+            name: "pool-fast".to_string(),
+            strategy,
+            members: vec![
+                PoolMember::Name("a".to_string()),
+                PoolMember::Weighted {
+                    profile: "b".to_string(),
+                    weight: 3,
+                },
+                PoolMember::Name("c".to_string()),
+            ],
+        };
+        let config = ClaudexConfig {
+            profiles: vec![profile("a", 50), profile("b", 10), profile("c", 30)],
+            ..Default::default()
+        };
+        (pool, config)
+    }
+
+    fn names(profiles: &[ProfileConfig]) -> Vec<&str> {
+        profiles.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    fn order(pool: &PoolConfig, config: &ClaudexConfig) -> Vec<ProfileConfig> {
+        order_members(
+            pool,
+            config,
+            &HashMap::new(),
+            &HashMap::new(),
+            &MetricsStore::new(),
+            &PoolCursors::new(),
+        )
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let (pool, config) = setup(PoolStrategy::Priority);
+        // priority 越高越优先：a(50) > c(30) > b(10)
+        assert_eq!(names(&order(&pool, &config)), vec!["a", "c", "b"]);
+    }
+
+    #[test]
+    fn test_round_robin_rotates() {
+        let (pool, config) = setup(PoolStrategy::RoundRobin);
+        let cursors = PoolCursors::new();
+        let first: Vec<String> = (0..4)
+            .map(|_| {
+                order_members(
+                    &pool,
+                    &config,
+                    &HashMap::new(),
+                    &HashMap::new(),
+                    &MetricsStore::new(),
+                    &cursors,
+                )[0]
+                .name
+                .clone()
+            })
+            .collect();
+        assert_eq!(first, vec!["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_least_latency_uses_health() {
+        let (pool, config) = setup(PoolStrategy::LeastLatency);
+        let mut health = HashMap::new();
+        let status = |healthy, latency| HealthStatus {
+            healthy,
+            latency_ms: Some(latency),
+            last_check: None,
+            error: None,
+            source: HealthSource::Probe,
+            consecutive_failures: 0,
+        };
+        health.insert("a".to_string(), status(true, 120));
+        health.insert("b".to_string(), status(false, 5));
+        health.insert("c".to_string(), status(true, 40));
+        let ordered = order_members(
+            &pool,
+            &config,
+            &HashMap::new(),
+            &health,
+            &MetricsStore::new(),
+            &PoolCursors::new(),
+        );
+        // 不健康的 b 排最后
+        assert_eq!(names(&ordered), vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn test_least_in_flight() {
+        let (pool, config) = setup(PoolStrategy::LeastInFlight);
+        let metrics = MetricsStore::new();
+        metrics
+            .get_or_create("b")
+            .in_flight
+            .store(4, Ordering::Relaxed);
+        metrics
+            .get_or_create("c")
+            .in_flight
+            .store(1, Ordering::Relaxed);
+        let ordered = order_members(
+            &pool,
+            &config,
+            &HashMap::new(),
+            &HashMap::new(),
+            &metrics,
+            &PoolCursors::new(),
+        );
+        assert_eq!(names(&ordered), vec!["a", "c", "b"]);
+    }
+
+    #[test]
+    fn test_least_in_flight_ties_prefer_higher_priority() {
+        let (pool, config) = setup(PoolStrategy::LeastInFlight);
+        let metrics = MetricsStore::new();
+        metrics
+            .get_or_create("a")
+            .in_flight
+            .store(2, Ordering::Relaxed);
+        let ordered = order_members(
+            &pool,
+            &config,
+            &HashMap::new(),
+            &HashMap::new(),
+            &metrics,
+            &PoolCursors::new(),
+        );
+        assert_eq!(names(&ordered), vec!["c", "b", "a"]);
+    }
+
+    #[test]
+    fn test_skips_disabled_and_open_circuit() {
+        let (pool, mut config) = setup(PoolStrategy::Priority);
+        config.profiles[2].enabled = false;
+        let mut breakers = HashMap::new();
+        let mut cb = CircuitBreaker::new(1, Duration::from_secs(60));
+        cb.record_failure();
+        breakers.insert("b".to_string(), cb);
+        let ordered = order_members(
+            &pool,
+            &config,
+            &breakers,
+            &HashMap::new(),
+            &MetricsStore::new(),
+            &PoolCursors::new(),
+        );
+        assert_eq!(names(&ordered), vec!["a"]);
+    }
+
+    #[test]
+    fn test_weighted_shuffle_respects_weights() {
+        let candidates = |weights: &[u32]| -> Vec<Candidate> {
+            weights
+                .iter()
+                .enumerate()
+                .map(|(i, w)| Candidate {
+                    profile: profile(&i.to_string(), 100),
+                    weight: *w,
+                    latency_ms: None,
+                    in_flight: 0,
+                })
+                .collect()
+        };
+        // 总权重 4：r=0.3 → 1.2 落在第二个成员（权重 3）
+        let mut draws = [0.3, 0.0].into_iter();
+        let ordered = weighted_shuffle(candidates(&[1, 3]), || draws.next().unwrap());
+        assert_eq!(ordered[0].profile.name, "1");
+        assert_eq!(ordered[1].profile.name, "0");
+
+        // 权重为 0 的成员只会排在最后
+        let mut draws = std::iter::repeat(0.99);
+        let ordered = weighted_shuffle(candidates(&[0, 2, 0]), || draws.next().unwrap());
+        assert_eq!(ordered[0].profile.name, "1");
+    }
+
+    #[test]
+    fn test_member_model_rewrites_foreign_model() {
+        let mut p = profile("a", 100);
+        p.models.haiku = Some("a-mini".to_string());
+        assert_eq!(member_model(&p, Some("a-mini")), "a-mini");
+        assert_eq!(member_model(&p, Some("claude-sonnet-4")), "a-model");
+        assert_eq!(member_model(&p, None), "a-model");
+    }
+
+    #[test]
+    fn test_member_model_maps_claude_slot() {
+        let mut p = profile("a", 100);
+        p.models.haiku = Some("a-mini".to_string());
+        p.models.opus = Some("a-max".to_string());
+        assert_eq!(
+            member_model(&p, Some("claude-3-5-haiku-20241022")),
+            "a-mini"
+        );
+        assert_eq!(member_model(&p, Some("claude-opus-4-1")), "a-max");
+        // 未配置的 slot 回退到默认模型
+        assert_eq!(member_model(&p, Some("claude-sonnet-4")), "a-model");
+    }
+
+    #[test]
+    fn test_member_deserialize_forms() {
+        let pool: PoolConfig = toml::from_str(
+            r#"
+            name = "p"
+            strategy = "weighted_random"
+            members = ["a", { profile = "b", weight = 5 }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(pool.strategy, PoolStrategy::WeightedRandom);
+        assert_eq!(pool.members[0].profile(), "a");
+        assert_eq!(pool.members[0].weight(), 1);
+        assert_eq!(pool.members[1].weight(), 5);
+    }
+}
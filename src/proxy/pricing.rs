@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// 单个模型的价格（美元 / 百万 token）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    #[serde(default)]
+    pub input: f64,
+    #[serde(default)]
+    pub output: f64,
+    #[serde(default)]
+    pub cache_read: f64,
+    #[serde(default)]
+    pub cache_write: f64,
+}
+
+impl ModelPrice {
+    const fn new(input: f64, output: f64, cache_read: f64, cache_write: f64) -> Self {
+        Self {
+            input,
+            output,
+            cache_read,
+            cache_write,
+        }
+    }
+
+    /// 按 token 用量估算费用（美元）
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        (usage.input_tokens as f64 * self.input
+            + usage.output_tokens as f64 * self.output
+            + usage.cache_read_tokens as f64 * self.cache_read
+            + usage.cache_write_tokens as f64 * self.cache_write)
+            / 1_000_000.0
+    }
+}
+
+/// 一次请求的 token 用量（Anthropic 语义：input_tokens 不含缓存命中部分）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+    #[serde(default)]
+    pub cache_read_tokens: u64,
+    #[serde(default)]
+    pub cache_write_tokens: u64,
+}
+
+impl TokenUsage {
+    /// 从 Anthropic 格式的 usage 对象解析
+    pub fn from_anthropic(usage: &Value) -> Self {
+        let get = |key: &str| usage.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+        Self {
+            input_tokens: get("input_tokens"),
+            output_tokens: get("output_tokens"),
+            cache_read_tokens: get("cache_read_input_tokens"),
+            cache_write_tokens: get("cache_creation_input_tokens"),
+        }
+    }
+
+    /// 合并流式事件中的增量 usage：非零字段覆盖（message_delta 给的是累计值）
+    pub fn merge(&mut self, other: &TokenUsage) {
+        if other.input_tokens > 0 {
+            self.input_tokens = other.input_tokens;
+        }
+        if other.output_tokens > 0 {
+            self.output_tokens = other.output_tokens;
+        }
+        if other.cache_read_tokens > 0 {
+            self.cache_read_tokens = other.cache_read_tokens;
+        }
+        if other.cache_write_tokens > 0 {
+            self.cache_write_tokens = other.cache_write_tokens;
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens + self.cache_read_tokens + self.cache_write_tokens
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+}
+
+/// 内置价格表（公开标价，按模型 id 前缀匹配，最长前缀优先）
+const BUILTIN_PRICES: &[(&str, ModelPrice)] = &[
+    // Anthropic
+    ("claude-opus-4-5", ModelPrice::new(5.0, 25.0, 0.5, 6.25)),
+    ("claude-opus-4", ModelPrice::new(15.0, 75.0, 1.5, 18.75)),
+    ("claude-sonnet-4", ModelPrice::new(3.0, 15.0, 0.3, 3.75)),
+    ("claude-3-7-sonnet", ModelPrice::new(3.0, 15.0, 0.3, 3.75)),
+    ("claude-3-5-sonnet", ModelPrice::new(3.0, 15.0, 0.3, 3.75)),
+    ("claude-haiku-4-5", ModelPrice::new(1.0, 5.0, 0.1, 1.25)),
+    ("claude-3-5-haiku", ModelPrice::new(0.8, 4.0, 0.08, 1.0)),
+    // OpenAI
+    ("gpt-5-mini", ModelPrice::new(0.25, 2.0, 0.025, 0.0)),
+    ("gpt-5-nano", ModelPrice::new(0.05, 0.4, 0.005, 0.0)),
+    ("gpt-5", ModelPrice::new(1.25, 10.0, 0.125, 0.0)),
+    ("gpt-4.1-mini", ModelPrice::new(0.4, 1.6, 0.1, 0.0)),
+    ("gpt-4.1-nano", ModelPrice::new(0.1, 0.4, 0.025, 0.0)),
+    ("gpt-4.1", ModelPrice::new(2.0, 8.0, 0.5, 0.0)),
+    ("gpt-4o-mini", ModelPrice::new(0.15, 0.6, 0.075, 0.0)),
+    ("gpt-4o", ModelPrice::new(2.5, 10.0, 1.25, 0.0)),
+    ("o4-mini", ModelPrice::new(1.1, 4.4, 0.275, 0.0)),
+    ("o3", ModelPrice::new(2.0, 8.0, 0.5, 0.0)),
+    // DeepSeek
+    ("deepseek-chat", ModelPrice::new(0.27, 1.1, 0.07, 0.0)),
+    ("deepseek-reasoner", ModelPrice::new(0.55, 2.19, 0.14, 0.0)),
+    // xAI
+    ("grok-4", ModelPrice::new(3.0, 15.0, 0.75, 0.0)),
+    ("grok-3-mini", ModelPrice::new(0.3, 0.5, 0.075, 0.0)),
+    ("grok-3", ModelPrice::new(3.0, 15.0, 0.75, 0.0)),
+    // Google
+    ("gemini-2.5-pro", ModelPrice::new(1.25, 10.0, 0.31, 0.0)),
+    ("gemini-2.5-flash", ModelPrice::new(0.3, 2.5, 0.075, 0.0)),
+    ("gemini-2.0-flash", ModelPrice::new(0.1, 0.4, 0.025, 0.0)),
+    // Moonshot / Zhipu / Qwen
+    ("kimi-k2", ModelPrice::new(0.6, 2.5, 0.15, 0.0)),
+    ("glm-4.5", ModelPrice::new(0.6, 2.2, 0.11, 0.0)),
+    ("qwen-max", ModelPrice::new(1.6, 6.4, 0.0, 0.0)),
+    ("qwen-plus", ModelPrice::new(0.4, 1.2, 0.0, 0.0)),
+];
+
+/// 查找模型价格：用户覆盖优先，其次内置表。
+///
+/// 匹配规则：精确匹配 > 最长前缀匹配；带 provider 前缀的 id（如 `anthropic/claude-sonnet-4`）
+/// 会再用 `/` 之后的部分匹配一次。
+pub fn lookup_price(overrides: &HashMap<String, ModelPrice>, model: &str) -> Option<ModelPrice> {
+    let bare = model.rsplit('/').next().unwrap_or(model);
+    for candidate in [model, bare] {
+        if let Some(price) =
+            best_prefix_match(overrides.iter().map(|(k, v)| (k.as_str(), *v)), candidate)
+        {
+            return Some(price);
+        }
+    }
+    for candidate in [model, bare] {
+        if let Some(price) = best_prefix_match(BUILTIN_PRICES.iter().copied(), candidate) {
+            return Some(price);
+        }
+    }
+    None
+}
+
+fn best_prefix_match<'a>(
+    table: impl Iterator<Item = (&'a str, ModelPrice)>,
+    model: &str,
+) -> Option<ModelPrice> {
+    table
+        .filter(|(key, _)| model.starts_with(key))
+        .max_by_key(|(key, _)| key.len())
+        .map(|(_, price)| price)
+}
+
+/// 格式化美元金额（小额保留更多小数位）
+pub fn format_cost(cost: f64) -> String {
+    if cost > 0.0 && cost < 0.01 {
+        format!("${cost:.4}")
+    } else {
+        format!("${cost:.2}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_exact_builtin_match() {
+        let price = lookup_price(&HashMap::new(), "deepseek-chat").unwrap();
+        assert_eq!(price.input, 0.27);
+        assert_eq!(price.output, 1.1);
+    }
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        let price = lookup_price(&HashMap::new(), "gpt-4o-mini-2024-07-18").unwrap();
+        assert_eq!(price.input, 0.15);
+        let price = lookup_price(&HashMap::new(), "claude-opus-4-5-20251101").unwrap();
+        assert_eq!(price.input, 5.0);
+        let price = lookup_price(&HashMap::new(), "claude-opus-4-1-20250805").unwrap();
+        assert_eq!(price.input, 15.0);
+    }
+
+    #[test]
+    fn test_provider_prefixed_model() {
+        let price = lookup_price(&HashMap::new(), "anthropic/claude-sonnet-4").unwrap();
+        assert_eq!(price.output, 15.0);
+    }
+
+    #[test]
+    fn test_override_takes_precedence() {
+        let mut overrides = HashMap::new();
+        overrides.insert(
+            "deepseek-chat".to_string(),
+            ModelPrice::new(0.0, 0.0, 0.0, 0.0),
+        );
+        let price = lookup_price(&overrides, "deepseek-chat").unwrap();
+        assert_eq!(price.input, 0.0);
+    }
+
+    #[test]
+    fn test_unknown_model() {
+        assert!(lookup_price(&HashMap::new(), "my-local-llama").is_none());
+    }
+
+    #[test]
+    fn test_cost_calculation() {
+        let price = ModelPrice::new(3.0, 15.0, 0.3, 3.75);
+        let usage = TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cache_read_tokens: 1_000_000,
+            cache_write_tokens: 0,
+        };
+        let cost = price.cost(&usage);
+        assert!((cost - 4.8).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_usage_from_anthropic() {
+        let usage = TokenUsage::from_anthropic(&json!({
+            "input_tokens": 10,
+            "output_tokens": 5,
+            "cache_read_input_tokens": 100,
+            "cache_creation_input_tokens": 20
+        }));
+        assert_eq!(usage.input_tokens, 10);
+        assert_eq!(usage.output_tokens, 5);
+        assert_eq!(usage.cache_read_tokens, 100);
+        assert_eq!(usage.cache_write_tokens, 20);
+        assert_eq!(usage.total(), 135);
+    }
+
+    #[test]
+    fn test_usage_merge_keeps_nonzero() {
+        let mut usage = TokenUsage {
+            input_tokens: 50,
+            ..Default::default()
+        };
+        usage.merge(&TokenUsage {
+            output_tokens: 7,
+            ..Default::default()
+        });
+        assert_eq!(usage.input_tokens, 50);
+        assert_eq!(usage.output_tokens, 7);
+    }
+
+    #[test]
+    fn test_format_cost() {
+        assert_eq!(format_cost(0.0), "$0.00");
+        assert_eq!(format_cost(0.0042), "$0.0042");
+        assert_eq!(format_cost(12.345), "$12.35");
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::response::Response;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::metrics::ProfileMetrics;
+
+/// Profile 限流配置
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RateLimitConfig {
+    /// 每分钟请求数上限
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// 每分钟 token 上限（按请求体估算的输入 token 计）
+    #[serde(default)]
+    pub tokens_per_minute: Option<u64>,
+    /// 最大并发请求数
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// 超限请求排队的最长等待时间（毫秒），0 表示立即拒绝
+    #[serde(default = "default_max_wait_ms")]
+    pub max_wait_ms: u64,
+}
+
+fn default_max_wait_ms() -> u64 {
+    10_000
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+            max_concurrent: None,
+            max_wait_ms: default_max_wait_ms(),
+        }
+    }
+}
+
+/// 令牌桶：容量为每分钟配额，按秒匀速补充
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    available: f64,
+    per_sec: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_minute: f64, now: Instant) -> Self {
+        Self {
+            capacity: per_minute,
+            available: per_minute,
+            per_sec: per_minute / 60.0,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.available = (self.available + elapsed * self.per_sec).min(self.capacity);
+        self.last = now;
+    }
+
+    /// 补足 `amount` 需要等待的时间（单次请求超过桶容量时按容量计算，避免永远无法通过）
+    fn wait_for(&self, amount: f64) -> Duration {
+        let amount = amount.min(self.capacity);
+        if self.available >= amount || self.per_sec <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((amount - self.available) / self.per_sec)
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.capacity);
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<TokenBucket>,
+    tokens: Option<TokenBucket>,
+}
+
+/// 单个 profile 的限流器
+#[derive(Debug)]
+pub struct ProfileLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<Buckets>,
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl ProfileLimiter {
+    fn new(config: RateLimitConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            buckets: Mutex::new(Buckets {
+                requests: config
+                    .requests_per_minute
+                    .map(|n| TokenBucket::new(n as f64, now)),
+                tokens: config
+                    .tokens_per_minute
+                    .map(|n| TokenBucket::new(n as f64, now)),
+            }),
+            semaphore: config
+                .max_concurrent
+                .map(|n| Arc::new(Semaphore::new(n.max(1)))),
+            config,
+        }
+    }
+
+    /// 尝试立即扣减令牌；不足时返回需要等待的时长
+    fn try_take(&self, tokens: u64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut b = self.buckets.lock().unwrap();
+        let mut wait = Duration::ZERO;
+        if let Some(bucket) = b.requests.as_mut() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_for(1.0));
+        }
+        if let Some(bucket) = b.tokens.as_mut() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_for(tokens as f64));
+        }
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        if let Some(bucket) = b.requests.as_mut() {
+            bucket.take(1.0);
+        }
+        if let Some(bucket) = b.tokens.as_mut() {
+            bucket.take(tokens as f64);
+        }
+        Ok(())
+    }
+
+    /// 获取通行许可：令牌不足或并发已满时排队，超过 max_wait_ms 仍未获取则返回错误信息
+    pub async fn acquire(
+        &self,
+        profile: &str,
+        tokens: u64,
+        metrics: &ProfileMetrics,
+    ) -> Result<RateLimitPermit, String> {
+        let max_wait = Duration::from_millis(self.config.max_wait_ms);
+        let deadline = Instant::now() + max_wait;
+        let mut queued: Option<QueueGuard<'_>> = None;
+
+        loop {
+            match self.try_take(tokens) {
+                Ok(()) => break,
+                Err(wait) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if wait > remaining {
+                        metrics.rate_limited.fetch_add(1, Ordering::Relaxed);
+                        return Err(format!(
+                            "rate limit exceeded for profile '{profile}' (retry in {:.1}s)",
+                            wait.as_secs_f64()
+                        ));
+                    }
+                    queued.get_or_insert_with(|| QueueGuard::enter(metrics));
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+
+        let permit = match &self.semaphore {
+            Some(sem) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match sem.clone().try_acquire_owned() {
+                    Ok(p) => Some(p),
+                    Err(_) => {
+                        queued.get_or_insert_with(|| QueueGuard::enter(metrics));
+                        match tokio::time::timeout(remaining, sem.clone().acquire_owned()).await {
+                            Ok(Ok(p)) => Some(p),
+                            _ => {
+                                metrics.rate_limited.fetch_add(1, Ordering::Relaxed);
+                                return Err(format!(
+                                    "too many concurrent requests for profile '{profile}' (max {})",
+                                    self.config.max_concurrent.unwrap_or_default()
+                                ));
+                            }
+                        }
+                    }
+                }
+            }
+            None => None,
+        };
+
+        Ok(RateLimitPermit { permit })
+    }
+}
+
+/// 排队计数守卫：离开作用域时扣减 queue_depth
+struct QueueGuard<'a> {
+    metrics: &'a ProfileMetrics,
+}
+
+impl<'a> QueueGuard<'a> {
+    fn enter(metrics: &'a ProfileMetrics) -> Self {
+        metrics.queue_depth.fetch_add(1, Ordering::Relaxed);
+        Self { metrics }
+    }
+}
+
+impl Drop for QueueGuard<'_> {
+    fn drop(&mut self) {
+        self.metrics.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 并发许可，drop 时释放
+#[derive(Debug)]
+pub struct RateLimitPermit {
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl RateLimitPermit {
+    /// 将许可绑定到响应体上，流式响应结束后才释放并发名额
+    pub fn hold_until_body_end(self, response: Response) -> Response {
+        if self.permit.is_none() {
+            return response;
+        }
+        let (parts, body) = response.into_parts();
+        let stream = body.into_data_stream().map(move |chunk| {
+            let _held = &self.permit;
+            chunk
+        });
+        Response::from_parts(parts, Body::from_stream(stream))
+    }
+}
+
+/// 按 profile 缓存限流器；配置变化时重建
+#[derive(Debug, Clone, Default)]
+pub struct RateLimiterMap {
+    inner: Arc<Mutex<HashMap<String, Arc<ProfileLimiter>>>>,
+}
+
+impl RateLimiterMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, profile: &str, config: &RateLimitConfig) -> Arc<ProfileLimiter> {
+        let mut map = self.inner.lock().unwrap();
+        match map.get(profile) {
+            Some(limiter) if limiter.config == *config => limiter.clone(),
+            _ => {
+                let limiter = Arc::new(ProfileLimiter::new(config.clone()));
+                map.insert(profile.to_string(), limiter.clone());
+                limiter
+            }
+        }
+    }
+}
+
+/// 估算请求体的输入 token 数：按请求中的模型（缺省时为 `default_model`）选择本地 tokenizer
+pub fn estimate_request_tokens(body: &serde_json::Value, default_model: &str) -> u64 {
+    let model = body
+        .get("model")
+        .and_then(|m| m.as_str())
+        .unwrap_or(default_model);
+    crate::tokenizer::count_request_tokens(model, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+        rpm: Option<u32>,
+        tpm: Option<u64>,
+        conc: Option<usize>,
+        wait: u64,
+    ) -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_minute: rpm,
+            tokens_per_minute: tpm,
+            max_concurrent: conc,
+            max_wait_ms: wait,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_per_minute_rejects_when_no_wait() {
+        let limiter = ProfileLimiter::new(config(Some(2), None, None, 0));
+        let m = ProfileMetrics::new();
+        assert!(limiter.acquire("p", 0, &m).await.is_ok());
+        assert!(limiter.acquire("p", 0, &m).await.is_ok());
+        let err = limiter.acquire("p", 0, &m).await.unwrap_err();
+        assert!(err.contains("rate limit exceeded"));
+        assert_eq!(m.rate_limited.load(Ordering::Relaxed), 1);
+        assert_eq!(m.queue_depth.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_tokens_per_minute() {
+        let limiter = ProfileLimiter::new(config(None, Some(1000), None, 0));
+        let m = ProfileMetrics::new();
+        assert!(limiter.acquire("p", 800, &m).await.is_ok());
+        assert!(limiter.acquire("p", 500, &m).await.is_err());
+        assert!(limiter.acquire("p", 100, &m).await.is_ok());
+    }
+
+    #[test]
+    fn test_bucket_refills_over_time() {
+        // 60 rpm = 每秒补充 1 个
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(60.0, start);
+        bucket.take(60.0);
+        assert_eq!(bucket.wait_for(1.0), Duration::from_secs(1));
+        bucket.refill(start + Duration::from_millis(1500));
+        assert!(bucket.wait_for(1.0).is_zero());
+        bucket.refill(start + Duration::from_secs(600));
+        assert_eq!(bucket.available, 60.0);
+    }
+
+    #[tokio::test]
+    async fn test_queued_request_waits_for_refill() {
+        // 6000 rpm = 每 10ms 补充 1 个
+        let limiter = ProfileLimiter::new(config(Some(6000), None, None, 1_000));
+        let m = ProfileMetrics::new();
+        for _ in 0..6000 {
+            limiter.acquire("p", 0, &m).await.unwrap();
+        }
+        assert!(limiter.acquire("p", 0, &m).await.is_ok());
+        assert_eq!(m.queue_depth.load(Ordering::Relaxed), 0);
+        assert_eq!(m.rate_limited.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_times_out() {
+        let limiter = ProfileLimiter::new(config(None, None, Some(1), 20));
+        let m = ProfileMetrics::new();
+        let first = limiter.acquire("p", 0, &m).await.unwrap();
+        let err = limiter.acquire("p", 0, &m).await.unwrap_err();
+        assert!(err.contains("too many concurrent requests"));
+        drop(first);
+        assert!(limiter.acquire("p", 0, &m).await.is_ok());
+    }
+
+    #[test]
+    fn test_oversized_request_capped_at_capacity() {
+        let limiter = ProfileLimiter::new(config(None, Some(100), None, 0));
+        assert!(limiter.try_take(10_000).is_ok());
+        assert!(limiter.try_take(1).is_err());
+    }
+
+    #[test]
+    fn test_map_rebuilds_on_config_change() {
+        let map = RateLimiterMap::new();
+        let a = map.get("p", &config(Some(10), None, None, 0));
+        let b = map.get("p", &config(Some(10), None, None, 0));
+        assert!(Arc::ptr_eq(&a, &b));
+        let c = map.get("p", &config(Some(20), None, None, 0));
+        assert!(!Arc::ptr_eq(&a, &c));
+    }
+
+    #[test]
+    fn test_parse_rate_limit_config() {
+        let c: RateLimitConfig = toml::from_str(
+            r#"
+            requests_per_minute = 50
+            max_concurrent = 4
+        "#,
+        )
+        .unwrap();
+        assert_eq!(c.requests_per_minute, Some(50));
+        assert_eq!(c.max_concurrent, Some(4));
+        assert_eq!(c.max_wait_ms, 10_000);
+    }
+}
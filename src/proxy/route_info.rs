@@ -0,0 +1,298 @@
+use axum::body::{Body, Bytes};
+use axum::http::HeaderValue;
+use axum::response::Response;
+use futures::stream::{Stream, StreamExt};
+use serde_json::Value;
+
+/// 实际响应的 profile
+pub const PROFILE_HEADER: &str = "x-claudex-profile";
+/// 实际发往上游的模型
+pub const MODEL_HEADER: &str = "x-claudex-model";
+/// 依次尝试过的 profile（逗号分隔，最后一个为响应者）
+pub const FAILOVER_CHAIN_HEADER: &str = "x-claudex-failover-chain";
+/// 选择该 profile 的原因（逗号分隔，无路由时为 `direct`）
+pub const ROUTE_REASON_HEADER: &str = "x-claudex-route-reason";
+
+/// 等待 message_start 时最多缓冲的字节数，超出后原样透传
+const MAX_PREFIX_BUFFER: usize = 64 * 1024;
+
+/// 一次请求的路由决策：auto 路由、model route、pool、预算 / 上下文改道与故障转移
+#[derive(Debug, Clone, Default)]
+pub struct RouteDecision {
+    /// 客户端请求的 profile（URL 中的名称）
+    pub requested: String,
+    reasons: Vec<String>,
+    chain: Vec<String>,
+    served_by: Option<String>,
+}
+
+impl RouteDecision {
+    pub fn new(requested: &str) -> Self {
+        Self {
+            requested: requested.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn push_reason(&mut self, reason: impl Into<String>) {
+        self.reasons.push(reason.into());
+    }
+
+    /// 记录一次转发尝试
+    pub fn attempt(&mut self, profile: &str) {
+        self.chain.push(profile.to_string());
+    }
+
+    /// 记录实际响应的 profile
+    pub fn served(&mut self, profile: &str) {
+        self.served_by = Some(profile.to_string());
+    }
+
+    pub fn reason(&self) -> String {
+        if self.reasons.is_empty() {
+            "direct".to_string()
+        } else {
+            self.reasons.join(",")
+        }
+    }
+
+    pub fn chain(&self) -> String {
+        self.chain.join(",")
+    }
+
+    /// 写入路由响应头并输出结构化日志（含代理自身返回的错误响应）。
+    /// profile 为实际响应者（未转发或全部失败时省略），模型取 `try_forward` 写入的 `x-claudex-model`
+    pub fn apply(&self, response: &mut Response) {
+        let profile = self.served_by.as_deref();
+        let model = response
+            .headers()
+            .get(MODEL_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+        let reason = self.reason();
+        let chain = self.chain();
+        tracing::info!(
+            requested = %self.requested,
+            profile = %profile.unwrap_or("-"),
+            model = %model.as_deref().unwrap_or("-"),
+            failover_chain = %chain,
+            route_reason = %reason,
+            status = response.status().as_u16(),
+            "route decision"
+        );
+        let headers = response.headers_mut();
+        if let Some(profile) = profile {
+            insert_header(headers, PROFILE_HEADER, profile);
+        }
+        insert_header(headers, FAILOVER_CHAIN_HEADER, &chain);
+        insert_header(headers, ROUTE_REASON_HEADER, &reason);
+    }
+}
+
+fn insert_header(headers: &mut axum::http::HeaderMap, name: &'static str, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
+
+/// 标记实际发往上游的模型
+pub fn set_model_header(response: &mut Response, model: &str) {
+    insert_header(response.headers_mut(), MODEL_HEADER, model);
+}
+
+/// 把响应中的模型名（非流式 `model` / 流式 `message_start.message.model`）
+/// 改写为 `x-claudex-model` 中的真实上游模型
+pub async fn rewrite_response_model(response: Response) -> Response {
+    let Some(model) = response
+        .headers()
+        .get(MODEL_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+    else {
+        return response;
+    };
+    if !response.status().is_success() {
+        return response;
+    }
+    let is_sse = response
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("text/event-stream"));
+    let (mut parts, body) = response.into_parts();
+    if is_sse {
+        let stream = rewrite_sse_model(body.into_data_stream(), model);
+        return Response::from_parts(parts, Body::from_stream(stream));
+    }
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to read response body for model rewrite");
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let rewritten = serde_json::from_slice::<Value>(&bytes)
+        .ok()
+        .filter(|v| v.get("model").is_some())
+        .and_then(|mut v| {
+            v["model"] = Value::String(model);
+            serde_json::to_vec(&v).ok()
+        });
+    match rewritten {
+        Some(body) => {
+            parts.headers.remove("content-length");
+            Response::from_parts(parts, Body::from(body))
+        }
+        None => Response::from_parts(parts, Body::from(bytes)),
+    }
+}
+
+/// 缓冲 SSE 流直到首个 message_start 事件，改写其中的模型后其余数据原样透传
+fn rewrite_sse_model<S, E>(input: S, model: String) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+{
+    async_stream::stream! {
+        let mut stream = input;
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut done = false;
+        while let Some(chunk) = stream.next().await {
+            let bytes = match chunk {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    yield Err(e);
+                    continue;
+                }
+            };
+            if done {
+                yield Ok(bytes);
+                continue;
+            }
+            buffer.extend_from_slice(&bytes);
+            if let Some(rewritten) = rewrite_message_start(&buffer, &model) {
+                done = true;
+                yield Ok(Bytes::from(rewritten));
+            } else if buffer.len() > MAX_PREFIX_BUFFER {
+                done = true;
+                yield Ok(Bytes::from(std::mem::take(&mut buffer)));
+            }
+        }
+        if !done && !buffer.is_empty() {
+            yield Ok(Bytes::from(buffer));
+        }
+    }
+}
+
+/// 缓冲区中出现完整的 message_start data 行时返回改写后的整个缓冲区
+fn rewrite_message_start(buffer: &[u8], model: &str) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(buffer).ok()?;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        if !line.ends_with('\n') {
+            return None;
+        }
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            continue;
+        };
+        let Ok(mut event) = serde_json::from_str::<Value>(data.trim()) else {
+            continue;
+        };
+        if event.get("type").and_then(|t| t.as_str()) != Some("message_start") {
+            continue;
+        }
+        event["message"]["model"] = Value::String(model.to_string());
+        let mut out = Vec::with_capacity(buffer.len());
+        out.extend_from_slice(&buffer[..start]);
+        out.extend_from_slice(format!("data: {event}\n").as_bytes());
+        out.extend_from_slice(&buffer[offset..]);
+        return Some(out);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[test]
+    fn test_reason_and_chain() {
+        let mut route = RouteDecision::new("auto");
+        assert_eq!(route.reason(), "direct");
+        route.push_reason("auto:rule=vision");
+        route.attempt("gemini");
+        route.attempt("openrouter");
+        route.push_reason("failover");
+        assert_eq!(route.reason(), "auto:rule=vision,failover");
+        assert_eq!(route.chain(), "gemini,openrouter");
+    }
+
+    #[test]
+    fn test_apply_sets_headers() {
+        let mut route = RouteDecision::new("main");
+        route.attempt("main");
+        let mut response = Response::new(Body::empty());
+        set_model_header(&mut response, "gpt-4o");
+        route.served("main");
+        route.apply(&mut response);
+        let h = response.headers();
+        assert_eq!(h[PROFILE_HEADER], "main");
+        assert_eq!(h[MODEL_HEADER], "gpt-4o");
+        assert_eq!(h[FAILOVER_CHAIN_HEADER], "main");
+        assert_eq!(h[ROUTE_REASON_HEADER], "direct");
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_non_streaming_model() {
+        let mut response = Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "application/json")
+            .body(Body::from(
+                json!({"model": "claude-sonnet-4", "content": []}).to_string(),
+            ))
+            .unwrap();
+        set_model_header(&mut response, "deepseek-chat");
+        let response = rewrite_response_model(response).await;
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(body["model"], "deepseek-chat");
+    }
+
+    #[tokio::test]
+    async fn test_rewrite_sse_across_chunks() {
+        let start =
+            json!({"type": "message_start", "message": {"id": "m", "model": "claude-sonnet-4"}});
+        let full = format!(
+            "event: message_start\ndata: {start}\n\nevent: ping\ndata: {{\"type\":\"ping\"}}\n\n"
+        );
+        let (a, b) = full.split_at(40);
+        let chunks: Vec<Result<Bytes, std::io::Error>> = vec![
+            Ok(Bytes::from(a.to_string())),
+            Ok(Bytes::from(b.to_string())),
+            Ok(Bytes::from_static(b"data: {\"type\":\"message_stop\"}\n\n")),
+        ];
+        let out: Vec<Bytes> = rewrite_sse_model(futures::stream::iter(chunks), "glm-4.5".into())
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        let text: String = out
+            .iter()
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+            .collect();
+        assert!(text.contains("\"model\":\"glm-4.5\""));
+        assert!(!text.contains("claude-sonnet-4"));
+        assert!(text.starts_with("event: message_start\n"));
+        assert!(text.contains("event: ping\n"));
+        assert!(text.ends_with("message_stop\"}\n\n"));
+    }
+
+    #[test]
+    fn test_incomplete_message_start_waits() {
+        assert!(rewrite_message_start(b"data: {\"type\":\"message_st", "x").is_none());
+    }
+}
@@ -182,6 +182,11 @@ pub fn anthropic_to_openai(
     }
     if let Some(stream) = anthropic.get("stream") {
         openai_req["stream"] = stream.clone();
+        // 流式响应默认不带 usage，显式请求最后一个 chunk 附带用量（用于费用统计）；
+        // 不支持该字段的后端可在 strip_params 中加入 "stream_options"
+        if stream.as_bool() == Some(true) {
+            openai_req["stream_options"] = json!({"include_usage": true});
+        }
     }
 
     // Convert tools（截断超过 64 字符的工具名）
@@ -288,15 +293,7 @@ pub fn openai_to_anthropic(openai: &Value, tool_name_map: &ToolNameMap) -> Resul
 
     // Usage
     let empty_usage = json!({});
-    let usage = openai.get("usage").unwrap_or(&empty_usage);
-    let input_tokens = usage
-        .get("prompt_tokens")
-        .and_then(|t| t.as_u64())
-        .unwrap_or(0);
-    let output_tokens = usage
-        .get("completion_tokens")
-        .and_then(|t| t.as_u64())
-        .unwrap_or(0);
+    let usage = openai_usage_to_anthropic(openai.get("usage").unwrap_or(&empty_usage));
 
     let model = openai
         .get("model")
@@ -311,15 +308,40 @@ pub fn openai_to_anthropic(openai: &Value, tool_name_map: &ToolNameMap) -> Resul
         "content": content,
         "stop_reason": stop_reason,
         "stop_sequence": null,
-        "usage": {
-            "input_tokens": input_tokens,
-            "output_tokens": output_tokens,
-        }
+        "usage": usage,
     });
 
     Ok(resp)
 }
 
+/// OpenAI usage → Anthropic usage。
+/// OpenAI 的 prompt_tokens 包含缓存命中部分，Anthropic 的 input_tokens 不包含，需要拆分。
+pub fn openai_usage_to_anthropic(usage: &Value) -> Value {
+    let prompt_tokens = usage
+        .get("prompt_tokens")
+        .and_then(|t| t.as_u64())
+        .unwrap_or(0);
+    let output_tokens = usage
+        .get("completion_tokens")
+        .and_then(|t| t.as_u64())
+        .unwrap_or(0);
+    let cached_tokens = usage
+        .get("prompt_tokens_details")
+        .and_then(|d| d.get("cached_tokens"))
+        .and_then(|t| t.as_u64())
+        .unwrap_or(0)
+        .min(prompt_tokens);
+
+    let mut result = json!({
+        "input_tokens": prompt_tokens - cached_tokens,
+        "output_tokens": output_tokens,
+    });
+    if cached_tokens > 0 {
+        result["cache_read_input_tokens"] = json!(cached_tokens);
+    }
+    result
+}
+
 fn convert_content_to_openai(content: Option<&Value>) -> Value {
     match content {
         None => json!(""),
@@ -776,6 +798,30 @@ mod tests {
         assert_eq!(result["content"][0]["input"]["city"], "Tokyo");
     }
 
+    #[test]
+    fn test_openai_cached_tokens_split() {
+        let usage = openai_usage_to_anthropic(&json!({
+            "prompt_tokens": 100,
+            "completion_tokens": 5,
+            "prompt_tokens_details": {"cached_tokens": 80}
+        }));
+        assert_eq!(usage["input_tokens"], 20);
+        assert_eq!(usage["cache_read_input_tokens"], 80);
+        assert_eq!(usage["output_tokens"], 5);
+    }
+
+    #[test]
+    fn test_stream_requests_usage() {
+        let req = json!({
+            "model": "m",
+            "max_tokens": 10,
+            "stream": true,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        let result = a2o(&req, "m");
+        assert_eq!(result["stream_options"]["include_usage"], true);
+    }
+
     #[test]
     fn test_stop_reason_mapping() {
         let make_resp = |reason: &str| {
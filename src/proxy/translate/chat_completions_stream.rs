@@ -3,7 +3,7 @@ use futures::stream::{Stream, StreamExt};
 use serde_json::{json, Value};
 use std::pin::Pin;
 
-use crate::proxy::util::{format_sse, ToolNameMap};
+use crate::proxy::util::{format_sse, ToolNameMap, Utf8Chunker};
 
 /// Translates an OpenAI SSE stream to Anthropic SSE format.
 ///
@@ -37,26 +37,16 @@ where
 
         let mut stream = std::pin::pin!(input);
         let mut buffer = String::new();
+        let mut decoder = Utf8Chunker::default();
 
         while let Some(chunk_result) = stream.next().await {
             match chunk_result {
                 Ok(chunk) => {
-                    buffer.push_str(&String::from_utf8_lossy(&chunk));
+                    buffer.push_str(&decoder.push(&chunk));
 
-                    // Process complete SSE lines
-                    while let Some(pos) = buffer.find("\n\n") {
-                        let line = buffer[..pos].to_string();
-                        buffer = buffer[pos + 2..].to_string();
-
-                        if let Some(events) = state.process_openai_line(&line) {
-                            for event in events {
-                                yield Ok(Bytes::from(event));
-                            }
-                        }
-                    }
-                    // Also handle single newline delimited chunks
+                    // 按行处理（兼容 \n\n 与 \r\n 分隔）
                     while let Some(pos) = buffer.find('\n') {
-                        let line = buffer[..pos].to_string();
+                        let line = buffer[..pos].trim_end_matches('\r').to_string();
                         buffer = buffer[pos + 1..].to_string();
 
                         if line.is_empty() {
@@ -77,6 +67,18 @@ where
             }
         }
 
+        // 上游未以换行结尾时处理残留的最后一行
+        if let Some(events) = state.process_openai_line(buffer.trim_end_matches('\r')) {
+            for event in events {
+                yield Ok(Bytes::from(event));
+            }
+        }
+        if let Some(events) = state.finalize_tool_call() {
+            for event in events {
+                yield Ok(Bytes::from(event));
+            }
+        }
+
         // Send final events
         if state.block_started {
             let block_stop = format_sse("content_block_stop", &json!({
@@ -88,8 +90,8 @@ where
 
         let msg_delta = format_sse("message_delta", &json!({
             "type": "message_delta",
-            "delta": {"stop_reason": "end_turn", "stop_sequence": null},
-            "usage": {"output_tokens": state.output_tokens}
+            "delta": {"stop_reason": state.stop_reason(), "stop_sequence": null},
+            "usage": state.final_usage()
         }));
         yield Ok(Bytes::from(msg_delta));
 
@@ -103,7 +105,17 @@ struct StreamState {
     block_index: usize,
     block_started: bool,
     output_tokens: u64,
+    /// 上游 usage chunk（Anthropic 格式），stream_options.include_usage 时出现
+    usage: Option<Value>,
     current_tool_call: Option<ToolCallState>,
+    /// 当前工具调用在上游 tool_calls 数组中的 index
+    current_tool_index: Option<u64>,
+    /// 已开始过的工具调用 index（用于识别重复 id 与交错的参数分片）
+    seen_tool_indices: Vec<u64>,
+    /// 当前块打开期间出现的其他工具调用（按出现顺序缓冲，当前块关闭后依次补发）
+    pending_tools: Vec<PendingToolCall>,
+    has_tool_use: bool,
+    finish_reason: Option<String>,
     tool_name_map: ToolNameMap,
 }
 
@@ -113,37 +125,72 @@ struct ToolCallState {
     arguments_buffer: String,
 }
 
+struct PendingToolCall {
+    index: u64,
+    call: ToolCallState,
+}
+
 impl StreamState {
     fn new(tool_name_map: ToolNameMap) -> Self {
         Self {
             block_index: 0,
             block_started: false,
             output_tokens: 0,
+            usage: None,
             current_tool_call: None,
+            current_tool_index: None,
+            seen_tool_indices: Vec::new(),
+            pending_tools: Vec::new(),
+            has_tool_use: false,
+            finish_reason: None,
             tool_name_map,
         }
     }
 
+    /// 最终 message_delta 的 usage：优先使用上游完整 usage
+    fn final_usage(&self) -> Value {
+        self.usage
+            .clone()
+            .unwrap_or_else(|| json!({"output_tokens": self.output_tokens}))
+    }
+
+    /// 最终 stop_reason：出现过工具调用时一律为 tool_use（部分上游工具调用后仍返回 "stop"）
+    fn stop_reason(&self) -> &str {
+        if self.has_tool_use {
+            return "tool_use";
+        }
+        match self.finish_reason.as_deref() {
+            Some("length") => "max_tokens",
+            _ => "end_turn",
+        }
+    }
+
     fn process_openai_line(&mut self, line: &str) -> Option<Vec<String>> {
-        let data = line.strip_prefix("data: ")?.trim();
+        let data = line
+            .strip_prefix("data:")
+            .map(|d| d.strip_prefix(' ').unwrap_or(d))?
+            .trim();
 
         if data == "[DONE]" {
             return self.finalize_tool_call();
         }
 
         let parsed: Value = serde_json::from_str(data).ok()?;
-        let choice = parsed.get("choices")?.as_array()?.first()?;
-        let delta = choice.get("delta")?;
 
-        let mut events = Vec::new();
-
-        // Track usage
-        if let Some(usage) = parsed.get("usage") {
+        // Track usage（include_usage 的最后一个 chunk 的 choices 为空，需在取 choice 之前处理）
+        if let Some(usage) = parsed.get("usage").filter(|u| u.is_object()) {
             if let Some(tokens) = usage.get("completion_tokens").and_then(|t| t.as_u64()) {
                 self.output_tokens = tokens;
             }
+            self.usage =
+                Some(crate::proxy::translate::chat_completions::openai_usage_to_anthropic(usage));
         }
 
+        let choice = parsed.get("choices")?.as_array()?.first()?;
+        let delta = choice.get("delta")?;
+
+        let mut events = Vec::new();
+
         // Handle text content
         if let Some(content) = delta.get("content").and_then(|c| c.as_str()) {
             if !content.is_empty() {
@@ -179,13 +226,80 @@ impl StreamState {
 
         // Handle tool calls
         if let Some(tool_calls) = delta.get("tool_calls").and_then(|tc| tc.as_array()) {
-            for tc in tool_calls {
+            for (position, tc) in tool_calls.iter().enumerate() {
                 let empty_func = json!({});
                 let func = tc.get("function").unwrap_or(&empty_func);
+                let id = tc
+                    .get("id")
+                    .and_then(|id| id.as_str())
+                    .filter(|id| !id.is_empty());
+                let tool_index = tc
+                    .get("index")
+                    .and_then(|i| i.as_u64())
+                    .unwrap_or(position as u64);
+
+                // 同一 index 的后续分片（部分上游每个分片都重复 id）视为参数续写
+                let continues_current = match (&self.current_tool_call, self.current_tool_index) {
+                    (Some(current), Some(current_index)) => {
+                        current_index == tool_index && id.is_none_or(|id| id == current.id)
+                    }
+                    (Some(_), None) => id.is_none(),
+                    (None, _) => false,
+                };
+
+                let args = func
+                    .get("arguments")
+                    .and_then(|a| a.as_str())
+                    .filter(|a| !a.is_empty());
+
+                if !continues_current {
+                    // 已缓冲的并行调用：按 id（缺省时按 index）续写参数
+                    if let Some(pending) = self.pending_tools.iter_mut().rev().find(|p| match id {
+                        Some(id) => p.call.id == id,
+                        None => p.index == tool_index,
+                    }) {
+                        pending.call.arguments_buffer.push_str(args.unwrap_or(""));
+                        continue;
+                    }
+
+                    if self.seen_tool_indices.contains(&tool_index) && id.is_none() {
+                        // 已关闭的工具块无法再追加参数，Anthropic 事件流要求块按顺序闭合
+                        tracing::warn!(
+                            tool_index,
+                            "dropping interleaved tool call fragment for a closed block"
+                        );
+                        continue;
+                    }
+
+                    let truncated_name = func.get("name").and_then(|n| n.as_str()).unwrap_or("");
+                    // 还原被截断的工具名
+                    let name = self
+                        .tool_name_map
+                        .get(truncated_name)
+                        .cloned()
+                        .unwrap_or_else(|| truncated_name.to_string());
+                    let id = id
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("toolu_{}", uuid::Uuid::new_v4().simple()));
+
+                    // 交错的并行调用：当前块保持流式输出，新调用先缓冲，避免后续分片落到已关闭的块
+                    if self.current_tool_call.is_some() {
+                        self.pending_tools.push(PendingToolCall {
+                            index: tool_index,
+                            call: ToolCallState {
+                                id,
+                                name,
+                                arguments_buffer: args.unwrap_or("").to_string(),
+                            },
+                        });
+                        self.seen_tool_indices.push(tool_index);
+                        continue;
+                    }
 
-                // New tool call starts
-                if let Some(id) = tc.get("id").and_then(|id| id.as_str()) {
                     // Finalize previous blocks
+                    if let Some(prev_events) = self.finalize_tool_call() {
+                        events.extend(prev_events);
+                    }
                     if self.block_started {
                         events.push(format_sse(
                             "content_block_stop",
@@ -197,23 +311,15 @@ impl StreamState {
                         self.block_index += 1;
                         self.block_started = false;
                     }
-                    if let Some(prev_events) = self.finalize_tool_call() {
-                        events.extend(prev_events);
-                    }
-
-                    let truncated_name = func.get("name").and_then(|n| n.as_str()).unwrap_or("");
-                    // 还原被截断的工具名
-                    let name = self
-                        .tool_name_map
-                        .get(truncated_name)
-                        .cloned()
-                        .unwrap_or_else(|| truncated_name.to_string());
 
                     self.current_tool_call = Some(ToolCallState {
-                        id: id.to_string(),
+                        id: id.clone(),
                         name: name.clone(),
                         arguments_buffer: String::new(),
                     });
+                    self.current_tool_index = Some(tool_index);
+                    self.seen_tool_indices.push(tool_index);
+                    self.has_tool_use = true;
 
                     events.push(format_sse(
                         "content_block_start",
@@ -232,7 +338,7 @@ impl StreamState {
                 }
 
                 // Accumulate arguments
-                if let Some(args) = func.get("arguments").and_then(|a| a.as_str()) {
+                if let Some(args) = args {
                     if let Some(ref mut tool_state) = self.current_tool_call {
                         tool_state.arguments_buffer.push_str(args);
                         events.push(format_sse(
@@ -253,6 +359,7 @@ impl StreamState {
 
         // Handle finish_reason
         if let Some(finish) = choice.get("finish_reason").and_then(|f| f.as_str()) {
+            self.finish_reason = Some(finish.to_string());
             if finish == "tool_calls" {
                 if let Some(tool_events) = self.finalize_tool_call() {
                     events.extend(tool_events);
@@ -269,6 +376,7 @@ impl StreamState {
 
     fn finalize_tool_call(&mut self) -> Option<Vec<String>> {
         let _tool_state = self.current_tool_call.take()?;
+        self.current_tool_index = None;
         let mut events = Vec::new();
 
         if self.block_started {
@@ -283,6 +391,44 @@ impl StreamState {
             self.block_started = false;
         }
 
+        // 依次补发缓冲的并行调用，参数一次性输出
+        for pending in std::mem::take(&mut self.pending_tools) {
+            events.push(format_sse(
+                "content_block_start",
+                &json!({
+                    "type": "content_block_start",
+                    "index": self.block_index,
+                    "content_block": {
+                        "type": "tool_use",
+                        "id": pending.call.id,
+                        "name": pending.call.name,
+                        "input": {}
+                    }
+                }),
+            ));
+            if !pending.call.arguments_buffer.is_empty() {
+                events.push(format_sse(
+                    "content_block_delta",
+                    &json!({
+                        "type": "content_block_delta",
+                        "index": self.block_index,
+                        "delta": {
+                            "type": "input_json_delta",
+                            "partial_json": pending.call.arguments_buffer
+                        }
+                    }),
+                ));
+            }
+            events.push(format_sse(
+                "content_block_stop",
+                &json!({
+                    "type": "content_block_stop",
+                    "index": self.block_index,
+                }),
+            ));
+            self.block_index += 1;
+        }
+
         Some(events)
     }
 }
@@ -435,6 +581,22 @@ mod tests {
         assert_eq!(state.output_tokens, 42);
     }
 
+    #[test]
+    fn test_usage_only_chunk_tracked() {
+        let mut state = StreamState::new(std::collections::HashMap::new());
+        let line = format!(
+            "data: {}",
+            json!({
+                "choices": [],
+                "usage": {"prompt_tokens": 120, "completion_tokens": 8}
+            })
+        );
+        assert!(state.process_openai_line(&line).is_none());
+        let usage = state.final_usage();
+        assert_eq!(usage["input_tokens"], 120);
+        assert_eq!(usage["output_tokens"], 8);
+    }
+
     #[test]
     fn test_finalize_tool_call_no_pending() {
         let mut state = StreamState::new(std::collections::HashMap::new());
@@ -468,4 +630,69 @@ mod tests {
         state.process_openai_line(&line2);
         assert_eq!(state.block_index, 1); // incremented after closing text block
     }
+
+    #[test]
+    fn test_repeated_tool_id_continues_block() {
+        let mut state = StreamState::new(std::collections::HashMap::new());
+        let chunk = |args: &str| {
+            format!(
+                "data: {}",
+                json!({"choices": [{"delta": {"tool_calls": [{
+                    "index": 0, "id": "call_1", "function": {"name": "f", "arguments": args}
+                }]}}]})
+            )
+        };
+        state.process_openai_line(&chunk("{\"a\":"));
+        let events = state.process_openai_line(&chunk("1}")).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].contains("input_json_delta"));
+        assert_eq!(state.block_index, 0);
+        assert_eq!(state.stop_reason(), "tool_use");
+    }
+
+    #[test]
+    fn test_interleaved_tool_fragments_are_buffered() {
+        let mut state = StreamState::new(std::collections::HashMap::new());
+        let chunk = |tool_call: Value| {
+            format!(
+                "data: {}",
+                json!({"choices": [{"delta": {"tool_calls": [tool_call]}}]})
+            )
+        };
+        state.process_openai_line(&chunk(
+            json!({"index": 0, "id": "call_a", "function": {"name": "a", "arguments": "{\"x\":"}}),
+        ));
+        let events = state.process_openai_line(&chunk(
+            json!({"index": 1, "id": "call_b", "function": {"name": "b", "arguments": "{\"y\":"}}),
+        ));
+        assert!(events.is_none());
+        state.process_openai_line(&chunk(json!({"index": 0, "function": {"arguments": "1}"}})));
+        state.process_openai_line(&chunk(json!({"index": 1, "function": {"arguments": "2}"}})));
+        assert_eq!(
+            state.current_tool_call.as_ref().unwrap().arguments_buffer,
+            "{\"x\":1}"
+        );
+
+        let line = format!(
+            "data: {}",
+            json!({"choices": [{"delta": {}, "finish_reason": "tool_calls"}]})
+        );
+        let events = state.process_openai_line(&line).unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(events[1].contains("call_b"));
+        assert!(events[2].contains(r#"{\"y\":2}"#));
+        assert_eq!(state.block_index, 2);
+        assert!(state.pending_tools.is_empty());
+    }
+
+    #[test]
+    fn test_length_finish_maps_to_max_tokens() {
+        let mut state = StreamState::new(std::collections::HashMap::new());
+        let line = format!(
+            "data: {}",
+            json!({"choices": [{"delta": {}, "finish_reason": "length"}]})
+        );
+        state.process_openai_line(&line);
+        assert_eq!(state.stop_reason(), "max_tokens");
+    }
 }
@@ -297,10 +297,7 @@ pub fn responses_to_anthropic(resp: &Value, tool_name_map: &ToolNameMap) -> Resu
 
     // usage
     let usage = resp.get("usage").cloned().unwrap_or(json!({}));
-    let anthropic_usage = json!({
-        "input_tokens": usage.get("input_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
-        "output_tokens": usage.get("output_tokens").and_then(|v| v.as_u64()).unwrap_or(0),
-    });
+    let anthropic_usage = responses_usage_to_anthropic(&usage);
 
     let model = resp
         .get("model")
@@ -320,6 +317,33 @@ pub fn responses_to_anthropic(resp: &Value, tool_name_map: &ToolNameMap) -> Resu
     }))
 }
 
+/// Responses API usage → Anthropic usage（input_tokens 扣除缓存命中部分）
+pub fn responses_usage_to_anthropic(usage: &Value) -> Value {
+    let input_tokens = usage
+        .get("input_tokens")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
+    let output_tokens = usage
+        .get("output_tokens")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
+    let cached_tokens = usage
+        .get("input_tokens_details")
+        .and_then(|d| d.get("cached_tokens"))
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0)
+        .min(input_tokens);
+
+    let mut result = json!({
+        "input_tokens": input_tokens - cached_tokens,
+        "output_tokens": output_tokens,
+    });
+    if cached_tokens > 0 {
+        result["cache_read_input_tokens"] = json!(cached_tokens);
+    }
+    result
+}
+
 fn convert_user_content(content: Option<&Value>) -> Vec<Value> {
     match content {
         Some(Value::String(s)) => vec![json!({"type": "input_text", "text": s})],
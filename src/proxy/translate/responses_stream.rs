@@ -3,7 +3,7 @@ use futures::stream::{Stream, StreamExt};
 use serde_json::{json, Value};
 use std::pin::Pin;
 
-use crate::proxy::util::{format_sse, ToolNameMap};
+use crate::proxy::util::{format_sse, ToolNameMap, Utf8Chunker};
 
 /// Translates an OpenAI Responses API SSE stream to Anthropic SSE format.
 ///
@@ -37,15 +37,16 @@ where
 
         let mut stream = std::pin::pin!(input);
         let mut buffer = String::new();
+        let mut decoder = Utf8Chunker::default();
 
         while let Some(chunk_result) = stream.next().await {
             match chunk_result {
                 Ok(chunk) => {
-                    buffer.push_str(&String::from_utf8_lossy(&chunk));
+                    buffer.push_str(&decoder.push(&chunk));
 
                     // Process complete SSE events (separated by double newline or single newline)
                     while let Some(pos) = buffer.find('\n') {
-                        let line = buffer[..pos].to_string();
+                        let line = buffer[..pos].trim_end_matches('\r').to_string();
                         buffer = buffer[pos + 1..].to_string();
 
                         if line.is_empty() {
@@ -64,6 +65,11 @@ where
             }
         }
 
+        // 上游未以换行结尾时处理残留的最后一行
+        for event in state.process_line(buffer.trim_end_matches('\r')) {
+            yield Ok(Bytes::from(event));
+        }
+
         // Finalize: close any open block and send message_delta + message_stop
         if state.block_started {
             yield Ok(Bytes::from(format_sse("content_block_stop", &json!({
@@ -76,7 +82,7 @@ where
         yield Ok(Bytes::from(format_sse("message_delta", &json!({
             "type": "message_delta",
             "delta": {"stop_reason": stop_reason, "stop_sequence": null},
-            "usage": {"output_tokens": state.output_tokens}
+            "usage": state.final_usage()
         }))));
         yield Ok(Bytes::from(format_sse("message_stop", &json!({"type": "message_stop"}))));
     };
@@ -88,9 +94,15 @@ struct ResponsesStreamState {
     tool_name_map: ToolNameMap,
     block_index: usize,
     block_started: bool,
+    /// 当前打开的块是否为 tool_use
+    block_is_tool: bool,
+    /// 当前工具块是否已收到参数分片
+    tool_args_streamed: bool,
     has_tool_use: bool,
     stop_reason: String,
     output_tokens: u64,
+    /// response.completed 中的完整 usage（Anthropic 格式）
+    usage: Option<Value>,
 }
 
 impl ResponsesStreamState {
@@ -99,10 +111,38 @@ impl ResponsesStreamState {
             tool_name_map,
             block_index: 0,
             block_started: false,
+            block_is_tool: false,
+            tool_args_streamed: false,
             has_tool_use: false,
             stop_reason: "end_turn".to_string(),
             output_tokens: 0,
+            usage: None,
+        }
+    }
+
+    /// 最终 message_delta 的 usage：优先使用上游完整 usage
+    fn final_usage(&self) -> Value {
+        self.usage
+            .clone()
+            .unwrap_or_else(|| json!({"output_tokens": self.output_tokens}))
+    }
+
+    /// 关闭当前打开的 tool_use 块（文本块保持不变）
+    fn close_tool_block(&mut self) -> Vec<String> {
+        if !self.block_started || !self.block_is_tool {
+            return vec![];
         }
+        self.block_started = false;
+        self.block_is_tool = false;
+        let event = format_sse(
+            "content_block_stop",
+            &json!({
+                "type": "content_block_stop",
+                "index": self.block_index,
+            }),
+        );
+        self.block_index += 1;
+        vec![event]
     }
 
     fn process_line(&mut self, line: &str) -> Vec<String> {
@@ -134,7 +174,8 @@ impl ResponsesStreamState {
                     return vec![];
                 }
 
-                let mut events = Vec::new();
+                // 工具块未闭合时文本另起新块
+                let mut events = self.close_tool_block();
 
                 // Start content block if not started
                 if !self.block_started {
@@ -147,6 +188,7 @@ impl ResponsesStreamState {
                         }),
                     ));
                     self.block_started = true;
+                    self.block_is_tool = false;
                 }
 
                 events.push(format_sse(
@@ -161,7 +203,7 @@ impl ResponsesStreamState {
                 events
             }
             "response.output_text.done" | "response.content_part.done" => {
-                if self.block_started {
+                if self.block_started && !self.block_is_tool {
                     self.block_started = false;
                     let event = format_sse(
                         "content_block_stop",
@@ -225,6 +267,8 @@ impl ResponsesStreamState {
                         }),
                     ));
                     self.block_started = true;
+                    self.block_is_tool = true;
+                    self.tool_args_streamed = false;
 
                     return events;
                 }
@@ -232,9 +276,10 @@ impl ResponsesStreamState {
             }
             "response.function_call_arguments.delta" => {
                 let delta = json.get("delta").and_then(|d| d.as_str()).unwrap_or("");
-                if delta.is_empty() {
+                if delta.is_empty() || !self.block_is_tool {
                     return vec![];
                 }
+                self.tool_args_streamed = true;
 
                 vec![format_sse(
                     "content_block_delta",
@@ -246,21 +291,28 @@ impl ResponsesStreamState {
                 )]
             }
             "response.function_call_arguments.done" => {
-                if self.block_started {
-                    self.block_started = false;
-                    let event = format_sse(
-                        "content_block_stop",
-                        &json!({
-                            "type": "content_block_stop",
-                            "index": self.block_index,
-                        }),
-                    );
-                    self.block_index += 1;
-                    return vec![event];
+                let mut events = Vec::new();
+                // 部分上游只在 done 事件中给出完整参数
+                if self.block_is_tool && !self.tool_args_streamed {
+                    if let Some(args) = json
+                        .get("arguments")
+                        .and_then(|a| a.as_str())
+                        .filter(|a| !a.is_empty())
+                    {
+                        events.push(format_sse(
+                            "content_block_delta",
+                            &json!({
+                                "type": "content_block_delta",
+                                "index": self.block_index,
+                                "delta": {"type": "input_json_delta", "partial_json": args},
+                            }),
+                        ));
+                    }
                 }
-                vec![]
+                events.extend(self.close_tool_block());
+                events
             }
-            "response.completed" => {
+            "response.completed" | "response.incomplete" => {
                 // Extract usage from the completed response
                 if let Some(resp) = json.get("response") {
                     if let Some(usage) = resp.get("usage") {
@@ -268,6 +320,9 @@ impl ResponsesStreamState {
                             .get("output_tokens")
                             .and_then(|v| v.as_u64())
                             .unwrap_or(0);
+                        self.usage = Some(
+                            crate::proxy::translate::responses::responses_usage_to_anthropic(usage),
+                        );
                     }
                     let status = resp
                         .get("status")
@@ -341,5 +396,8 @@ mod tests {
             r#"data: {"type":"response.completed","response":{"status":"completed","usage":{"input_tokens":100,"output_tokens":50,"total_tokens":150}}}"#,
         );
         assert_eq!(state.output_tokens, 50);
+        let usage = state.final_usage();
+        assert_eq!(usage["input_tokens"], 100);
+        assert_eq!(usage["output_tokens"], 50);
     }
 }
@@ -0,0 +1,450 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::{Local, NaiveDate, TimeZone};
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::adapter::ByteStream;
+use super::budget::BudgetTracker;
+use super::metrics::ProfileMetrics;
+use super::pricing::{format_cost, ModelPrice, TokenUsage};
+use super::util::Utf8Chunker;
+
+/// 用量账本中的一条记录（JSONL，一行一条）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    /// Unix 时间戳（毫秒）
+    pub timestamp: i64,
+    pub profile: String,
+    pub model: String,
+    #[serde(flatten)]
+    pub usage: TokenUsage,
+    /// 估算费用（美元），模型无价格时为 0
+    pub cost_usd: f64,
+}
+
+/// 用量账本路径（~/.local/share/claudex/usage.jsonl）
+pub fn ledger_path() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|d| d.join("claudex").join("usage.jsonl"))
+}
+
+/// 追加一条用量记录
+pub fn append_record(record: &UsageRecord) -> Result<()> {
+    let path = ledger_path().context("cannot determine data directory")?;
+    append_record_to(&path, record)
+}
+
+/// 追加一条用量记录到指定账本文件
+pub fn append_record_to(path: &Path, record: &UsageRecord) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// 读取 [from, to]（本地日期，含两端）范围内的记录，损坏的行直接跳过
+pub fn load_records(from: NaiveDate, to: NaiveDate) -> Result<Vec<UsageRecord>> {
+    let path = match ledger_path() {
+        Some(p) if p.exists() => p,
+        _ => return Ok(Vec::new()),
+    };
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(parse_records(&content, from, to))
+}
+
+fn parse_records(content: &str, from: NaiveDate, to: NaiveDate) -> Vec<UsageRecord> {
+    content
+        .lines()
+        .filter_map(|line| serde_json::from_str::<UsageRecord>(line).ok())
+        .filter(|r| match Local.timestamp_millis_opt(r.timestamp).single() {
+            Some(ts) => {
+                let day = ts.date_naive();
+                day >= from && day <= to
+            }
+            None => false,
+        })
+        .collect()
+}
+
+/// 一次请求的用量记录器：按实际响应的 profile 与上游模型计价，
+/// 更新 metrics、预算并写入账本
+pub struct UsageRecorder {
+    pub metrics: Arc<ProfileMetrics>,
+    pub budgets: BudgetTracker,
+    pub profile: String,
+    pub model: String,
+    pub price: Option<ModelPrice>,
+    /// 用量账本文件（None 表示不落盘）
+    pub ledger: Option<PathBuf>,
+}
+
+impl UsageRecorder {
+    pub fn record(&self, usage: TokenUsage) {
+        if usage.is_empty() {
+            return;
+        }
+        let cost = self.price.map(|p| p.cost(&usage)).unwrap_or(0.0);
+        self.metrics.record_usage(&usage, cost);
+        self.budgets.record(&self.profile, &usage, cost);
+
+        let record = UsageRecord {
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            profile: self.profile.clone(),
+            model: self.model.clone(),
+            usage,
+            cost_usd: cost,
+        };
+        if let Some(ref ledger) = self.ledger {
+            if let Err(e) = append_record_to(ledger, &record) {
+                tracing::warn!(error = %e, "failed to write usage record");
+            }
+        }
+        tracing::debug!(
+            profile = %self.profile,
+            model = %self.model,
+            input_tokens = usage.input_tokens,
+            output_tokens = usage.output_tokens,
+            cost = %format_cost(cost),
+            "recorded usage"
+        );
+    }
+
+    /// 从非流式 Anthropic 格式响应中提取 usage 并记录
+    pub fn record_response(&self, resp_body: &Value) {
+        if let Some(usage) = resp_body.get("usage") {
+            self.record(TokenUsage::from_anthropic(usage));
+        }
+    }
+
+    /// 包装流式响应，流结束时记录 usage
+    pub fn tap_stream(self, stream: ByteStream) -> ByteStream {
+        tap_sse_usage(stream, move |usage| self.record(usage))
+    }
+}
+
+/// 包装 Anthropic 格式的 SSE 输出流，从 message_start / message_delta 中收集 usage，
+/// 流结束时回调 `on_done`；客户端中途断开（流被丢弃）时按已收集的部分回调。数据原样透传。
+pub fn tap_sse_usage<F>(input: ByteStream, on_done: F) -> ByteStream
+where
+    F: FnOnce(TokenUsage) + Send + 'static,
+{
+    let output = async_stream::stream! {
+        let mut stream = input;
+        let mut decoder = Utf8Chunker::default();
+        let mut buffer = String::new();
+        let mut collected = UsageGuard {
+            usage: TokenUsage::default(),
+            on_done: Some(on_done),
+        };
+
+        while let Some(chunk) = stream.next().await {
+            if let Ok(ref bytes) = chunk {
+                buffer.push_str(&decoder.push(bytes));
+                while let Some(pos) = buffer.find('\n') {
+                    let line: String = buffer.drain(..=pos).collect();
+                    if let Some(found) = usage_from_sse_line(&line) {
+                        collected.usage.merge(&found);
+                    }
+                }
+            }
+            yield chunk;
+        }
+    };
+    Box::pin(output)
+}
+
+/// 持有流中已收集的 usage，drop 时（正常结束或被中途丢弃）回调一次
+struct UsageGuard<F: FnOnce(TokenUsage)> {
+    usage: TokenUsage,
+    on_done: Option<F>,
+}
+
+impl<F: FnOnce(TokenUsage)> Drop for UsageGuard<F> {
+    fn drop(&mut self) {
+        if let Some(on_done) = self.on_done.take() {
+            on_done(self.usage);
+        }
+    }
+}
+
+fn usage_from_sse_line(line: &str) -> Option<TokenUsage> {
+    let data = line.trim().strip_prefix("data:")?.trim();
+    let event: Value = serde_json::from_str(data).ok()?;
+    match event.get("type").and_then(|t| t.as_str())? {
+        "message_start" => Some(TokenUsage::from_anthropic(
+            event.get("message")?.get("usage")?,
+        )),
+        "message_delta" => Some(TokenUsage::from_anthropic(event.get("usage")?)),
+        _ => None,
+    }
+}
+
+// ── `claudex cost` report ──
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CostSummary {
+    pub requests: u64,
+    pub usage: TokenUsage,
+    pub cost_usd: f64,
+}
+
+impl CostSummary {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.usage.input_tokens += record.usage.input_tokens;
+        self.usage.output_tokens += record.usage.output_tokens;
+        self.usage.cache_read_tokens += record.usage.cache_read_tokens;
+        self.usage.cache_write_tokens += record.usage.cache_write_tokens;
+        self.cost_usd += record.cost_usd;
+    }
+}
+
+/// 按 (profile, model) 汇总
+pub fn summarize(records: &[UsageRecord]) -> BTreeMap<(String, String), CostSummary> {
+    let mut groups: BTreeMap<(String, String), CostSummary> = BTreeMap::new();
+    for r in records {
+        groups
+            .entry((r.profile.clone(), r.model.clone()))
+            .or_default()
+            .add(r);
+    }
+    groups
+}
+
+/// 汇总单个 profile 在日期范围内的费用
+pub fn profile_summary(profile: &str, from: NaiveDate, to: NaiveDate) -> Result<CostSummary> {
+    let mut summary = CostSummary::default();
+    for r in load_records(from, to)?
+        .iter()
+        .filter(|r| r.profile == profile)
+    {
+        summary.add(r);
+    }
+    Ok(summary)
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .with_context(|| format!("invalid date '{s}', expected YYYY-MM-DD"))
+}
+
+/// `claudex cost`：打印日期范围内的费用报告（默认最近 30 天）
+pub fn print_cost_report(
+    from: Option<&str>,
+    to: Option<&str>,
+    profile: Option<&str>,
+    json: bool,
+) -> Result<()> {
+    let today = Local::now().date_naive();
+    let to = match to {
+        Some(s) => parse_date(s)?,
+        None => today,
+    };
+    let from = match from {
+        Some(s) => parse_date(s)?,
+        None => to - chrono::Duration::days(29),
+    };
+    if from > to {
+        anyhow::bail!("--from ({from}) is after --to ({to})");
+    }
+
+    let mut records = load_records(from, to)?;
+    if let Some(name) = profile {
+        records.retain(|r| r.profile == name);
+    }
+    let groups = summarize(&records);
+
+    if json {
+        let rows: Vec<Value> = groups
+            .iter()
+            .map(|((profile, model), s)| {
+                serde_json::json!({
+                    "profile": profile,
+                    "model": model,
+                    "requests": s.requests,
+                    "input_tokens": s.usage.input_tokens,
+                    "output_tokens": s.usage.output_tokens,
+                    "cache_read_tokens": s.usage.cache_read_tokens,
+                    "cache_write_tokens": s.usage.cache_write_tokens,
+                    "cost_usd": s.cost_usd,
+                })
+            })
+            .collect();
+        let out = serde_json::json!({
+            "from": from.to_string(),
+            "to": to.to_string(),
+            "rows": rows,
+            "total_usd": groups.values().map(|s| s.cost_usd).sum::<f64>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+
+    println!("Cost report: {from} → {to}");
+    println!();
+    if groups.is_empty() {
+        println!("No usage recorded in this period.");
+        return Ok(());
+    }
+
+    println!(
+        "{:<16} {:<28} {:>8} {:>12} {:>12} {:>12} {:>10}",
+        "PROFILE", "MODEL", "REQS", "INPUT", "OUTPUT", "CACHED", "COST"
+    );
+    println!("{}", "-".repeat(104));
+    let mut total = CostSummary::default();
+    for ((profile, model), s) in &groups {
+        println!(
+            "{:<16} {:<28} {:>8} {:>12} {:>12} {:>12} {:>10}",
+            profile,
+            model,
+            s.requests,
+            s.usage.input_tokens,
+            s.usage.output_tokens,
+            s.usage.cache_read_tokens + s.usage.cache_write_tokens,
+            format_cost(s.cost_usd)
+        );
+        total.requests += s.requests;
+        total.usage.input_tokens += s.usage.input_tokens;
+        total.usage.output_tokens += s.usage.output_tokens;
+        total.usage.cache_read_tokens += s.usage.cache_read_tokens;
+        total.usage.cache_write_tokens += s.usage.cache_write_tokens;
+        total.cost_usd += s.cost_usd;
+    }
+    println!("{}", "-".repeat(104));
+    println!(
+        "{:<16} {:<28} {:>8} {:>12} {:>12} {:>12} {:>10}",
+        "TOTAL",
+        "",
+        total.requests,
+        total.usage.input_tokens,
+        total.usage.output_tokens,
+        total.usage.cache_read_tokens + total.usage.cache_write_tokens,
+        format_cost(total.cost_usd)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::stream;
+
+    fn record(profile: &str, model: &str, day: &str, cost: f64) -> String {
+        let ts = NaiveDate::parse_from_str(day, "%Y-%m-%d")
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_local_timezone(Local)
+            .unwrap()
+            .timestamp_millis();
+        serde_json::to_string(&serde_json::json!({
+            "timestamp": ts,
+            "profile": profile,
+            "model": model,
+            "input_tokens": 100,
+            "output_tokens": 10,
+            "cost_usd": cost,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_records_date_filter() {
+        let content = [
+            record("a", "m", "2025-01-01", 1.0),
+            record("a", "m", "2025-01-15", 2.0),
+            "not json".to_string(),
+            record("b", "m", "2025-02-01", 4.0),
+        ]
+        .join("\n");
+        let from = NaiveDate::from_ymd_opt(2025, 1, 10).unwrap();
+        let to = NaiveDate::from_ymd_opt(2025, 1, 31).unwrap();
+        let records = parse_records(&content, from, to);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].cost_usd, 2.0);
+        assert_eq!(records[0].usage.input_tokens, 100);
+    }
+
+    #[test]
+    fn test_summarize_groups_by_profile_and_model() {
+        let content = [
+            record("a", "m1", "2025-01-01", 1.0),
+            record("a", "m1", "2025-01-02", 2.0),
+            record("a", "m2", "2025-01-02", 0.5),
+        ]
+        .join("\n");
+        let from = NaiveDate::from_ymd_opt(2024, 12, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2025, 2, 1).unwrap();
+        let groups = summarize(&parse_records(&content, from, to));
+        assert_eq!(groups.len(), 2);
+        let s = groups[&("a".to_string(), "m1".to_string())];
+        assert_eq!(s.requests, 2);
+        assert_eq!(s.usage.input_tokens, 200);
+        assert!((s.cost_usd - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_usage_from_sse_lines() {
+        let start = r#"data: {"type":"message_start","message":{"usage":{"input_tokens":42,"output_tokens":1}}}"#;
+        let delta = r#"data: {"type":"message_delta","delta":{},"usage":{"output_tokens":17}}"#;
+        assert_eq!(usage_from_sse_line(start).unwrap().input_tokens, 42);
+        assert_eq!(usage_from_sse_line(delta).unwrap().output_tokens, 17);
+        assert!(usage_from_sse_line("event: message_start").is_none());
+        assert!(usage_from_sse_line(r#"data: {"type":"ping"}"#).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tap_sse_usage_collects_and_passes_through() {
+        let chunks: Vec<Result<Bytes, reqwest::Error>> = vec![
+            Ok(Bytes::from(
+                "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":30,",
+            )),
+            Ok(Bytes::from(
+                "\"output_tokens\":0}}}\n\nevent: message_delta\ndata: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":9}}\n\n",
+            )),
+        ];
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tapped = tap_sse_usage(Box::pin(stream::iter(chunks)), move |u| {
+            tx.send(u).unwrap();
+        });
+        let out: Vec<_> = tapped.collect().await;
+        assert_eq!(out.len(), 2);
+        let usage = rx.recv().unwrap();
+        assert_eq!(usage.input_tokens, 30);
+        assert_eq!(usage.output_tokens, 9);
+    }
+
+    #[tokio::test]
+    async fn test_tap_sse_usage_records_on_disconnect() {
+        let chunks: Vec<Result<Bytes, reqwest::Error>> = vec![Ok(Bytes::from(
+            "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":30,\"output_tokens\":0}}}\n\n",
+        ))];
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut tapped = tap_sse_usage(
+            Box::pin(stream::iter(chunks).chain(stream::pending())),
+            move |u| tx.send(u).unwrap(),
+        );
+        tapped.next().await.unwrap().unwrap();
+        assert!(rx.try_recv().is_err());
+
+        // 客户端中断：流被丢弃，按已收集的 usage 记录
+        drop(tapped);
+        assert_eq!(rx.recv().unwrap().input_tokens, 30);
+        assert!(rx.try_recv().is_err());
+    }
+}
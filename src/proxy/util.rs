@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use serde_json::{json, Value};
 
 /// OpenAI 工具名最大长度
@@ -29,6 +31,27 @@ pub fn format_sse(event: &str, data: &Value) -> String {
     )
 }
 
+/// 把分块字节拼成 UTF-8 字符串，未完整的多字节字符留到下一块
+#[derive(Debug, Default)]
+pub struct Utf8Chunker {
+    pending: Vec<u8>,
+}
+
+impl Utf8Chunker {
+    pub fn push(&mut self, bytes: &[u8]) -> String {
+        self.pending.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let rest = self.pending.split_off(valid);
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending = rest;
+        text
+    }
+}
+
 /// API key 预览（显示首尾各 4 字符）
 pub fn format_key_preview(key: &str) -> String {
     if key.is_empty() {
@@ -58,6 +81,41 @@ pub fn to_anthropic_error(status: u16, message: &str) -> Value {
     })
 }
 
+/// 简单 glob 匹配：`*` 匹配任意长度字符，`?` 匹配单个字符
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // 最近一个 `*` 的位置及其已匹配到的 text 位置，用于回溯
+    let mut star: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+/// 构造 Anthropic 格式的错误响应（JSON body + 对应状态码）
+pub fn anthropic_error_response(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        [("content-type", "application/json")],
+        to_anthropic_error(status.as_u16(), message).to_string(),
+    )
+        .into_response()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -92,6 +150,17 @@ mod tests {
         assert_eq!(r1.len(), 64);
     }
 
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("claude-*haiku*", "claude-3-5-haiku-20241022"));
+        assert!(glob_match("claude-haiku-?-5", "claude-haiku-4-5"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("gpt-4o", "gpt-4o"));
+        assert!(!glob_match("gpt-4o", "gpt-4o-mini"));
+        assert!(!glob_match("claude-*opus*", "claude-sonnet-4"));
+        assert!(!glob_match("?", ""));
+    }
+
     #[test]
     fn test_format_sse() {
         let data = json!({"type": "test"});
@@ -122,4 +191,14 @@ mod tests {
         assert_eq!(err["error"]["type"], "authentication_error");
         assert_eq!(err["error"]["message"], "invalid key");
     }
+
+    #[test]
+    fn test_anthropic_error_response_status() {
+        let resp = anthropic_error_response(StatusCode::TOO_MANY_REQUESTS, "slow down");
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            resp.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+    }
 }
@@ -1,5 +1,8 @@
 use anyhow::Result;
-use serde_json::{json, Value};
+use serde_json::Value;
+
+use crate::proxy::internal::{self, InternalRequest};
+use crate::proxy::ProxyState;
 
 const CLASSIFICATION_PROMPT: &str = r#"Analyze the following user request and classify its intent into exactly ONE of these categories:
 - code: Code generation, modification, debugging, or programming tasks
@@ -10,43 +13,30 @@ const CLASSIFICATION_PROMPT: &str = r#"Analyze the following user request and cl
 
 Respond with ONLY the category name, nothing else."#;
 
+/// 用分类 profile 判断用户请求的意图（经 ProviderAdapter，支持任意 provider 类型与 OAuth）
 pub async fn classify_intent(
-    base_url: &str,
-    api_key: &str,
+    state: &ProxyState,
+    profile: &str,
     model: &str,
     prompt: &str,
-    http_client: &reqwest::Client,
 ) -> Result<String> {
-    let url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
-
-    let body = json!({
-        "model": model,
-        "messages": [
-            {"role": "system", "content": CLASSIFICATION_PROMPT},
-            {"role": "user", "content": prompt},
-        ],
-        "max_tokens": 10,
-        "temperature": 0.0,
-    });
-
-    let mut req = http_client.post(&url).json(&body);
-    if !api_key.is_empty() {
-        req = req.header("Authorization", format!("Bearer {api_key}"));
+    let reply = internal::complete(
+        state,
+        &InternalRequest {
+            profile,
+            model,
+            system: CLASSIFICATION_PROMPT,
+            prompt,
+            max_tokens: 10,
+            temperature: 0.0,
+        },
+    )
+    .await?;
+
+    let intent = reply.text.trim().to_lowercase();
+    if intent.is_empty() {
+        return Ok("default".to_string());
     }
-
-    let resp: Value = req.send().await?.json().await?;
-
-    let intent = resp
-        .get("choices")
-        .and_then(|c| c.as_array())
-        .and_then(|c| c.first())
-        .and_then(|c| c.get("message"))
-        .and_then(|m| m.get("content"))
-        .and_then(|c| c.as_str())
-        .unwrap_or("default")
-        .trim()
-        .to_lowercase();
-
     Ok(intent)
 }
 
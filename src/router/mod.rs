@@ -1,4 +1,6 @@
 pub mod classifier;
+pub mod rules;
+pub mod sticky;
 
 use std::collections::HashMap;
 
@@ -14,6 +16,12 @@ pub struct RouterConfig {
     pub model: String,
     #[serde(default)]
     pub rules: HashMap<String, String>,
+    /// 确定性规则（`[[router.routes]]`），先于分类器执行，命中则不再调用 LLM
+    #[serde(default)]
+    pub routes: Vec<rules::RouteRule>,
+    /// 会话粘性路由（默认开启）
+    #[serde(default)]
+    pub sticky: sticky::StickyConfig,
 }
 
 impl RouterConfig {
@@ -63,5 +71,6 @@ mod tests {
         assert!(config.profile.is_empty());
         assert!(config.model.is_empty());
         assert!(config.rules.is_empty());
+        assert!(config.routes.is_empty());
     }
 }
@@ -0,0 +1,338 @@
+use axum::http::HeaderMap;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+use super::classifier::extract_last_user_message;
+
+/// `claudex run` 注入的工作目录 header，供规则按项目路由
+pub const CWD_HEADER: &str = "x-claudex-cwd";
+
+/// 确定性路由规则：所有已设置的条件都满足时命中，按配置顺序取第一条
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RouteRule {
+    /// 规则名（仅用于日志）
+    #[serde(default)]
+    pub name: Option<String>,
+    /// 命中后使用的 profile（或 pool）
+    pub profile: String,
+    /// 最后一条用户消息的正则
+    #[serde(default)]
+    pub message: Option<RulePattern>,
+    /// 估算请求 token 数下限 / 上限（含）
+    #[serde(default)]
+    pub min_tokens: Option<u64>,
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+    #[serde(default)]
+    pub has_images: Option<bool>,
+    #[serde(default)]
+    pub has_tools: Option<bool>,
+    /// 是否开启 extended thinking
+    #[serde(default)]
+    pub thinking: Option<bool>,
+    /// 请求模型名的正则
+    #[serde(default)]
+    pub model: Option<RulePattern>,
+    /// 工作目录（`x-claudex-cwd` header）的正则
+    #[serde(default)]
+    pub cwd: Option<RulePattern>,
+}
+
+/// 规则中的正则：加载配置时编译一次；无效正则保留原文与错误信息，
+/// 匹配时视为不满足，由 `claudex config validate` 报告
+#[derive(Debug, Clone)]
+pub struct RulePattern {
+    source: String,
+    regex: Result<Regex, String>,
+}
+
+impl RulePattern {
+    pub fn new(source: impl Into<String>) -> Self {
+        let source = source.into();
+        let regex = Regex::new(&source).map_err(|e| e.to_string());
+        Self { source, regex }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// 编译错误（正则有效时为 None）
+    pub fn error(&self) -> Option<&str> {
+        self.regex.as_ref().err().map(String::as_str)
+    }
+
+    fn is_match(&self, value: &str) -> bool {
+        self.regex.as_ref().is_ok_and(|re| re.is_match(value))
+    }
+}
+
+impl PartialEq for RulePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for RulePattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for RulePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = Self::new(String::deserialize(deserializer)?);
+        if let Some(e) = pattern.error() {
+            tracing::warn!(pattern = %pattern.source, error = %e, "invalid routing rule regex");
+        }
+        Ok(pattern)
+    }
+}
+
+/// 从请求中提取的路由特征
+#[derive(Debug, Clone, Default)]
+pub struct RequestFeatures {
+    pub last_user_message: String,
+    pub est_tokens: u64,
+    pub has_images: bool,
+    pub has_tools: bool,
+    pub thinking: bool,
+    pub model: Option<String>,
+    pub cwd: Option<String>,
+}
+
+impl RequestFeatures {
+    pub fn extract(body: &Value, headers: &HeaderMap) -> Self {
+        Self {
+            last_user_message: extract_last_user_message(body).unwrap_or_default(),
+            // 规则在选定 profile 之前匹配：按请求模型估算，缺省按 Claude 计
+            est_tokens: crate::proxy::rate_limit::estimate_request_tokens(body, "claude"),
+            has_images: body
+                .get("messages")
+                .and_then(|m| m.as_array())
+                .is_some_and(|msgs| msgs.iter().any(|m| contains_image(&m["content"]))),
+            has_tools: body
+                .get("tools")
+                .and_then(|t| t.as_array())
+                .is_some_and(|t| !t.is_empty()),
+            thinking: body
+                .get("thinking")
+                .and_then(|t| t.get("type"))
+                .and_then(|t| t.as_str())
+                .is_some_and(|t| t != "disabled"),
+            model: body.get("model").and_then(|m| m.as_str()).map(String::from),
+            cwd: headers
+                .get(CWD_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+        }
+    }
+}
+
+/// 内容块（含 tool_result 嵌套内容）中是否有图片
+fn contains_image(content: &Value) -> bool {
+    content.as_array().is_some_and(|blocks| {
+        blocks.iter().any(|b| {
+            b.get("type").and_then(|t| t.as_str()) == Some("image")
+                || b.get("content").is_some_and(contains_image)
+        })
+    })
+}
+
+impl RouteRule {
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.profile)
+    }
+
+    /// 无法编译的正则：(字段名, pattern)
+    pub fn invalid_patterns(&self) -> Vec<(&'static str, &RulePattern)> {
+        [
+            ("message", &self.message),
+            ("model", &self.model),
+            ("cwd", &self.cwd),
+        ]
+        .into_iter()
+        .filter_map(|(field, pattern)| Some((field, pattern.as_ref()?)))
+        .filter(|(_, pattern)| pattern.error().is_some())
+        .collect()
+    }
+
+    pub fn matches(&self, f: &RequestFeatures) -> bool {
+        regex_matches(&self.message, Some(&f.last_user_message))
+            && self.min_tokens.is_none_or(|min| f.est_tokens >= min)
+            && self.max_tokens.is_none_or(|max| f.est_tokens <= max)
+            && self.has_images.is_none_or(|v| v == f.has_images)
+            && self.has_tools.is_none_or(|v| v == f.has_tools)
+            && self.thinking.is_none_or(|v| v == f.thinking)
+            && regex_matches(&self.model, f.model.as_deref())
+            && regex_matches(&self.cwd, f.cwd.as_deref())
+    }
+}
+
+/// 未设置 pattern 视为满足；设置了但值缺失或正则无效视为不满足
+fn regex_matches(pattern: &Option<RulePattern>, value: Option<&str>) -> bool {
+    let Some(pattern) = pattern else {
+        return true;
+    };
+    value.is_some_and(|value| pattern.is_match(value))
+}
+
+/// 按顺序返回第一条命中的规则
+pub fn first_match<'a>(
+    rules: &'a [RouteRule],
+    features: &RequestFeatures,
+) -> Option<&'a RouteRule> {
+    rules.iter().find(|r| r.matches(features))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(profile: &str) -> RouteRule {
+        RouteRule {
+            profile: profile.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn features(body: Value) -> RequestFeatures {
+        RequestFeatures::extract(&body, &HeaderMap::new())
+    }
+
+    #[test]
+    fn test_extract_features() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CWD_HEADER, "/home/me/work/api".parse().unwrap());
+        let body = json!({
+            "model": "claude-opus-4",
+            "thinking": {"type": "enabled", "budget_tokens": 2048},
+            "tools": [{"name": "Bash"}],
+            "messages": [
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": [
+                        {"type": "image", "source": {"type": "base64", "data": "AAAA"}}
+                    ]},
+                    {"type": "text", "text": "what is in this screenshot?"}
+                ]}
+            ]
+        });
+        let f = RequestFeatures::extract(&body, &headers);
+        assert_eq!(f.last_user_message, "what is in this screenshot?");
+        assert!(f.has_images);
+        assert!(f.has_tools);
+        assert!(f.thinking);
+        assert_eq!(f.model.as_deref(), Some("claude-opus-4"));
+        assert_eq!(f.cwd.as_deref(), Some("/home/me/work/api"));
+        assert!(f.est_tokens > 0);
+    }
+
+    #[test]
+    fn test_all_conditions_must_match() {
+        let r = RouteRule {
+            message: Some(RulePattern::new("(?i)refactor")),
+            has_tools: Some(true),
+            ..rule("deepseek")
+        };
+        let with_tools = json!({
+            "tools": [{"name": "Edit"}],
+            "messages": [{"role": "user", "content": "Refactor this module"}]
+        });
+        assert!(r.matches(&features(with_tools)));
+        let without_tools = json!({
+            "messages": [{"role": "user", "content": "Refactor this module"}]
+        });
+        assert!(!r.matches(&features(without_tools)));
+    }
+
+    #[test]
+    fn test_token_bounds_and_model() {
+        let small = RouteRule {
+            max_tokens: Some(100),
+            model: Some(RulePattern::new("haiku")),
+            ..rule("groq")
+        };
+        let big = RouteRule {
+            min_tokens: Some(100),
+            ..rule("gemini")
+        };
+        let rules = vec![small, big];
+        let short = json!({
+            "model": "claude-3-5-haiku",
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        assert_eq!(
+            first_match(&rules, &features(short)).unwrap().profile,
+            "groq"
+        );
+        let long = json!({
+            "model": "claude-3-5-haiku",
+            "messages": [{"role": "user", "content": "x".repeat(2000)}]
+        });
+        assert_eq!(
+            first_match(&rules, &features(long)).unwrap().profile,
+            "gemini"
+        );
+    }
+
+    #[test]
+    fn test_missing_value_or_bad_regex_does_not_match() {
+        let cwd = RouteRule {
+            cwd: Some(RulePattern::new("^/work/")),
+            ..rule("work")
+        };
+        assert!(!cwd.matches(&features(json!({"messages": []}))));
+        let bad = RouteRule {
+            message: Some(RulePattern::new("(")),
+            ..rule("x")
+        };
+        assert!(!bad.matches(&features(json!({
+            "messages": [{"role": "user", "content": "("}]
+        }))));
+        assert_eq!(bad.invalid_patterns().len(), 1);
+        assert_eq!(bad.invalid_patterns()[0].0, "message");
+        assert!(cwd.invalid_patterns().is_empty());
+    }
+
+    #[test]
+    fn test_empty_rule_matches_everything() {
+        let rules = vec![rule("catch-all")];
+        assert!(first_match(&rules, &RequestFeatures::default()).is_some());
+        assert!(first_match(&[], &RequestFeatures::default()).is_none());
+    }
+
+    #[test]
+    fn test_deserialize_rule() {
+        let r: RouteRule = toml::from_str(
+            r#"
+            name = "vision"
+            profile = "gemini"
+            has_images = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(r.label(), "vision");
+        assert_eq!(r.has_images, Some(true));
+        assert!(r.message.is_none());
+    }
+
+    #[test]
+    fn test_deserialize_compiles_patterns() {
+        let r: RouteRule = toml::from_str(
+            r#"
+            profile = "local"
+            message = "(?i)^fix"
+            cwd = "["
+            "#,
+        )
+        .unwrap();
+        assert_eq!(r.message.as_ref().unwrap().as_str(), "(?i)^fix");
+        assert!(r.message.as_ref().unwrap().error().is_none());
+        assert!(r.cwd.as_ref().unwrap().error().is_some());
+        // 序列化保留原文
+        let round_trip: RouteRule = toml::from_str(&toml::to_string(&r).unwrap()).unwrap();
+        assert_eq!(round_trip, r);
+    }
+}
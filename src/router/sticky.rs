@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+/// 会话粘性路由：同一会话后续轮次沿用首次分类的 profile
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StickyConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// 会话空闲超过该时长（秒）后重新分类
+    #[serde(default = "default_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_ttl_secs() -> u64 {
+    3600
+}
+
+impl Default for StickyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            ttl_secs: default_ttl_secs(),
+        }
+    }
+}
+
+/// 会话指纹：请求的模型 slot + system prompt + 首条用户消息的哈希；有 `metadata.user_id`
+/// （Claude Code 在其中携带 session id）时只作为命名空间。同一 session 里的 haiku 后台调用
+/// （标题、话题）与子 agent 对话有各自的模型与首条消息，不会与主对话共用同一条路由
+pub fn fingerprint(body: &Value) -> Option<String> {
+    let first_user = body
+        .get("messages")?
+        .as_array()?
+        .iter()
+        .find(|m| m.get("role").and_then(|r| r.as_str()) == Some("user"))?;
+    let message = text_of(first_user.get("content")?);
+    if message.is_empty() {
+        return None;
+    }
+    let user_id = body
+        .get("metadata")
+        .and_then(|m| m.get("user_id"))
+        .and_then(|u| u.as_str())
+        .unwrap_or_default();
+    let model = model_slot(
+        body.get("model")
+            .and_then(|m| m.as_str())
+            .unwrap_or_default(),
+    );
+    let system = body.get("system").map(text_of).unwrap_or_default();
+    let source =
+        format!("user_id:{user_id}\u{0}model:{model}\u{0}system:{system}\u{0}user:{message}");
+    let digest = Sha256::digest(source.as_bytes());
+    Some(digest.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Claude 模型归为 haiku / sonnet / opus slot（会话中切换同一 slot 的版本仍粘在原路由），
+/// 其它模型名原样使用
+fn model_slot(model: &str) -> String {
+    let lower = model.to_ascii_lowercase();
+    ["haiku", "sonnet", "opus"]
+        .into_iter()
+        .find(|slot| lower.contains(slot))
+        .map(str::to_string)
+        .unwrap_or(lower)
+}
+
+/// 字符串或内容块数组中的文本
+fn text_of(content: &Value) -> String {
+    match content {
+        Value::String(s) => s.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|b| b.get("text").and_then(|t| t.as_str()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+#[derive(Debug)]
+struct StickyEntry {
+    profile: String,
+    last_used: Instant,
+}
+
+/// 会话指纹 → profile 的路由缓存（滑动过期）
+#[derive(Debug, Default, Clone)]
+pub struct StickyRoutes {
+    inner: Arc<Mutex<HashMap<String, StickyEntry>>>,
+}
+
+impl StickyRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 查找未过期的路由并刷新使用时间
+    pub fn get(&self, fingerprint: &str, ttl: Duration) -> Option<String> {
+        self.get_at(fingerprint, ttl, Instant::now())
+    }
+
+    fn get_at(&self, fingerprint: &str, ttl: Duration, now: Instant) -> Option<String> {
+        let mut map = self.inner.lock().unwrap();
+        match map.get_mut(fingerprint) {
+            Some(entry) if now.saturating_duration_since(entry.last_used) < ttl => {
+                entry.last_used = now;
+                Some(entry.profile.clone())
+            }
+            Some(_) => {
+                map.remove(fingerprint);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, fingerprint: &str, profile: &str, ttl: Duration) {
+        self.insert_at(fingerprint, profile, ttl, Instant::now());
+    }
+
+    fn insert_at(&self, fingerprint: &str, profile: &str, ttl: Duration, now: Instant) {
+        let mut map = self.inner.lock().unwrap();
+        map.retain(|_, e| now.saturating_duration_since(e.last_used) < ttl);
+        map.insert(
+            fingerprint.to_string(),
+            StickyEntry {
+                profile: profile.to_string(),
+                last_used: now,
+            },
+        );
+    }
+
+    pub fn remove(&self, fingerprint: &str) {
+        self.inner.lock().unwrap().remove(fingerprint);
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn conversation(turns: &[(&str, &str)]) -> Value {
+        let messages: Vec<Value> = turns
+            .iter()
+            .map(|(role, text)| json!({"role": role, "content": text}))
+            .collect();
+        json!({
+            "system": [{"type": "text", "text": "You are Claude Code."}],
+            "messages": messages
+        })
+    }
+
+    #[test]
+    fn test_fingerprint_stable_across_turns() {
+        let first = conversation(&[("user", "fix the parser")]);
+        let later = conversation(&[
+            ("user", "fix the parser"),
+            ("assistant", "done"),
+            ("user", "now add tests"),
+        ]);
+        assert_eq!(fingerprint(&first), fingerprint(&later));
+
+        let other = conversation(&[("user", "write docs")]);
+        assert_ne!(fingerprint(&first), fingerprint(&other));
+    }
+
+    #[test]
+    fn test_fingerprint_includes_system_prompt() {
+        let a = conversation(&[("user", "hi")]);
+        let mut b = a.clone();
+        b["system"] = json!("different system prompt");
+        assert_ne!(fingerprint(&a), fingerprint(&b));
+    }
+
+    #[test]
+    fn test_fingerprint_namespaces_by_user_id() {
+        let mut a = conversation(&[("user", "first")]);
+        let mut b = a.clone();
+        a["metadata"] = json!({"user_id": "user_abc_session_123"});
+        b["metadata"] = json!({"user_id": "user_abc_session_456"});
+        assert_ne!(fingerprint(&a), fingerprint(&b));
+    }
+
+    #[test]
+    fn test_fingerprint_separates_background_calls_in_session() {
+        let session = json!({"user_id": "user_abc_session_123"});
+        let mut main = conversation(&[("user", "refactor the router")]);
+        main["model"] = json!("claude-sonnet-4-20250514");
+        main["metadata"] = session.clone();
+        // 同一 session 的 haiku 标题请求与子 agent 对话
+        let mut title = conversation(&[("user", "Summarize this conversation in a title")]);
+        title["model"] = json!("claude-3-5-haiku-20241022");
+        title["metadata"] = session.clone();
+        let mut agent = conversation(&[("user", "Search the repo for route rules")]);
+        agent["model"] = json!("claude-sonnet-4-20250514");
+        agent["metadata"] = session.clone();
+        assert_ne!(fingerprint(&main), fingerprint(&title));
+        assert_ne!(fingerprint(&main), fingerprint(&agent));
+
+        // 相同首条消息但不同 slot 也分开；同一 slot 切换版本仍沿用
+        let mut haiku_main = main.clone();
+        haiku_main["model"] = json!("claude-3-5-haiku-20241022");
+        assert_ne!(fingerprint(&main), fingerprint(&haiku_main));
+        let mut upgraded = main.clone();
+        upgraded["model"] = json!("claude-sonnet-4-5");
+        assert_eq!(fingerprint(&main), fingerprint(&upgraded));
+    }
+
+    #[test]
+    fn test_fingerprint_requires_user_message() {
+        assert!(fingerprint(&json!({"messages": []})).is_none());
+        assert!(fingerprint(&json!({})).is_none());
+    }
+
+    #[test]
+    fn test_sticky_ttl_slides_and_expires() {
+        let routes = StickyRoutes::new();
+        let ttl = Duration::from_secs(60);
+        let start = Instant::now();
+        routes.insert_at("fp", "deepseek", ttl, start);
+        let t1 = start + Duration::from_secs(50);
+        assert_eq!(routes.get_at("fp", ttl, t1).as_deref(), Some("deepseek"));
+        // 使用后重新计时
+        let t2 = t1 + Duration::from_secs(50);
+        assert_eq!(routes.get_at("fp", ttl, t2).as_deref(), Some("deepseek"));
+        assert!(routes.get_at("fp", ttl, t2 + ttl).is_none());
+        assert!(routes.is_empty());
+    }
+
+    #[test]
+    fn test_insert_purges_expired() {
+        let routes = StickyRoutes::new();
+        let ttl = Duration::from_secs(10);
+        let start = Instant::now();
+        routes.insert_at("old", "a", ttl, start);
+        routes.insert_at("new", "b", ttl, start + Duration::from_secs(20));
+        assert_eq!(routes.len(), 1);
+    }
+}
@@ -0,0 +1,457 @@
+//! 本地 token 计数：内置 tiktoken 的 cl100k / o200k rank 表，对文本做真实的 BPE 合并，
+//! 再按模型家族乘校正系数（如 Claude 的词表比 cl100k 切得更碎）。
+//! 图片按尺寸公式、PDF 按页数计，不按 base64 长度估算
+
+use base64::Engine;
+use serde_json::Value;
+use tiktoken_rs::CoreBPE;
+
+/// BPE 编码（rank 表随 `tiktoken-rs` 内置，首次使用时加载）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// GPT-4 / GPT-3.5
+    Cl100k,
+    /// GPT-4o 之后，对 CJK 与长词合并更充分
+    O200k,
+}
+
+impl Encoding {
+    fn bpe(self) -> &'static CoreBPE {
+        match self {
+            Encoding::Cl100k => tiktoken_rs::cl100k_base_singleton(),
+            Encoding::O200k => tiktoken_rs::o200k_base_singleton(),
+        }
+    }
+
+    /// 文本的 BPE token 数（特殊 token 按普通文本处理）
+    fn count(self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        self.bpe().encode_ordinary(text).len() as u64
+    }
+}
+
+/// 模型家族对应的编码与校正系数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tokenizer {
+    pub encoding: Encoding,
+    /// 乘在 BPE 结果上的系数（非 OpenAI 模型的词表与 cl100k / o200k 不完全一致）
+    pub factor: f64,
+}
+
+/// 每条消息的固定开销（角色标记与分隔符）
+const MESSAGE_OVERHEAD: u64 = 4;
+/// 每个请求的固定开销
+const REQUEST_OVERHEAD: u64 = 3;
+/// 携带 tools 时上游注入的工具使用系统提示
+const TOOLS_OVERHEAD: u64 = 346;
+/// 图片长边上限（超出按比例缩放）
+const IMAGE_MAX_EDGE: f64 = 1568.0;
+/// 图片像素上限（约 1.15 MP）
+const IMAGE_MAX_PIXELS: f64 = 1_150_000.0;
+/// 每个图片 token 覆盖的像素数
+const PIXELS_PER_TOKEN: f64 = 750.0;
+/// 无法解析尺寸（URL 图片、未知格式）时按上限计
+pub const IMAGE_FALLBACK_TOKENS: u64 = 1600;
+/// PDF 每页的估算 token 数
+const PDF_PAGE_TOKENS: u64 = 1500;
+
+impl Tokenizer {
+    /// 按模型名选择编码与校正系数（支持 `provider/model` 形式）
+    pub fn for_model(model: &str) -> Self {
+        let lower = model.to_ascii_lowercase();
+        let bare = lower.rsplit('/').next().unwrap_or(&lower);
+        let (encoding, factor) = if bare.starts_with("claude") {
+            (Encoding::Cl100k, 1.15)
+        } else if [
+            "gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4", "chatgpt", "codex",
+        ]
+        .iter()
+        .any(|p| bare.starts_with(p))
+        {
+            (Encoding::O200k, 1.0)
+        } else if bare.starts_with("gpt-") {
+            (Encoding::Cl100k, 1.0)
+        } else if ["deepseek", "qwen", "glm", "kimi", "moonshot", "minimax"]
+            .iter()
+            .any(|p| bare.starts_with(p))
+        {
+            (Encoding::O200k, 0.95)
+        } else if bare.starts_with("gemini") || bare.starts_with("gemma") {
+            (Encoding::O200k, 1.05)
+        } else if bare.starts_with("grok") {
+            (Encoding::O200k, 1.0)
+        } else if bare.starts_with("llama") || bare.starts_with("mistral") {
+            (Encoding::Cl100k, 1.05)
+        } else {
+            (Encoding::Cl100k, 1.0)
+        };
+        Self { encoding, factor }
+    }
+
+    /// 一段文本的 token 数
+    pub fn count_text(&self, text: &str) -> u64 {
+        if text.is_empty() {
+            return 0;
+        }
+        self.scale(self.encoding.count(text))
+    }
+
+    fn scale(&self, tokens: u64) -> u64 {
+        (tokens as f64 * self.factor).ceil() as u64
+    }
+
+    /// 一个 Anthropic Messages 请求体的输入 token 数（system、messages、tools）
+    pub fn count_request(&self, body: &Value) -> u64 {
+        let mut text = REQUEST_OVERHEAD;
+        let mut fixed = 0u64;
+
+        match body.get("system") {
+            Some(Value::String(s)) => text += self.encoding.count(s),
+            Some(Value::Array(blocks)) => {
+                for block in blocks {
+                    let (t, images) = self.block_tokens(block);
+                    text += t;
+                    fixed += images;
+                }
+            }
+            _ => {}
+        }
+
+        if let Some(messages) = body.get("messages").and_then(|m| m.as_array()) {
+            for message in messages {
+                text += MESSAGE_OVERHEAD;
+                match message.get("content") {
+                    Some(Value::String(s)) => text += self.encoding.count(s),
+                    Some(Value::Array(blocks)) => {
+                        for block in blocks {
+                            let (t, images) = self.block_tokens(block);
+                            text += t;
+                            fixed += images;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(tools) = body.get("tools").and_then(|t| t.as_array()) {
+            if !tools.is_empty() {
+                fixed += TOOLS_OVERHEAD;
+            }
+            for tool in tools {
+                text += self.encoding.count(&tool.to_string());
+            }
+        }
+
+        self.scale(text) + fixed
+    }
+
+    /// 返回 (需要乘校正系数的文本 token, 图片 / 文档等固定 token)
+    fn block_tokens(&self, block: &Value) -> (u64, u64) {
+        let str_field = |key: &str| block.get(key).and_then(|v| v.as_str()).unwrap_or("");
+        match str_field("type") {
+            "text" => (self.encoding.count(str_field("text")), 0),
+            "thinking" => (self.encoding.count(str_field("thinking")), 0),
+            "redacted_thinking" => (0, 0),
+            "image" => (0, image_tokens(block.get("source"))),
+            "document" => self.document_tokens(block.get("source")),
+            "tool_use" => {
+                let input = block
+                    .get("input")
+                    .map(|v| v.to_string())
+                    .unwrap_or_default();
+                let text = self.encoding.count(str_field("name")) + self.encoding.count(&input);
+                (text, 0)
+            }
+            "tool_result" => match block.get("content") {
+                Some(Value::String(s)) => (self.encoding.count(s), 0),
+                Some(Value::Array(blocks)) => blocks.iter().fold((0, 0), |acc, b| {
+                    let (t, f) = self.block_tokens(b);
+                    (acc.0 + t, acc.1 + f)
+                }),
+                _ => (0, 0),
+            },
+            _ => (self.encoding.count(&block.to_string()), 0),
+        }
+    }
+
+    fn document_tokens(&self, source: Option<&Value>) -> (u64, u64) {
+        let Some(source) = source else {
+            return (0, 0);
+        };
+        match source.get("type").and_then(|t| t.as_str()) {
+            Some("text") => {
+                let data = source.get("data").and_then(|d| d.as_str()).unwrap_or("");
+                (self.encoding.count(data), 0)
+            }
+            Some("base64") => {
+                let pages = source
+                    .get("data")
+                    .and_then(|d| d.as_str())
+                    .and_then(|d| base64::engine::general_purpose::STANDARD.decode(d).ok())
+                    .map(|pdf| pdf_page_count(&pdf))
+                    .unwrap_or(1);
+                (0, pages * PDF_PAGE_TOKENS)
+            }
+            _ => (0, PDF_PAGE_TOKENS),
+        }
+    }
+}
+
+/// 按模型计算请求体的输入 token 数
+pub fn count_request_tokens(model: &str, body: &Value) -> u64 {
+    Tokenizer::for_model(model).count_request(body)
+}
+
+/// 图片 token：解析 base64 头部得到尺寸，缩放到长边 1568、约 1.15MP 以内后按
+/// `宽 × 高 / 750` 计算；URL 或未知格式按上限计
+pub fn image_tokens(source: Option<&Value>) -> u64 {
+    let dimensions = source
+        .filter(|s| s.get("type").and_then(|t| t.as_str()) == Some("base64"))
+        .and_then(|s| s.get("data").and_then(|d| d.as_str()))
+        .and_then(image_dimensions_base64);
+    match dimensions {
+        Some((w, h)) => tokens_for_dimensions(w, h),
+        None => IMAGE_FALLBACK_TOKENS,
+    }
+}
+
+pub fn tokens_for_dimensions(width: u32, height: u32) -> u64 {
+    if width == 0 || height == 0 {
+        return 0;
+    }
+    let (mut w, mut h) = (width as f64, height as f64);
+    let edge_scale = IMAGE_MAX_EDGE / w.max(h);
+    if edge_scale < 1.0 {
+        w *= edge_scale;
+        h *= edge_scale;
+    }
+    let pixel_scale = (IMAGE_MAX_PIXELS / (w * h)).sqrt();
+    if pixel_scale < 1.0 {
+        w *= pixel_scale;
+        h *= pixel_scale;
+    }
+    ((w * h) / PIXELS_PER_TOKEN).ceil() as u64
+}
+
+/// 只解码 base64 的前若干字节以读取图片头
+fn image_dimensions_base64(data: &str) -> Option<(u32, u32)> {
+    // JPEG 的 SOF 段可能在 EXIF 之后，多取一些
+    let prefix_len = data.len().min(64 * 1024) / 4 * 4;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data.get(..prefix_len)?)
+        .ok()?;
+    image_dimensions(&bytes)
+}
+
+/// 从 PNG / GIF / JPEG / WebP 文件头读取宽高
+pub fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be32 = |b: &[u8]| u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+    let le16 = |b: &[u8]| u16::from_le_bytes([b[0], b[1]]) as u32;
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") && bytes.len() >= 24 {
+        return Some((be32(&bytes[16..20]), be32(&bytes[20..24])));
+    }
+    if bytes.starts_with(b"GIF8") && bytes.len() >= 10 {
+        return Some((le16(&bytes[6..8]), le16(&bytes[8..10])));
+    }
+    if bytes.starts_with(b"RIFF") && bytes.len() >= 30 && &bytes[8..12] == b"WEBP" {
+        return match &bytes[12..16] {
+            b"VP8X" => {
+                let w = u32::from_le_bytes([bytes[24], bytes[25], bytes[26], 0]) + 1;
+                let h = u32::from_le_bytes([bytes[27], bytes[28], bytes[29], 0]) + 1;
+                Some((w, h))
+            }
+            b"VP8 " => Some((le16(&bytes[26..28]) & 0x3fff, le16(&bytes[28..30]) & 0x3fff)),
+            b"VP8L" if bytes.len() >= 25 => {
+                let bits = u32::from_le_bytes([bytes[21], bytes[22], bytes[23], bytes[24]]);
+                Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+            }
+            _ => None,
+        };
+    }
+    if bytes.starts_with(&[0xFF, 0xD8]) {
+        return jpeg_dimensions(bytes);
+    }
+    None
+}
+
+/// 扫描 JPEG 段直到 SOF（0xC0..=0xCF，排除 DHT/JPG/DAC）
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let mut i = 2;
+    while i + 9 < bytes.len() {
+        if bytes[i] != 0xFF {
+            return None;
+        }
+        let marker = bytes[i + 1];
+        if marker == 0xFF {
+            i += 1;
+            continue;
+        }
+        let len = u16::from_be_bytes([bytes[i + 2], bytes[i + 3]]) as usize;
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let h = u16::from_be_bytes([bytes[i + 5], bytes[i + 6]]) as u32;
+            let w = u16::from_be_bytes([bytes[i + 7], bytes[i + 8]]) as u32;
+            return Some((w, h));
+        }
+        i += 2 + len;
+    }
+    None
+}
+
+/// 统计 PDF 中的 `/Type /Page` 对象（排除 `/Pages`），至少 1 页
+fn pdf_page_count(pdf: &[u8]) -> u64 {
+    let mut count = 0;
+    for pattern in [&b"/Type /Page"[..], &b"/Type/Page"[..]] {
+        let mut i = 0;
+        while i + pattern.len() <= pdf.len() {
+            if &pdf[i..i + pattern.len()] == pattern {
+                if pdf.get(i + pattern.len()) != Some(&b's') {
+                    count += 1;
+                }
+                i += pattern.len();
+            } else {
+                i += 1;
+            }
+        }
+    }
+    count.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_bpe_counts_match_tiktoken() {
+        let cl100k = Tokenizer::for_model("gpt-4");
+        assert_eq!(cl100k.count_text("hello world"), 2);
+        assert_eq!(cl100k.count_text("tiktoken is great!"), 6);
+        assert_eq!(cl100k.count_text(""), 0);
+        // 特殊 token 的字面量按普通文本切分
+        assert!(cl100k.count_text("<|endoftext|>") > 1);
+    }
+
+    #[test]
+    fn test_cjk_counts_per_encoding() {
+        let text = "请帮我重构这个函数并补充单元测试";
+        let cl100k = Tokenizer::for_model("gpt-4").count_text(text);
+        let o200k = Tokenizer::for_model("gpt-4o").count_text(text);
+        // 16 个汉字：cl100k 17 个 token，o200k 13 个
+        assert_eq!((cl100k, o200k), (17, 13));
+        assert!(o200k < cl100k);
+        assert!(cl100k > text.len() as u64 / 4);
+    }
+
+    #[test]
+    fn test_claude_factor_scales_bpe_count() {
+        let text = "fn main() { println!(\"hello\"); }";
+        let base = Tokenizer::for_model("gpt-4").count_text(text);
+        let claude = Tokenizer::for_model("claude-sonnet-4").count_text(text);
+        assert_eq!(claude, (base as f64 * 1.15).ceil() as u64);
+    }
+
+    #[test]
+    fn test_model_families() {
+        assert_eq!(Tokenizer::for_model("claude-sonnet-4").factor, 1.15);
+        assert_eq!(
+            Tokenizer::for_model("openai/gpt-4o-mini").encoding,
+            Encoding::O200k
+        );
+        assert_eq!(
+            Tokenizer::for_model("qwen3-coder").encoding,
+            Encoding::O200k
+        );
+        assert_eq!(Tokenizer::for_model("unknown").encoding, Encoding::Cl100k);
+    }
+
+    #[test]
+    fn test_image_dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&800u32.to_be_bytes());
+        png.extend_from_slice(&600u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), Some((800, 600)));
+
+        let gif = b"GIF89a\x40\x01\xf0\x00";
+        assert_eq!(image_dimensions(gif), Some((320, 240)));
+
+        let jpeg = [
+            0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xC0, 0x00, 0x11, 0x08, 0x01,
+            0xE0, 0x02, 0x80, 0x03,
+        ];
+        assert_eq!(image_dimensions(&jpeg), Some((640, 480)));
+    }
+
+    #[test]
+    fn test_image_tokens_formula() {
+        assert_eq!(tokens_for_dimensions(200, 200), 54);
+        // 超大图片被缩放到约 1.15MP
+        let large = tokens_for_dimensions(4000, 3000);
+        assert!((1500..=1550).contains(&large), "{large}");
+
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&200u32.to_be_bytes());
+        png.extend_from_slice(&200u32.to_be_bytes());
+        png.extend_from_slice(&[0u8; 4096]);
+        let data = base64::engine::general_purpose::STANDARD.encode(&png);
+        let source = json!({"type": "base64", "media_type": "image/png", "data": data});
+        assert_eq!(image_tokens(Some(&source)), 54);
+        let url = json!({"type": "url", "url": "https://example.com/a.png"});
+        assert_eq!(image_tokens(Some(&url)), IMAGE_FALLBACK_TOKENS);
+    }
+
+    #[test]
+    fn test_request_ignores_base64_length() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        png.extend_from_slice(&100u32.to_be_bytes());
+        png.extend_from_slice(&100u32.to_be_bytes());
+        png.extend_from_slice(&vec![7u8; 300_000]);
+        let data = base64::engine::general_purpose::STANDARD.encode(&png);
+        let body = json!({
+            "model": "claude-sonnet-4",
+            "messages": [{"role": "user", "content": [
+                {"type": "image", "source": {"type": "base64", "media_type": "image/png", "data": data}},
+                {"type": "text", "text": "describe this"}
+            ]}]
+        });
+        let tokens = count_request_tokens("claude-sonnet-4", &body);
+        // 14 个图片 token + 文本与开销，而不是 base64 长度 / 4（约 10 万）
+        assert!(tokens < 50, "{tokens}");
+    }
+
+    #[test]
+    fn test_request_counts_tools_and_tool_results() {
+        let body = json!({
+            "system": "You are helpful",
+            "messages": [
+                {"role": "user", "content": "read main"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "t1", "name": "read_file", "input": {"path": "src/main.rs"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": "fn main() {}"}
+                ]}
+            ],
+            "tools": [{"name": "read_file", "description": "Read a file",
+                "input_schema": {"type": "object", "properties": {"path": {"type": "string"}}}}]
+        });
+        let tok = Tokenizer::for_model("gpt-4");
+        let without_tools = {
+            let mut b = body.clone();
+            b.as_object_mut().unwrap().remove("tools");
+            tok.count_request(&b)
+        };
+        let with_tools = tok.count_request(&body);
+        assert!(with_tools > without_tools + TOOLS_OVERHEAD);
+        assert!(without_tools > 3 * MESSAGE_OVERHEAD);
+    }
+
+    #[test]
+    fn test_pdf_page_count() {
+        let pdf = b"%PDF /Type /Pages /Type /Page x /Type/Page y";
+        assert_eq!(pdf_page_count(pdf), 2);
+        assert_eq!(pdf_page_count(b"%PDF"), 1);
+    }
+}
@@ -6,6 +6,7 @@ use ratatui::Frame;
 
 use crate::config::ClaudexConfig;
 use crate::proxy::health::HealthMap;
+use crate::proxy::pricing::format_cost;
 
 use super::{App, AppMode, RightPanel};
 
@@ -192,6 +193,20 @@ fn render_metrics(f: &mut Frame, app: &App, area: Rect) {
         .map(|m| m.total_tokens.load(std::sync::atomic::Ordering::Relaxed))
         .sum();
 
+    let total_cost: f64 = snapshot.values().map(|m| m.total_cost()).sum();
+    let queued: u64 = snapshot
+        .values()
+        .map(|m| m.queue_depth.load(std::sync::atomic::Ordering::Relaxed))
+        .sum();
+    let cache_hits: u64 = snapshot
+        .values()
+        .map(|m| m.cache_hits.load(std::sync::atomic::Ordering::Relaxed))
+        .sum();
+    let summary_hits: u64 = snapshot
+        .values()
+        .map(|m| m.summary_hits.load(std::sync::atomic::Ordering::Relaxed))
+        .sum();
+
     let avg_latency = {
         let latencies: Vec<_> = snapshot.values().filter_map(|m| m.avg_latency()).collect();
         if latencies.is_empty() {
@@ -233,7 +248,8 @@ fn render_metrics(f: &mut Frame, app: &App, area: Rect) {
     let text = Line::from(vec![
         proxy_status,
         Span::raw(format!(
-            " |  Requests: {total_requests}  |  Tokens: {token_display}  |  Avg: {avg_latency}  |  Success: {success_rate}"
+            " |  Requests: {total_requests}  |  Tokens: {token_display}  |  Cost: {}  |  Avg: {avg_latency}  |  Success: {success_rate}  |  Queued: {queued}  |  Cache hits: {cache_hits}  |  Summary hits: {summary_hits}",
+            format_cost(total_cost)
         )),
     ]);
 
@@ -124,6 +124,7 @@ impl ProfileSnapshot {
                 ProviderType::DirectAnthropic => "DirectAnthropic".to_string(),
                 ProviderType::OpenAICompatible => "OpenAICompatible".to_string(),
                 ProviderType::OpenAIResponses => "OpenAIResponses".to_string(),
+                ProviderType::Mock => "Mock".to_string(),
             },
             base_url: p.base_url.clone(),
             default_model: p.default_model.clone(),
@@ -132,7 +133,11 @@ impl ProfileSnapshot {
                 AuthType::ApiKey => "ApiKey".to_string(),
                 AuthType::OAuth => "OAuth".to_string(),
             },
-            has_api_key: !p.api_key.is_empty() || p.api_key_keyring.is_some(),
+            has_api_key: !p.api_key.is_empty()
+                || p.api_key_keyring.is_some()
+                || p.key_pool
+                    .as_ref()
+                    .is_some_and(|pool| !pool.keys.is_empty()),
         }
     }
 }
@@ -256,6 +261,7 @@ impl ProfileForm {
                         "DirectAnthropic".to_string(),
                         "OpenAICompatible".to_string(),
                         "OpenAIResponses".to_string(),
+                        "Mock".to_string(),
                     ]),
                     "OpenAICompatible",
                 ),
@@ -276,6 +282,7 @@ impl ProfileForm {
             ProviderType::DirectAnthropic => "DirectAnthropic",
             ProviderType::OpenAICompatible => "OpenAICompatible",
             ProviderType::OpenAIResponses => "OpenAIResponses",
+            ProviderType::Mock => "Mock",
         };
         Self {
             fields: vec![
@@ -286,6 +293,7 @@ impl ProfileForm {
                         "DirectAnthropic".to_string(),
                         "OpenAICompatible".to_string(),
                         "OpenAIResponses".to_string(),
+                        "Mock".to_string(),
                     ]),
                     provider_type,
                 ),
@@ -309,6 +317,7 @@ impl ProfileForm {
         let provider_type = match self.fields[FIELD_PROVIDER_TYPE].value.as_str() {
             "DirectAnthropic" => ProviderType::DirectAnthropic,
             "OpenAIResponses" => ProviderType::OpenAIResponses,
+            "Mock" => ProviderType::Mock,
             _ => ProviderType::OpenAICompatible,
         };
         ProfileConfig {
@@ -492,6 +501,8 @@ pub async fn run_tui(
 
     let mut event_stream = EventStream::new();
     let mut tick = tokio::time::interval(std::time::Duration::from_millis(250));
+    // 健康状态由运行中的 proxy 通过 HTTP 提供，降低轮询频率
+    let mut health_poll = tokio::time::interval(std::time::Duration::from_secs(2));
 
     loop {
         // Handle pending async actions first
@@ -587,6 +598,13 @@ pub async fn run_tui(
                     }
                 }
             }
+            _ = health_poll.tick(), if app.proxy_running => {
+                let config = app.config.read().await.clone();
+                if let Ok(reports) = crate::proxy::health::fetch_remote(&config).await {
+                    *app.health_status.write().await =
+                        crate::proxy::health::from_reports(reports, std::time::Instant::now());
+                }
+            }
         }
     }
 
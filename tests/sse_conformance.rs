@@ -0,0 +1,360 @@
+//! Golden-file conformance suite for the SSE stream translators.
+//!
+//! Each `tests/fixtures/sse/<provider>/<case>.sse` file is a recorded upstream
+//! transcript; `<case>.expected.json` holds the Anthropic events it must
+//! translate to. Every transcript is replayed under several chunkings (whole,
+//! single bytes, odd-sized slices) so split lines and split UTF-8 are covered,
+//! and the output is checked for Anthropic event-stream legality.
+//!
+//! Run with `UPDATE_GOLDEN=1` to (re)write the expected files.
+
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use claudex::proxy::translate::chat_completions_stream::translate_sse_stream;
+use claudex::proxy::translate::responses_stream::translate_responses_stream;
+
+/// 切分方式：整体、逐字节、奇数长度分片
+const CHUNK_SIZES: &[usize] = &[usize::MAX, 1, 7, 61];
+
+#[derive(Clone, Copy)]
+enum Translator {
+    ChatCompletions,
+    Responses,
+}
+
+fn translator_for(provider: &str) -> Translator {
+    match provider {
+        "codex" => Translator::Responses,
+        _ => Translator::ChatCompletions,
+    }
+}
+
+fn fixtures_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sse")
+}
+
+fn collect_fixtures() -> Vec<(String, PathBuf)> {
+    let mut fixtures = Vec::new();
+    for provider in std::fs::read_dir(fixtures_root()).unwrap() {
+        let provider = provider.unwrap().path();
+        if !provider.is_dir() {
+            continue;
+        }
+        let provider_name = provider.file_name().unwrap().to_string_lossy().to_string();
+        for file in std::fs::read_dir(&provider).unwrap() {
+            let path = file.unwrap().path();
+            if path.extension().is_some_and(|e| e == "sse") {
+                fixtures.push((provider_name.clone(), path));
+            }
+        }
+    }
+    fixtures.sort_by(|a, b| a.1.cmp(&b.1));
+    fixtures
+}
+
+async fn translate(translator: Translator, transcript: &[u8], chunk_size: usize) -> String {
+    let chunks: Vec<Result<Bytes, reqwest::Error>> = transcript
+        .chunks(chunk_size.min(transcript.len().max(1)))
+        .map(|c| Ok(Bytes::copy_from_slice(c)))
+        .collect();
+    let input = futures::stream::iter(chunks);
+    let mut output = match translator {
+        Translator::ChatCompletions => translate_sse_stream(input, Default::default()),
+        Translator::Responses => translate_responses_stream(input, Default::default()),
+    };
+    let mut text = Vec::new();
+    while let Some(chunk) = output.next().await {
+        text.extend_from_slice(&chunk.unwrap());
+    }
+    String::from_utf8(text).expect("translator emitted invalid UTF-8")
+}
+
+/// 解析 Anthropic SSE 输出，校验 `event:` 名与 data.type 一致
+fn parse_events(output: &str) -> Result<Vec<Value>, String> {
+    let mut events = Vec::new();
+    for frame in output.split("\n\n").filter(|f| !f.is_empty()) {
+        let mut name = None;
+        let mut data = None;
+        for line in frame.lines() {
+            if let Some(v) = line.strip_prefix("event: ") {
+                name = Some(v);
+            } else if let Some(v) = line.strip_prefix("data: ") {
+                data = Some(v);
+            } else {
+                return Err(format!("unexpected SSE line: {line:?}"));
+            }
+        }
+        let name = name.ok_or_else(|| format!("frame without event name: {frame:?}"))?;
+        let data: Value = serde_json::from_str(data.ok_or("frame without data")?)
+            .map_err(|e| format!("invalid event JSON: {e}"))?;
+        if data["type"] != name {
+            return Err(format!(
+                "event name {name} does not match type {}",
+                data["type"]
+            ));
+        }
+        events.push(data);
+    }
+    Ok(events)
+}
+
+/// 校验 Anthropic 事件流合法性
+fn validate(events: &[Value]) -> Result<(), String> {
+    let types: Vec<&str> = events
+        .iter()
+        .map(|e| e["type"].as_str().unwrap_or(""))
+        .collect();
+    if types.first() != Some(&"message_start") {
+        return Err("stream must begin with message_start".into());
+    }
+    if types.iter().filter(|t| **t == "message_start").count() != 1 {
+        return Err("exactly one message_start expected".into());
+    }
+    if types.iter().filter(|t| **t == "message_stop").count() != 1 {
+        return Err("exactly one message_stop expected".into());
+    }
+    if types.last() != Some(&"message_stop") {
+        return Err("message_stop must be the final event".into());
+    }
+
+    let mut next_index = 0u64;
+    // (index, block type, accumulated partial_json)
+    let mut open: Option<(u64, String, String)> = None;
+    let mut message_delta_seen = false;
+
+    for (pos, event) in events.iter().enumerate() {
+        let at = |msg: &str| format!("event #{pos} ({}): {msg}", event["type"]);
+        match event["type"].as_str().unwrap_or("") {
+            "message_start" | "message_stop" | "ping" => {}
+            "content_block_start" => {
+                if message_delta_seen {
+                    return Err(at("block started after message_delta"));
+                }
+                if open.is_some() {
+                    return Err(at("block started while another is open"));
+                }
+                let index = event["index"].as_u64().ok_or_else(|| at("missing index"))?;
+                if index != next_index {
+                    return Err(at(&format!("expected index {next_index}, got {index}")));
+                }
+                let block_type = event["content_block"]["type"]
+                    .as_str()
+                    .ok_or_else(|| at("missing content_block.type"))?;
+                if block_type == "tool_use"
+                    && (event["content_block"]["id"]
+                        .as_str()
+                        .unwrap_or("")
+                        .is_empty()
+                        || event["content_block"]["name"]
+                            .as_str()
+                            .unwrap_or("")
+                            .is_empty())
+                {
+                    return Err(at("tool_use block needs id and name"));
+                }
+                open = Some((index, block_type.to_string(), String::new()));
+                next_index += 1;
+            }
+            "content_block_delta" => {
+                let (index, block_type, json_buf) = open
+                    .as_mut()
+                    .ok_or_else(|| at("delta without open block"))?;
+                if event["index"].as_u64() != Some(*index) {
+                    return Err(at("delta index does not match open block"));
+                }
+                let delta_type = event["delta"]["type"].as_str().unwrap_or("");
+                let allowed = match block_type.as_str() {
+                    "text" => delta_type == "text_delta",
+                    "tool_use" => delta_type == "input_json_delta",
+                    "thinking" => matches!(delta_type, "thinking_delta" | "signature_delta"),
+                    _ => false,
+                };
+                if !allowed {
+                    return Err(at(&format!(
+                        "{delta_type} not allowed in {block_type} block"
+                    )));
+                }
+                if let Some(partial) = event["delta"]["partial_json"].as_str() {
+                    json_buf.push_str(partial);
+                }
+            }
+            "content_block_stop" => {
+                let (index, block_type, json_buf) =
+                    open.take().ok_or_else(|| at("stop without open block"))?;
+                if event["index"].as_u64() != Some(index) {
+                    return Err(at("stop index does not match open block"));
+                }
+                if block_type == "tool_use" && !json_buf.is_empty() {
+                    let input: Value = serde_json::from_str(&json_buf)
+                        .map_err(|e| at(&format!("tool input is not valid JSON: {e}")))?;
+                    if !input.is_object() {
+                        return Err(at("tool input must be a JSON object"));
+                    }
+                }
+            }
+            "message_delta" => {
+                if open.is_some() {
+                    return Err(at("message_delta while a block is open"));
+                }
+                if message_delta_seen {
+                    return Err(at("duplicate message_delta"));
+                }
+                if event["delta"]["stop_reason"].as_str().is_none() {
+                    return Err(at("message_delta without stop_reason"));
+                }
+                message_delta_seen = true;
+            }
+            other => return Err(at(&format!("unknown event type {other}"))),
+        }
+    }
+    if open.is_some() {
+        return Err("stream ended with an open block".into());
+    }
+    if !message_delta_seen {
+        return Err("missing message_delta".into());
+    }
+    Ok(())
+}
+
+/// 抹去随机生成的 id，便于与 golden 文件比较
+fn normalize(mut events: Vec<Value>) -> Vec<Value> {
+    for event in &mut events {
+        if event["type"] == "message_start" {
+            event["message"]["id"] = json!("msg_<random>");
+        }
+        if let Some(id) = event["content_block"]["id"].as_str() {
+            if id.starts_with("toolu_") && id.len() == "toolu_".len() + 32 {
+                event["content_block"]["id"] = json!("toolu_<random>");
+            }
+        }
+    }
+    events
+}
+
+#[tokio::test]
+async fn test_sse_golden_transcripts() {
+    let fixtures = collect_fixtures();
+    assert!(!fixtures.is_empty(), "no SSE fixtures found");
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+
+    for (provider, path) in &fixtures {
+        let name = path
+            .strip_prefix(fixtures_root())
+            .unwrap()
+            .display()
+            .to_string();
+        let transcript = std::fs::read(path).unwrap();
+        let translator = translator_for(provider);
+
+        let mut baseline: Option<Vec<Value>> = None;
+        for &size in CHUNK_SIZES {
+            let output = translate(translator, &transcript, size).await;
+            let events = match parse_events(&output).and_then(|e| validate(&e).map(|_| e)) {
+                Ok(events) => normalize(events),
+                Err(e) => {
+                    failures.push(format!("{name} (chunk {size}): {e}"));
+                    continue;
+                }
+            };
+            match &baseline {
+                None => baseline = Some(events),
+                Some(b) if *b != events => {
+                    failures.push(format!("{name}: output differs with chunk size {size}"))
+                }
+                Some(_) => {}
+            }
+        }
+
+        let Some(events) = baseline else { continue };
+        let expected_path = path.with_extension("expected.json");
+        if update {
+            let mut body = serde_json::to_string_pretty(&events).unwrap();
+            body.push('\n');
+            std::fs::write(&expected_path, body).unwrap();
+            continue;
+        }
+        let expected: Vec<Value> = match std::fs::read_to_string(&expected_path) {
+            Ok(s) => serde_json::from_str(&s).unwrap(),
+            Err(_) => {
+                failures.push(format!("{name}: missing {}", expected_path.display()));
+                continue;
+            }
+        };
+        if expected != events {
+            failures.push(format!(
+                "{name}: output does not match golden file\n--- expected\n{}\n--- actual\n{}",
+                serde_json::to_string_pretty(&expected).unwrap(),
+                serde_json::to_string_pretty(&events).unwrap()
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[test]
+fn test_every_provider_has_fixtures() {
+    let fixtures = collect_fixtures();
+    for provider in ["openai", "deepseek", "qwen", "gemini", "copilot", "codex"] {
+        assert!(
+            fixtures.iter().any(|(p, _)| p == provider),
+            "no fixtures for {provider}"
+        );
+    }
+}
+
+#[test]
+fn test_validator_rejects_illegal_streams() {
+    let start = json!({"type": "message_start", "message": {"id": "m"}});
+    let stop = json!({"type": "message_stop"});
+    let delta = json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}});
+    let block = |i: u64| json!({"type": "content_block_start", "index": i, "content_block": {"type": "text", "text": ""}});
+    let block_stop = |i: u64| json!({"type": "content_block_stop", "index": i});
+
+    let legal = vec![
+        start.clone(),
+        block(0),
+        block_stop(0),
+        delta.clone(),
+        stop.clone(),
+    ];
+    assert!(validate(&legal).is_ok());
+
+    let cases = [
+        // 索引跳跃
+        vec![
+            start.clone(),
+            block(1),
+            block_stop(1),
+            delta.clone(),
+            stop.clone(),
+        ],
+        // 块未闭合
+        vec![start.clone(), block(0), delta.clone(), stop.clone()],
+        // 嵌套打开
+        vec![
+            start.clone(),
+            block(0),
+            block(1),
+            block_stop(1),
+            block_stop(0),
+            delta.clone(),
+            stop.clone(),
+        ],
+        // 重复 message_stop
+        vec![start.clone(), delta.clone(), stop.clone(), stop.clone()],
+        // 缺少 message_start
+        vec![delta.clone(), stop.clone()],
+    ];
+    for case in cases {
+        assert!(
+            validate(&case).is_err(),
+            "accepted illegal stream: {case:?}"
+        );
+    }
+}